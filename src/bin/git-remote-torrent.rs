@@ -0,0 +1,99 @@
+use std::env;
+use std::path::{Path, PathBuf};
+use std::process::exit;
+use tokio::io::{stdin, stdout, BufReader};
+
+use gtr::config::config_file::{self, Config, Torrent};
+use gtr::config::contacts;
+use gtr::daemon::transport_config;
+use gtr::transports::registry;
+use gtr::transports::remote_helper::{work_tree, RemoteHelper};
+use gtr::transports::url::RepoUrl;
+use gtr::utils::error::GtrResult;
+
+// git runs this helper for `torrent://` remotes, e.g. `git clone torrent://<hex sha1>/reponame`:
+// `git-remote-torrent <remote> [<url>]` with GIT_DIR pointing to the repository being fetched into.
+// The user may be given by petname from `gtr contacts`, `torrent://alice/reponame`, and the only
+// branch fetched by fragment, `torrent://alice/reponame#dev`.
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    let url = match (args.get(1), args.get(2)) {
+        (_, Some(url)) => url,
+        // remote given as address, e.g. `git fetch torrent://...`
+        (Some(remote), None) => remote,
+        _ => {
+            eprintln!("usage: git-remote-torrent <remote> [<url>]");
+            exit(1)
+        }
+    };
+    // GIT_DIR may be relative, git commands run by gtr find the repository from the given path
+    let git_dir = PathBuf::from(env::var("GIT_DIR").unwrap_or(String::from(".git")));
+    let git_dir = std::fs::canonicalize(&git_dir).unwrap_or(git_dir);
+
+    let runtime = match tokio::runtime::Runtime::new() {
+        Ok(runtime) => runtime,
+        Err(e) => {
+            eprintln!("fatal: {e}");
+            exit(128)
+        }
+    };
+    if let Err(e) = runtime.block_on(run(url, &git_dir)) {
+        eprintln!("fatal: {e}");
+        exit(128)
+    }
+}
+
+async fn run(url: &str, git_dir: &Path) -> GtrResult<()> {
+    let url = url.parse::<RepoUrl>()?.resolve(&contacts::list().await?)?;
+    let address = url.without_reference().to_string();
+    let conf = read_config(git_dir).await?;
+    let registry = registry(&conf).await?;
+    let transport = registry.for_url(&address)?;
+
+    let result = RemoteHelper::new(transport, &address, git_dir)
+        .with_reference(url.reference_name())
+        .run(BufReader::new(stdin()), stdout())
+        .await;
+    registry.shutdown().await?;
+
+    result
+}
+
+/// Transport settings of repository if it is shared with gtr, the ones of `gtd` otherwise (e.g.
+/// clone), mainline DHT if neither configures the torrent transport
+///
+/// The helper binds ephemeral ports, the configured ones belong to `gtd` which may be running.
+async fn read_config(git_dir: &Path) -> GtrResult<Config> {
+    let work_tree = work_tree(git_dir);
+    let mut conf = match work_tree.join(".gtr").exists() {
+        true => config_file::read_or_create(&work_tree).await?,
+        false => Config::default(),
+    };
+    if conf.transport.torrent.is_none() {
+        conf.transport = transport_config(&[]).await?;
+    }
+    let torrent = conf.transport.torrent.get_or_insert_with(Torrent::default);
+    torrent.bind.port = 0;
+    Ok(conf)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn reads_torrent_config_of_repository_without_gtr() {
+        let dir = env::temp_dir().join(format!("gtr-remote-torrent-{}", std::process::id()));
+        let git_dir = dir.join(".git");
+        std::fs::create_dir_all(&git_dir).unwrap();
+        // `daemon.toml` of the developer is not read
+        env::set_var("XDG_CONFIG_HOME", dir.join("config"));
+
+        let conf = read_config(&git_dir).await.unwrap();
+        assert_eq!(conf.transport.torrent.unwrap().bind.port, 0);
+        assert!(!dir.join(".gtr").exists());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
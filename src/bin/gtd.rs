@@ -0,0 +1,43 @@
+use std::path::PathBuf;
+use std::process::exit;
+use clap::{Arg, ArgAction, Command, value_parser};
+use env_logger::Env;
+
+use gtr::daemon::Daemon;
+use gtr::identity::keystore;
+use gtr::utils::error::GtrResult;
+
+// Daemon serving shared branches of given repositories to peers: `gtd [PATH]...`, without paths
+// it serves repositories registered by `gtr init`, `gtr share` and `git push`
+
+fn cli() -> Command {
+    Command::new("gtd")
+        .about("serve shared branches of repositories to peers")
+        .version(clap::crate_version!())
+        .arg(Arg::new("repositories")
+            .help("paths to repositories to serve, registered ones if none is given")
+            .action(ArgAction::Append)
+            .value_parser(value_parser!(PathBuf)))
+}
+
+#[tokio::main]
+async fn main() {
+    env_logger::Builder::from_env(Env::default().default_filter_or("info")).init();
+    let repositories: Vec<PathBuf> = cli()
+        .get_matches()
+        .get_many::<PathBuf>("repositories")
+        .unwrap_or_default()
+        .cloned()
+        .collect();
+
+    if let Err(e) = run(&repositories).await {
+        eprintln!("fatal: {e}");
+        exit(1)
+    }
+}
+
+async fn run(repositories: &[PathBuf]) -> GtrResult<()> {
+    keystore::check_passphrase().await?;
+    let daemon = Daemon::start(repositories).await?;
+    daemon.run().await
+}
@@ -1,4 +1,4 @@
-use std::path::PathBuf;
+use std::path::Path;
 use std::collections::HashSet;
 use crate::config::config_file;
 use crate::utils::error::GtrResult;
@@ -7,57 +7,50 @@ use crate::utils::error::GtrResult;
 ///
 /// The first parameter is the git repo directory. The second parameter is the list of branches to be added.
 /// It adds branches resolving duplication, stores them .gtr/gtrd-export.
-pub async fn include(dir: &PathBuf, new_branches: &Vec<&String>) -> GtrResult<()> {
+pub async fn include(dir: &Path, new_branches: &Vec<&String>) -> GtrResult<()> {
     let old_branches = read_branches(dir).await?;
     let old_branches: HashSet<&String> = old_branches.iter().collect();
-    let new_branches: HashSet<&String> = new_branches.iter().map(|s| *s).collect();
+    let new_branches: HashSet<&String> = new_branches.iter().copied().collect();
     let final_branches: Vec<&String> = old_branches
         .union(&new_branches)
-        .into_iter()
-        .map(|b| *b)
+        .copied()
         .collect();
     write_new_branches(dir, &final_branches).await?;
 
-    return Ok(())
+    Ok(())
 }
 
 /// Removes branches to be shared via gtrd
 ///
 /// The first parameter is the git repo directory. The second parameter is the list of branches not to be shared.
 /// It removes branches resolving duplication, stores new settings in .gtr/gtrd-export.
-pub async fn remove(dir: &PathBuf, del_branches: &Vec<&String>) -> GtrResult<()> {
+pub async fn remove(dir: &Path, del_branches: &Vec<&String>) -> GtrResult<()> {
     let old_branches = read_branches(dir).await?;
     let old_branches: HashSet<&String> = old_branches.iter().collect();
-    let del_branches: HashSet<&String> = del_branches.iter().map(|s| *s).collect();
+    let del_branches: HashSet<&String> = del_branches.iter().copied().collect();
     let final_branches: Vec<&String> = old_branches
         .difference(&del_branches)
-        .into_iter()
-        .map(|b| *b)
+        .copied()
         .collect();
     write_new_branches(dir, &final_branches).await?;
 
-    return Ok(())
+    Ok(())
 }
 
 /// Lists branches currently shared via gtrd
 ///
 /// The parameter is the git repo directory. It reads branches stored in .gtr/gtrd-export
-pub async fn list(dir: &PathBuf) -> GtrResult<Vec<String>>{
+pub async fn list(dir: &Path) -> GtrResult<Vec<String>>{
     let res = read_branches(dir).await?;
     Ok(res)
 }
 
-// TODO: implement method which will guarantee that `gtd` is running on startup
-// LINUX: systemd
-// MACOS: launchd
-// WINDOWS: task scheduler
-
-async fn read_branches(dir: &PathBuf) -> GtrResult<Vec<String>> {
+async fn read_branches(dir: &Path) -> GtrResult<Vec<String>> {
     let conf = config_file::read_or_create(dir).await?;
-    return Ok(conf.branches);
+    Ok(conf.branches)
 }
 
-async fn write_new_branches(dir: &PathBuf, branches: &Vec<&String>) -> GtrResult<()>{
+async fn write_new_branches(dir: &Path, branches: &Vec<&String>) -> GtrResult<()>{
     let mut sorted = branches.to_vec();
     sorted.sort();
 
@@ -66,31 +59,32 @@ async fn write_new_branches(dir: &PathBuf, branches: &Vec<&String>) -> GtrResult
 
     conf.save(dir).await?;
 
-    return Ok(())
+    Ok(())
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::path::PathBuf;
 
     #[tokio::test]
     async fn adds_and_removes_branches_in_settings() {
         let mut dir = PathBuf::new();
         dir.push("./.test");
 
-        let mut branches: Vec<String> = vec!["testA", "testB"]
+        let mut branches: Vec<String> = ["testA", "testB"]
             .iter()
             .map(|s| String::from(*s))
             .collect();
         branches.sort();
 
-        let mut more_branches: Vec<String> = vec!["testC", "testB", "testD"]
+        let mut more_branches: Vec<String> = ["testC", "testB", "testD"]
             .iter()
             .map(|s| String::from(*s))
             .collect();
         more_branches.sort();
 
-        let mut res_branches: Vec<String> = vec!["testA", "testB", "testC", "testD"]
+        let mut res_branches: Vec<String> = ["testA", "testB", "testC", "testD"]
             .iter()
             .map(|s| String::from(*s))
             .collect();
@@ -109,6 +103,6 @@ mod tests {
 
         let input_branches: Vec<&String> = res_branches.iter().collect();
         remove(&dir, &input_branches).await.unwrap();
-        assert!(read_branches(&dir).await.unwrap().join("").eq(""));
+        assert!(read_branches(&dir).await.unwrap().join("").is_empty());
     }
 }
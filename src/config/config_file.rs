@@ -1,4 +1,4 @@
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use tokio::fs::{File, create_dir_all};
 use tokio::io::{AsyncReadExt, AsyncWriteExt, ErrorKind};
 use toml;
@@ -14,72 +14,117 @@ use serde::{Serialize, Deserialize};
 pub struct Config {
     pub branches: Vec<String>,
     pub transport: Transport,
+    #[serde(default)]
+    pub packs: Packs,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
 pub struct Transport {
     pub torrent: Option<Torrent>
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Torrent {
     pub router: AddressPort,
     pub bind: AddressPort,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+/// Mainline DHT, used when neither the repository nor the daemon configure the transport
+impl Default for Torrent {
+    fn default() -> Self {
+        Torrent {
+            router: AddressPort { addr: String::from("router.bittorrent.com"), port: 6881 },
+            bind: AddressPort { addr: String::from("0.0.0.0"), port: 6881 },
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct AddressPort {
     pub addr: String,
     pub port: u16,
 }
 
+/// Limits of pack files cache in `.gtr/packs`
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Packs {
+    /// Total size of cached pack and index files in bytes
+    pub max_size: u64,
+    /// Seconds since last use after which pack is evicted
+    pub max_age: u64,
+}
+
+const DEFAULT_PACKS: Packs = Packs {
+    max_size: 1024 * 1024 * 1024,
+    max_age: 30 * 24 * 60 * 60,
+};
+
+impl Default for Packs {
+    fn default() -> Self {
+        DEFAULT_PACKS
+    }
+}
+
 const DEFAULT_CONFIG: Config = Config {
     branches: vec![],
-    transport: Transport { torrent: None }
+    transport: Transport { torrent: None },
+    packs: DEFAULT_PACKS,
 };
 
+impl Default for Config {
+    fn default() -> Self {
+        DEFAULT_CONFIG
+    }
+}
+
 impl Config {
-    pub async fn save(&self, dir: &PathBuf) -> GtrResult<()> {
+    pub async fn save(&self, dir: &Path) -> GtrResult<()> {
         let (_, settings_path) = get_config_path_dir_and_file(dir);
 
         match File::create(&settings_path).await {
-            Err(e) => return Err(ConfigError::save_failed(Box::new(e))),
+            Err(e) => Err(ConfigError::save_failed(Box::new(e))),
             Ok(mut file) => {
                 let content = toml::to_string(&self).unwrap();
                 file.write_all(content.as_bytes()).await.unwrap();
 
-                return Ok(())
+                Ok(())
             }
         }
     }
 }
 
-pub async fn read_or_create(dir: &PathBuf) -> GtrResult<Config> {
+pub async fn read_or_create(dir: &Path) -> GtrResult<Config> {
     let (config_dir, settings_path) = get_config_path_dir_and_file(dir);
     match tokio::fs::File::open(&settings_path).await {
         Ok(mut file) => {
             let mut data = String::new();
             match file.read_to_string(&mut data).await {
-                Ok(_) => return Ok(toml::from_str(&data).unwrap_or(DEFAULT_CONFIG)),
-                Err(e) => return Err(ConfigError::read_failed(Box::new(e)))
+                Ok(_) => Ok(toml::from_str(&data).unwrap_or(DEFAULT_CONFIG)),
+                Err(e) => Err(ConfigError::read_failed(Box::new(e)))
             }
         },
         Err(e) => match e.kind() {
             ErrorKind::NotFound => match create_dir_all(&config_dir).await {
-                Err(e) => return Err(ConfigError::dir_creation_failed(Box::new(e))),
+                Err(e) => Err(ConfigError::dir_creation_failed(Box::new(e))),
                 Ok(_) => {
                     DEFAULT_CONFIG.save(dir).await?;
-                    return Ok(DEFAULT_CONFIG)
+                    Ok(DEFAULT_CONFIG)
                 }
             },
-            _ => return Err(ConfigError::save_failed(Box::new(e))),
+            _ => Err(ConfigError::save_failed(Box::new(e))),
         }
-    };
+    }
+}
+
+/// Path of `.gtr/config.toml` of repository in `dir`
+pub fn config_path(dir: &Path) -> PathBuf {
+    let (_, settings_path) = get_config_path_dir_and_file(dir);
+    settings_path
 }
 
-fn get_config_path_dir_and_file(dir: &PathBuf) -> (PathBuf, PathBuf) {
+fn get_config_path_dir_and_file(dir: &Path) -> (PathBuf, PathBuf) {
     let config_dir = dir.join(CONFIG_DIR);
     let settings_path = config_dir.join(CONFIG_FILE);
 
-    return (config_dir, settings_path)
+    (config_dir, settings_path)
 }
@@ -0,0 +1,176 @@
+use std::collections::BTreeMap;
+use std::io::ErrorKind;
+use std::path::PathBuf;
+use serde::{Deserialize, Serialize};
+use tokio::fs;
+
+use crate::config::user_dirs::{self, config_dir};
+use crate::identity::key_hash;
+use crate::utils::error::{ConfigError, ContactError, GtrResult};
+use crate::utils::hex::from_hex;
+
+// NOTE: https://spritelyproject.org/news/petname-systems.html
+// Users are addressed by hash of their public key, `torrent://<hex sha1>/reponame`. The address
+// book in `$XDG_CONFIG_HOME/gtr/contacts.toml` gives them local names chosen by the user, so that
+// `git clone torrent://alice/reponame` works, see `transports::url`. Contacts are stored with the
+// hash or, if it is known, the public key.
+
+const CONTACTS_FILE: &str = "contacts.toml";
+// shorter than any encoding of keys, so that mistyped key is not taken for petname
+const MAX_PETNAME_LEN: usize = 32;
+
+/// Petnames with hex hash or public key of the contact
+#[derive(Serialize, Deserialize, Default, Debug, PartialEq)]
+pub struct Contacts {
+    pub contacts: BTreeMap<String, String>,
+}
+
+impl Contacts {
+    /// Hash of user given by hex hash or petname
+    pub fn resolve(&self, user: &str) -> GtrResult<[u8; 20]> {
+        if let Some(hash) = from_hex(user) {
+            return Ok(hash)
+        }
+        match self.contacts.get(user) {
+            Some(key) => parse_key(key),
+            None => Err(ContactError::unknown_contact(user)),
+        }
+    }
+}
+
+/// All contacts, none if the address book does not exist yet
+pub async fn list() -> GtrResult<Contacts> {
+    read().await
+}
+
+/// Adds contact with hex hash (40 digits) or public key (64 digits)
+pub async fn add(name: &str, key: &str) -> GtrResult<()> {
+    validate_name(name)?;
+    parse_key(key)?;
+    let _lock = lock().await?;
+    let mut contacts = read().await?;
+    if contacts.contacts.contains_key(name) {
+        return Err(ContactError::contact_exists(name))
+    }
+    contacts.contacts.insert(String::from(name), key.to_lowercase());
+    write(&contacts).await
+}
+
+pub async fn remove(name: &str) -> GtrResult<()> {
+    let _lock = lock().await?;
+    let mut contacts = read().await?;
+    if contacts.contacts.remove(name).is_none() {
+        return Err(ContactError::unknown_contact(name))
+    }
+    write(&contacts).await
+}
+
+pub async fn rename(name: &str, new_name: &str) -> GtrResult<()> {
+    validate_name(new_name)?;
+    let _lock = lock().await?;
+    let mut contacts = read().await?;
+    if contacts.contacts.contains_key(new_name) {
+        return Err(ContactError::contact_exists(new_name))
+    }
+    match contacts.contacts.remove(name) {
+        Some(key) => contacts.contacts.insert(String::from(new_name), key),
+        None => return Err(ContactError::unknown_contact(name)),
+    };
+    write(&contacts).await
+}
+
+/// Hash of contact stored by hash or public key
+pub fn parse_key(key: &str) -> GtrResult<[u8; 20]> {
+    if let Some(hash) = from_hex::<20>(key) {
+        return Ok(hash)
+    }
+    match from_hex::<32>(key) {
+        Some(public_key) => Ok(key_hash(&public_key)),
+        None => Err(ContactError::invalid_key(key)),
+    }
+}
+
+/// Petnames are user part of addresses, they can not be mistaken for keys
+pub fn is_petname(name: &str) -> bool {
+    !name.is_empty()
+        && name.len() <= MAX_PETNAME_LEN
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+fn validate_name(name: &str) -> GtrResult<()> {
+    match is_petname(name) {
+        true => Ok(()),
+        false => Err(ContactError::invalid_petname(name)),
+    }
+}
+
+fn contacts_path() -> Option<PathBuf> {
+    config_dir().map(|dir| dir.join(CONTACTS_FILE))
+}
+
+async fn read() -> GtrResult<Contacts> {
+    let path = match contacts_path() {
+        Some(path) => path,
+        None => return Ok(Contacts::default()),
+    };
+    match fs::read_to_string(&path).await {
+        Ok(data) => match toml::from_str(&data) {
+            Ok(contacts) => Ok(contacts),
+            Err(e) => Err(ConfigError::read_failed(Box::new(e))),
+        },
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(Contacts::default()),
+        Err(e) => Err(ConfigError::read_failed(Box::new(e))),
+    }
+}
+
+/// Address book is changed under its lock, so that concurrent changes are not lost
+async fn lock() -> GtrResult<std::fs::File> {
+    match contacts_path() {
+        Some(path) => user_dirs::lock(&path).await,
+        None => Err(ConfigError::save_failed("home directory is not known".into())),
+    }
+}
+
+async fn write(contacts: &Contacts) -> GtrResult<()> {
+    let path = match contacts_path() {
+        Some(path) => path,
+        None => return Err(ConfigError::save_failed("home directory is not known".into())),
+    };
+    let content = toml::to_string(contacts).unwrap_or_default();
+    user_dirs::replace(&path, &content).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HASH: &str = "56e6f1895a68d79df34436af3b69dfbbce19a6c5";
+    const PUBLIC_KEY: &str = "d75a980182b10ab7d54bfed3c964073a0ee172f3daa62325af021a68f707511a";
+
+    #[test]
+    fn resolves_petnames() {
+        let contacts = Contacts {
+            contacts: BTreeMap::from([
+                (String::from("alice"), String::from(HASH)),
+                (String::from("bob"), String::from(PUBLIC_KEY)),
+            ]),
+        };
+
+        assert_eq!(contacts.resolve("alice").unwrap(), from_hex(HASH).unwrap());
+        assert_eq!(contacts.resolve(HASH).unwrap(), from_hex(HASH).unwrap());
+        assert_eq!(contacts.resolve("bob").unwrap(), key_hash(&from_hex(PUBLIC_KEY).unwrap()));
+        assert!(contacts.resolve("carol").is_err());
+
+        assert!(validate_name("alice-laptop").is_ok());
+        assert!(validate_name(HASH).is_err());
+        assert!(validate_name("alice/gtr").is_err());
+    }
+
+    #[test]
+    fn accepts_only_ascii_petnames() {
+        assert!(is_petname("alice_2-laptop"));
+        for name in ["", ".", "..", "alice.bob", "ålice", "алиса", "alice bob", &"a".repeat(MAX_PETNAME_LEN + 1)] {
+            assert!(!is_petname(name), "{name}");
+        }
+    }
+}
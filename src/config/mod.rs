@@ -1,2 +1,5 @@
 pub mod branches;
 pub mod config_file;
+pub mod contacts;
+pub mod repositories;
+pub mod user_dirs;
@@ -0,0 +1,138 @@
+use std::collections::BTreeSet;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use serde::{Deserialize, Serialize};
+use tokio::fs;
+
+use crate::config::branches;
+use crate::config::config_file::config_path;
+use crate::config::user_dirs::{self, config_dir};
+use crate::utils::error::{ConfigError, GtrResult};
+
+// NOTE: gittorrentd shares all repositories under the directory it runs in which have
+// `git-daemon-export-ok` file. gtr keeps repositories with shared branches of the user in
+// `$XDG_CONFIG_HOME/gtr/repos.toml` instead, the daemon serves them wherever they are.
+
+const REPOS_FILE: &str = "repos.toml";
+
+/// Repositories with shared branches, by absolute path
+#[derive(Serialize, Deserialize, Default, Debug, PartialEq)]
+pub struct Repositories {
+    pub repositories: BTreeSet<PathBuf>,
+}
+
+/// Location of the registry, `$XDG_CONFIG_HOME/gtr/repos.toml`
+pub fn registry_path() -> Option<PathBuf> {
+    config_dir().map(|dir| dir.join(REPOS_FILE))
+}
+
+/// Registered repositories, none if the registry does not exist yet
+pub async fn list() -> GtrResult<Vec<PathBuf>> {
+    Ok(read().await?.repositories.into_iter().collect())
+}
+
+/// Registers repository in `dir` while it shares any branch and unregisters it otherwise, called
+/// after its shared branches change
+pub async fn update(dir: &PathBuf) -> GtrResult<()> {
+    let dir = std::fs::canonicalize(dir).unwrap_or(dir.clone());
+    let shared = !branches::list(&dir).await?.is_empty();
+
+    let _lock = lock().await?;
+    let mut registered = read().await?;
+    let changed = match shared {
+        true => registered.repositories.insert(dir),
+        false => registered.repositories.remove(&dir),
+    };
+    if changed {
+        write(&registered).await?;
+    }
+    Ok(())
+}
+
+/// Registers repositories with shared branches under `root`, e.g. the ones shared before the
+/// registry existed, returns them
+pub async fn scan(root: &PathBuf) -> GtrResult<Vec<PathBuf>> {
+    let mut found = Vec::new();
+    let mut pending = vec![std::fs::canonicalize(root).unwrap_or(root.clone())];
+    while let Some(dir) = pending.pop() {
+        if config_path(&dir).exists() {
+            if !branches::list(&dir).await?.is_empty() {
+                found.push(dir);
+            }
+            // nested repositories (submodules) are not shared on their own
+            continue
+        }
+        let mut entries = match fs::read_dir(&dir).await {
+            Ok(entries) => entries,
+            Err(_) => continue,
+        };
+        while let Ok(Some(entry)) = entries.next_entry().await {
+            let is_dir = entry.file_type().await.map(|t| t.is_dir()).unwrap_or(false);
+            if is_dir && !is_hidden(&entry.path()) {
+                pending.push(entry.path());
+            }
+        }
+    }
+
+    let _lock = lock().await?;
+    let mut registered = read().await?;
+    registered.repositories.extend(found.iter().cloned());
+    write(&registered).await?;
+
+    found.sort();
+    Ok(found)
+}
+
+fn is_hidden(path: &Path) -> bool {
+    path.file_name().is_some_and(|name| name.to_string_lossy().starts_with('.'))
+}
+
+async fn read() -> GtrResult<Repositories> {
+    let path = match registry_path() {
+        Some(path) => path,
+        None => return Ok(Repositories::default()),
+    };
+    match fs::read_to_string(&path).await {
+        Ok(data) => match toml::from_str(&data) {
+            Ok(registered) => Ok(registered),
+            Err(e) => Err(ConfigError::read_failed(Box::new(e))),
+        },
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(Repositories::default()),
+        Err(e) => Err(ConfigError::read_failed(Box::new(e))),
+    }
+}
+
+/// Registry is changed under its lock, so that concurrent updates (e.g. of hooks) are not lost
+async fn lock() -> GtrResult<std::fs::File> {
+    match registry_path() {
+        Some(path) => user_dirs::lock(&path).await,
+        None => Err(ConfigError::save_failed("home directory is not known".into())),
+    }
+}
+
+async fn write(registered: &Repositories) -> GtrResult<()> {
+    let path = match registry_path() {
+        Some(path) => path,
+        None => return Err(ConfigError::save_failed("home directory is not known".into())),
+    };
+    let content = toml::to_string(registered).unwrap_or_default();
+    user_dirs::replace(&path, &content).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stores_paths_of_repositories() {
+        let registered = Repositories {
+            repositories: BTreeSet::from([PathBuf::from("/home/user/gtr"), PathBuf::from("/srv/linux")]),
+        };
+        let content = toml::to_string(&registered).unwrap();
+        assert_eq!(content, "repositories = [\"/home/user/gtr\", \"/srv/linux\"]\n");
+        assert_eq!(toml::from_str::<Repositories>(&content).unwrap(), registered);
+
+        assert!(is_hidden(Path::new("/srv/.cache")));
+        assert!(!is_hidden(Path::new("/srv/linux")));
+    }
+}
@@ -0,0 +1,119 @@
+use std::env;
+use std::path::{Path, PathBuf};
+use tokio::fs::{self, OpenOptions};
+use tokio::io::AsyncWriteExt;
+
+use crate::utils::error::{ConfigError, GtrResult};
+
+// NOTE: https://specifications.freedesktop.org/basedir-spec/latest/
+// Settings in `.gtr` belong to a repository, the ones shared by all repositories of the user
+// (identity, DHT state, ...) are kept in XDG base directories under `gtr`.
+const APP_DIR: &str = "gtr";
+
+/// User settings, `$XDG_CONFIG_HOME/gtr` or `~/.config/gtr`
+pub fn config_dir() -> Option<PathBuf> {
+    base_dir("XDG_CONFIG_HOME", ".config")
+}
+
+/// State kept between runs, `$XDG_STATE_HOME/gtr` or `~/.local/state/gtr`
+pub fn state_dir() -> Option<PathBuf> {
+    base_dir("XDG_STATE_HOME", ".local/state")
+}
+
+/// Data which may be lost, `$XDG_CACHE_HOME/gtr` or `~/.cache/gtr`
+pub fn cache_dir() -> Option<PathBuf> {
+    base_dir("XDG_CACHE_HOME", ".cache")
+}
+
+/// Sockets and other files living while the user is logged in, `$XDG_RUNTIME_DIR/gtr`, the state
+/// directory when it is not set
+pub fn runtime_dir() -> Option<PathBuf> {
+    match env::var("XDG_RUNTIME_DIR") {
+        Ok(dir) if PathBuf::from(&dir).is_absolute() => Some(PathBuf::from(dir).join(APP_DIR)),
+        _ => state_dir(),
+    }
+}
+
+/// Takes exclusive lock of user file (`<file>.lock`), it is released when returned file is dropped
+///
+/// Files changed by several processes (e.g. `gtr` and hooks) are read and replaced under it.
+pub async fn lock(path: &Path) -> GtrResult<std::fs::File> {
+    create_parent(path).await?;
+    let lock_path = aside(path, "lock");
+    let file = match std::fs::File::options().create(true).truncate(false).write(true).open(lock_path) {
+        Ok(file) => file,
+        Err(e) => return Err(ConfigError::save_failed(Box::new(e))),
+    };
+
+    let locked = tokio::task::spawn_blocking(move || file.lock().map(|_| file)).await;
+    match locked {
+        Ok(Ok(file)) => Ok(file),
+        Ok(Err(e)) => Err(ConfigError::save_failed(Box::new(e))),
+        Err(e) => Err(ConfigError::save_failed(Box::new(e))),
+    }
+}
+
+/// Replaces content of user file, it is written aside and renamed so that readers never see it
+/// partially written
+pub async fn replace(path: &Path, content: &str) -> GtrResult<()> {
+    create_parent(path).await?;
+    let written = aside(path, "new");
+    let write = async {
+        // left by interrupted write, the caller holds the lock of the file
+        let _ = fs::remove_file(&written).await;
+        let mut file = OpenOptions::new().write(true).create_new(true).open(&written).await?;
+        file.write_all(content.as_bytes()).await?;
+        file.sync_all().await?;
+        fs::rename(&written, path).await
+    };
+    match write.await {
+        Ok(()) => Ok(()),
+        Err(e) => Err(ConfigError::save_failed(Box::new(e))),
+    }
+}
+
+async fn create_parent(path: &Path) -> GtrResult<()> {
+    if let Some(dir) = path.parent() {
+        if let Err(e) = fs::create_dir_all(dir).await {
+            return Err(ConfigError::dir_creation_failed(Box::new(e)))
+        }
+    }
+    Ok(())
+}
+
+/// File next to `path` with `extension` appended, e.g. `repos.toml.lock`
+fn aside(path: &Path, extension: &str) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(format!(".{extension}"));
+    path.with_file_name(name)
+}
+
+fn base_dir(variable: &str, default: &str) -> Option<PathBuf> {
+    // relative paths are invalid and should be ignored according to the specification
+    let base = match env::var(variable) {
+        Ok(dir) if PathBuf::from(&dir).is_absolute() => PathBuf::from(dir),
+        _ => PathBuf::from(env::var("HOME").ok()?).join(default),
+    };
+    Some(base.join(APP_DIR))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn replaces_file_under_lock() {
+        let dir = env::temp_dir().join(format!("gtr-user-dirs-{}", std::process::id()));
+        let path = dir.join("repos.toml");
+
+        let lock = lock(&path).await.unwrap();
+        replace(&path, "old").await.unwrap();
+        replace(&path, "new").await.unwrap();
+        drop(lock);
+
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "new");
+        assert!(dir.join("repos.toml.lock").exists());
+        assert!(!dir.join("repos.toml.new").exists());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,238 @@
+use std::os::fd::FromRawFd;
+use std::path::PathBuf;
+use std::time::Duration;
+use tokio::fs;
+use tokio::net::{UnixListener, UnixStream};
+use tokio::time::timeout;
+
+use crate::config::user_dirs::runtime_dir;
+use crate::git_interface::pkt_line::{Packet, PktReader, PktWriter};
+use crate::utils::error::{ConfigError, DaemonError, GtrResult, ProtocolError};
+
+// NOTE: the CLI talks to running daemon over `$XDG_RUNTIME_DIR/gtr/gtd.sock` with pkt-lines, the
+// same framing as git uses. Request is a single line followed by flush-pkt, response is a line per
+// repository or peer followed by flush-pkt, failure is a single `ERR <message>` line as in git.
+//
+//   reload <path>        serve repository again after its settings changed
+//   republish [<path>]   announce shared refs of repository, or all of them, now
+//   status <path>        result of the last announce of repository
+//   stats                connected peers and transferred pack data of each transport
+
+const SOCKET_FILE: &str = "gtd.sock";
+// stuck clients must not keep their connections open forever
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+// https://www.freedesktop.org/software/systemd/man/latest/sd_listen_fds.html
+const SD_LISTEN_FDS_START: i32 = 3;
+
+/// Location of control socket, `$XDG_RUNTIME_DIR/gtr/gtd.sock`
+pub fn socket_path() -> Option<PathBuf> {
+    runtime_dir().map(|dir| dir.join(SOCKET_FILE))
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Request {
+    Reload(PathBuf),
+    Republish(Option<PathBuf>),
+    Status(PathBuf),
+    Stats,
+}
+
+impl Request {
+    pub fn encode(&self) -> String {
+        match self {
+            Request::Reload(dir) => format!("reload {}", dir.display()),
+            Request::Republish(Some(dir)) => format!("republish {}", dir.display()),
+            Request::Republish(None) => String::from("republish"),
+            Request::Status(dir) => format!("status {}", dir.display()),
+            Request::Stats => String::from("stats"),
+        }
+    }
+
+    /// The same request with absolute paths, the daemon runs in another directory
+    fn absolute(&self) -> Self {
+        let absolute = |dir: &PathBuf| std::fs::canonicalize(dir).unwrap_or(dir.clone());
+        match self {
+            Request::Reload(dir) => Request::Reload(absolute(dir)),
+            Request::Republish(dir) => Request::Republish(dir.as_ref().map(absolute)),
+            Request::Status(dir) => Request::Status(absolute(dir)),
+            Request::Stats => Request::Stats,
+        }
+    }
+
+    pub fn decode(line: &str) -> GtrResult<Self> {
+        let (command, dir) = match line.split_once(' ') {
+            Some((command, dir)) => (command, Some(PathBuf::from(dir))),
+            None => (line, None),
+        };
+        match (command, dir) {
+            ("reload", Some(dir)) => Ok(Request::Reload(dir)),
+            ("republish", dir) => Ok(Request::Republish(dir)),
+            ("status", Some(dir)) => Ok(Request::Status(dir)),
+            ("stats", None) => Ok(Request::Stats),
+            _ => Err(ProtocolError::pkt_malformed(&format!("unknown request: {line}"))),
+        }
+    }
+}
+
+/// Checks whether the daemon runs, i.e. its control socket accepts connections
+pub async fn running() -> bool {
+    match socket_path() {
+        Some(path) => UnixStream::connect(&path).await.is_ok(),
+        None => false,
+    }
+}
+
+/// Sends request to running daemon, returns lines of its response
+pub async fn request(request: &Request) -> GtrResult<Vec<String>> {
+    let path = match socket_path() {
+        Some(path) => path,
+        None => return Err(DaemonError::not_running("home directory is not known".into())),
+    };
+    let mut stream = match UnixStream::connect(&path).await {
+        Ok(stream) => stream,
+        Err(e) => return Err(DaemonError::not_running(Box::new(e))),
+    };
+    exchange(&mut stream, &request.absolute()).await
+}
+
+async fn exchange(stream: &mut UnixStream, request: &Request) -> GtrResult<Vec<String>> {
+    let mut writer = PktWriter::new(&mut *stream);
+    writer.write_text(&request.encode()).await?;
+    writer.write_flush().await?;
+
+    let (section, _) = PktReader::new(&mut *stream).read_section().await?;
+    let lines: Vec<String> = section
+        .into_iter()
+        .filter_map(|data| Packet::Data(data).as_text().map(String::from))
+        .collect();
+    if let Some(message) = lines.first().and_then(|line| line.strip_prefix("ERR ")) {
+        return Err(ProtocolError::remote_failed(message))
+    }
+    Ok(lines)
+}
+
+/// Listening control socket of the daemon, the socket file is removed when it is dropped
+pub struct ControlSocket {
+    listener: UnixListener,
+    // none for socket passed by systemd, it owns the file
+    path: Option<PathBuf>,
+}
+
+impl ControlSocket {
+    /// Takes socket passed by systemd socket activation (`gtr daemon install --socket`) or binds
+    /// `socket_path()`
+    pub async fn bind() -> GtrResult<Self> {
+        if let Some(listener) = activated()? {
+            return Ok(ControlSocket { listener, path: None })
+        }
+
+        let path = match socket_path() {
+            Some(path) => path,
+            None => return Err(DaemonError::control_failed("home directory is not known".into())),
+        };
+        if UnixStream::connect(&path).await.is_ok() {
+            return Err(DaemonError::already_running(&path))
+        }
+        // left by daemon which did not stop cleanly
+        let _ = fs::remove_file(&path).await;
+        if let Some(dir) = path.parent() {
+            if let Err(e) = fs::create_dir_all(dir).await {
+                return Err(ConfigError::dir_creation_failed(Box::new(e)))
+            }
+        }
+        match UnixListener::bind(&path) {
+            Ok(listener) => Ok(ControlSocket { listener, path: Some(path) }),
+            Err(e) => Err(DaemonError::control_failed(Box::new(e))),
+        }
+    }
+
+    pub async fn accept(&self) -> GtrResult<UnixStream> {
+        match self.listener.accept().await {
+            Ok((stream, _)) => Ok(stream),
+            Err(e) => Err(DaemonError::control_failed(Box::new(e))),
+        }
+    }
+}
+
+impl Drop for ControlSocket {
+    fn drop(&mut self) {
+        if let Some(path) = &self.path {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
+
+/// Reads request of connected client
+pub async fn read_request(stream: &mut UnixStream) -> GtrResult<Request> {
+    let read = async {
+        let (section, _) = PktReader::new(&mut *stream).read_section().await?;
+        match section.into_iter().next().map(Packet::Data) {
+            Some(packet) => Request::decode(packet.as_text().unwrap_or_default()),
+            None => Err(ProtocolError::pkt_malformed("empty request")),
+        }
+    };
+    match timeout(REQUEST_TIMEOUT, read).await {
+        Ok(request) => request,
+        Err(_) => Err(ProtocolError::pkt_malformed("request timed out")),
+    }
+}
+
+/// Answers request with lines of response or its failure
+pub async fn respond(stream: &mut UnixStream, response: GtrResult<Vec<String>>) -> GtrResult<()> {
+    let mut writer = PktWriter::new(stream);
+    match response {
+        Ok(lines) => for line in lines {
+            writer.write_text(&line).await?;
+        },
+        Err(e) => writer.write_text(&format!("ERR {e}")).await?,
+    }
+    writer.write_flush().await
+}
+
+/// Listening socket passed by systemd if the daemon was started by its socket unit
+fn activated() -> GtrResult<Option<UnixListener>> {
+    let variable = |name| std::env::var(name).ok().and_then(|value| value.parse::<u32>().ok());
+    if variable("LISTEN_PID") != Some(std::process::id()) || variable("LISTEN_FDS").unwrap_or(0) < 1 {
+        return Ok(None)
+    }
+
+    // SAFETY: systemd passes the socket as the first descriptor after stdio, nothing else owns it
+    let listener = unsafe { std::os::unix::net::UnixListener::from_raw_fd(SD_LISTEN_FDS_START) };
+    let listener = listener.set_nonblocking(true).and_then(|_| UnixListener::from_std(listener));
+    match listener {
+        Ok(listener) => Ok(Some(listener)),
+        Err(e) => Err(DaemonError::control_failed(Box::new(e))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn exchanges_requests_and_responses() {
+        for request in [
+            Request::Reload(PathBuf::from("/srv/my repo")),
+            Request::Republish(None),
+            Request::Republish(Some(PathBuf::from("/srv/gtr"))),
+            Request::Status(PathBuf::from("/srv/gtr")),
+            Request::Stats,
+        ] {
+            assert_eq!(Request::decode(&request.encode()).unwrap(), request);
+        }
+        assert!(Request::decode("reload").is_err());
+
+        let (mut client, mut daemon) = UnixStream::pair().unwrap();
+        let answered = tokio::spawn(async move {
+            let request = read_request(&mut daemon).await.unwrap();
+            assert_eq!(request, Request::Stats);
+            respond(&mut daemon, Ok(vec![String::from("torrent: 0 peers")])).await.unwrap();
+
+            read_request(&mut daemon).await.unwrap();
+            respond(&mut daemon, Err(ProtocolError::remote_failed("not served"))).await.unwrap();
+        });
+        assert_eq!(exchange(&mut client, &Request::Stats).await.unwrap(), vec!["torrent: 0 peers"]);
+        assert!(exchange(&mut client, &Request::Republish(None)).await.is_err());
+        answered.await.unwrap();
+    }
+}
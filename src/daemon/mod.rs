@@ -0,0 +1,343 @@
+pub mod control;
+pub mod service;
+
+use std::collections::BTreeMap;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, SystemTime};
+use log::{error, info, warn};
+use serde::{Deserialize, Serialize};
+use tokio::fs;
+use tokio::net::UnixStream;
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::Mutex as AsyncMutex;
+use tokio::time::{interval, Instant, MissedTickBehavior};
+
+use crate::config::config_file::{self, config_path, Config, Transport};
+use crate::config::repositories;
+use crate::config::user_dirs::config_dir;
+use crate::git_interface::protocol_v2::shared_refs;
+use crate::transports::{registry, Registry};
+use crate::utils::error::{ConfigError, DaemonError, GtrResult};
+use control::{ControlSocket, Request};
+
+// NOTE: the daemon serves all repositories of the user with one instance of each transport (a
+// single DHT node), see note 2 in `main.rs`. Changes of `.gtr/config.toml` made by `gtr share` and
+// `gtr remove` are picked up by polling its modification time, announces are repeated before
+// peers drop them. Without given repositories the daemon follows the registry of the user,
+// `$XDG_CONFIG_HOME/gtr/repos.toml`, which is re-read with the same polling. The CLI asks it to
+// act on changes right away over control socket, see `control.rs`.
+
+const DAEMON_FILE: &str = "daemon.toml";
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
+// DHT nodes drop announces after 30 minutes
+const REANNOUNCE_INTERVAL: Duration = Duration::from_secs(15 * 60);
+/// Status of repository the daemon does not serve
+pub const NOT_SERVED: &str = "not served";
+/// Prefix of status of repository which failed to be announced
+pub const ANNOUNCE_FAILED: &str = "announce failed, ";
+
+/// Settings of the daemon, `$XDG_CONFIG_HOME/gtr/daemon.toml`
+#[derive(Serialize, Deserialize, Default, Debug)]
+pub struct DaemonConfig {
+    #[serde(default)]
+    pub transport: Transport,
+}
+
+/// State of served repository
+#[derive(Default)]
+struct Served {
+    // modification time of `.gtr/config.toml` when the repository was served
+    modified: Option<SystemTime>,
+    announced: Option<Instant>,
+    // number of announced refs or failure of the last announce
+    result: Option<Result<usize, String>>,
+}
+
+impl Served {
+    /// Result of the last announce as shown by `gtr list`
+    fn status(&self) -> String {
+        match (&self.result, self.announced) {
+            (Some(Ok(refs)), Some(announced)) => format!("announced {refs} refs {}s ago", announced.elapsed().as_secs()),
+            (Some(Err(e)), _) => format!("{ANNOUNCE_FAILED}{e}"),
+            _ => String::from("not announced yet"),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct Daemon {
+    registry: Arc<Registry>,
+    repositories: Arc<Mutex<BTreeMap<PathBuf, Served>>>,
+    // announces are made one at a time, requests which do not announce are answered meanwhile
+    announcing: Arc<AsyncMutex<()>>,
+    // repositories are the registered ones instead of given ones
+    registered: bool,
+}
+
+impl Daemon {
+    /// Starts transports for serving given repositories, or registered ones if none is given
+    pub async fn start(repositories: &[PathBuf]) -> GtrResult<Self> {
+        let registered = repositories.is_empty();
+        let repositories = match registered {
+            true => repositories::list().await?,
+            false => repositories.to_vec(),
+        };
+        let repositories: BTreeMap<PathBuf, Served> = repositories
+            .iter()
+            .map(|dir| (canonical(dir), Served::default()))
+            .collect();
+
+        let dirs: Vec<&PathBuf> = repositories.keys().collect();
+        let transport = transport_config(&dirs).await?;
+        let registry = registry(&Config { transport, ..Config::default() }).await?;
+
+        Ok(Daemon {
+            registry: Arc::new(registry),
+            repositories: Arc::new(Mutex::new(repositories)),
+            announcing: Arc::new(AsyncMutex::new(())),
+            registered,
+        })
+    }
+
+    /// Serves repositories and answers requests on control socket until SIGINT or SIGTERM, then
+    /// stops transports
+    ///
+    /// Repositories are announced in background and each request is answered in its own task, so
+    /// a slow announce delays neither other requests nor shutdown.
+    pub async fn run(&self) -> GtrResult<()> {
+        let control = ControlSocket::bind().await?;
+        let mut terminate = match signal(SignalKind::terminate()) {
+            Ok(terminate) => terminate,
+            Err(e) => return Err(DaemonError::signal_failed(Box::new(e))),
+        };
+        let daemon = self.clone();
+        let reloading = tokio::spawn(async move {
+            let mut ticks = interval(POLL_INTERVAL);
+            ticks.set_missed_tick_behavior(MissedTickBehavior::Delay);
+            loop {
+                ticks.tick().await;
+                daemon.reload().await;
+            }
+        });
+
+        loop {
+            tokio::select! {
+                accepted = control.accept() => match accepted {
+                    Ok(stream) => {
+                        let daemon = self.clone();
+                        tokio::spawn(async move { daemon.control(stream).await });
+                    },
+                    Err(e) => warn!("control socket: {e}"),
+                },
+                _ = tokio::signal::ctrl_c() => break,
+                _ = terminate.recv() => break,
+            }
+        }
+        reloading.abort();
+        self.registry.shutdown().await
+    }
+
+    /// Serves repositories which are new, changed their settings or are due to be announced again
+    async fn reload(&self) {
+        let _announcing = self.announcing.lock().await;
+        if self.registered {
+            self.follow_registry().await;
+        }
+        let repositories: Vec<(PathBuf, bool, Option<SystemTime>)> = self.repositories()
+            .iter()
+            .map(|(dir, served)| {
+                let due = served.announced.is_none_or(|announced| announced.elapsed() >= REANNOUNCE_INTERVAL);
+                (dir.clone(), due, served.modified)
+            })
+            .collect();
+        for (dir, due, served_modified) in repositories {
+            // failed repositories are retried with the next change or announce
+            if due || modified(&dir).await != served_modified {
+                self.announce(&dir).await;
+            }
+        }
+        for transport in self.registry.transports() {
+            transport.failures().iter().for_each(|e| warn!("{}: {e}", transport.scheme()));
+        }
+    }
+
+    /// Answers request of the CLI connected to control socket
+    async fn control(&self, mut stream: UnixStream) {
+        let response = match control::read_request(&mut stream).await {
+            Ok(request) => Ok(self.answer(request).await),
+            Err(e) => Err(e),
+        };
+        // the client may be gone, e.g. another daemon checking whether this one runs
+        let _ = control::respond(&mut stream, response).await;
+    }
+
+    async fn answer(&self, request: Request) -> Vec<String> {
+        match request {
+            Request::Reload(dir) => {
+                let dir = canonical(&dir);
+                let _announcing = self.announcing.lock().await;
+                // settings of the repository changed, so it might be (un)registered
+                if self.registered {
+                    self.follow_registry().await;
+                }
+                if !self.repositories().contains_key(&dir) {
+                    return vec![String::from(NOT_SERVED)]
+                }
+                self.announce(&dir).await;
+                vec![self.status(&dir)]
+            },
+            Request::Republish(dir) => {
+                let dir = dir.map(|dir| canonical(&dir));
+                let _announcing = self.announcing.lock().await;
+                let selected: Vec<PathBuf> = self.repositories()
+                    .keys()
+                    .filter(|served_dir| dir.as_ref().is_none_or(|dir| dir == *served_dir))
+                    .cloned()
+                    .collect();
+                let mut lines = Vec::new();
+                for served_dir in selected {
+                    self.announce(&served_dir).await;
+                    lines.push(format!("{}: {}", served_dir.display(), self.status(&served_dir)));
+                }
+                if lines.is_empty() && dir.is_some() {
+                    lines.push(String::from(NOT_SERVED));
+                }
+                lines
+            },
+            Request::Status(dir) => vec![self.status(&canonical(&dir))],
+            Request::Stats => {
+                let mut transports: Vec<_> = self.registry.transports().collect();
+                transports.sort_by_key(|transport| transport.scheme());
+                let mut lines = Vec::new();
+                for transport in transports {
+                    let stats = transport.stats();
+                    lines.push(format!(
+                        "{}: {} peers, uploaded {} bytes, downloaded {} bytes",
+                        transport.scheme(), stats.peers.len(), stats.uploaded, stats.downloaded
+                    ));
+                    lines.extend(stats.peers.iter().map(|peer| format!("  {peer}")));
+                }
+                lines
+            },
+        }
+    }
+
+    /// Adds newly registered repositories and stops announcing unregistered ones
+    async fn follow_registry(&self) {
+        let registered = match repositories::list().await {
+            Ok(registered) => registered,
+            Err(e) => return error!("registry: {e}"),
+        };
+        let unregistered: Vec<PathBuf> = {
+            let mut repositories = self.repositories();
+            for dir in &registered {
+                repositories.entry(dir.clone()).or_default();
+            }
+            let unregistered = repositories
+                .keys()
+                .filter(|dir| !registered.contains(dir))
+                .cloned()
+                .collect();
+            repositories.retain(|dir, _| registered.contains(dir));
+            unregistered
+        };
+        for dir in unregistered {
+            for transport in self.registry.transports() {
+                if let Err(e) = transport.announce(&dir, &[]).await {
+                    warn!("{}: {e}", dir.display());
+                }
+            }
+            info!("{}: unregistered", dir.display());
+        }
+    }
+
+    /// Serves repository and records the result, the caller holds `announcing`
+    async fn announce(&self, dir: &Path) {
+        let modified = modified(dir).await;
+        let announced = Instant::now();
+        let result = serve(&self.registry, dir).await.map_err(|e| e.to_string());
+        match &result {
+            Ok(refs) => info!("{}: announced {refs} refs", dir.display()),
+            Err(e) => warn!("{}: {e}", dir.display()),
+        }
+        if let Some(served) = self.repositories().get_mut(dir) {
+            *served = Served { modified, announced: Some(announced), result: Some(result) };
+        }
+    }
+
+    /// Result of the last announce of repository as shown by `gtr list`
+    fn status(&self, dir: &Path) -> String {
+        match self.repositories().get(dir) {
+            Some(served) => served.status(),
+            None => String::from(NOT_SERVED),
+        }
+    }
+
+    fn repositories(&self) -> MutexGuard<'_, BTreeMap<PathBuf, Served>> {
+        self.repositories.lock().unwrap()
+    }
+}
+
+/// Modification time of `.gtr/config.toml` of repository
+async fn modified(dir: &Path) -> Option<SystemTime> {
+    match fs::metadata(config_path(dir)).await {
+        Ok(metadata) => metadata.modified().ok(),
+        Err(_) => None,
+    }
+}
+
+fn canonical(dir: &PathBuf) -> PathBuf {
+    std::fs::canonicalize(dir).unwrap_or(dir.clone())
+}
+
+/// Serves repository with all transports and announces its shared refs, returns their number
+async fn serve(registry: &Registry, dir: &Path) -> GtrResult<usize> {
+    let refs = shared_refs(dir).await?;
+    for transport in registry.transports() {
+        transport.serve(dir).await?;
+        transport.announce(dir, &refs).await?;
+    }
+    Ok(refs.len())
+}
+
+/// Transports configured in `daemon.toml`, or the ones of the first repository configuring any
+pub async fn transport_config(repositories: &[&PathBuf]) -> GtrResult<Transport> {
+    if let Some(path) = config_dir().map(|dir| dir.join(DAEMON_FILE)) {
+        match fs::read_to_string(&path).await {
+            Ok(data) => match toml::from_str::<DaemonConfig>(&data) {
+                Ok(conf) => return Ok(conf.transport),
+                Err(e) => return Err(ConfigError::read_failed(Box::new(e))),
+            },
+            Err(e) if e.kind() == ErrorKind::NotFound => {},
+            Err(e) => return Err(ConfigError::read_failed(Box::new(e))),
+        }
+    }
+
+    for dir in repositories {
+        let conf = config_file::read_or_create(dir).await?;
+        if conf.transport.torrent.is_some() {
+            return Ok(conf.transport)
+        }
+    }
+    Ok(Transport::default())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_daemon_settings() {
+        let conf: DaemonConfig = toml::from_str("").unwrap();
+        assert!(conf.transport.torrent.is_none());
+
+        let conf: DaemonConfig = toml::from_str(r#"
+            [transport.torrent]
+            router = { addr = "router.bittorrent.com", port = 6881 }
+            bind = { addr = "0.0.0.0", port = 6881 }
+        "#).unwrap();
+        assert_eq!(conf.transport.torrent.unwrap().bind.port, 6881);
+    }
+}
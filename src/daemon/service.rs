@@ -0,0 +1,188 @@
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use tokio::fs;
+use tokio::process::Command;
+
+use crate::config::user_dirs::config_dir;
+use crate::utils::error::{DaemonError, GtrResult};
+
+// NOTE: https://www.freedesktop.org/software/systemd/man/latest/systemd.unit.html
+// `gtd` runs as a systemd user service serving registered repositories. User services start with
+// the first login, `loginctl enable-linger` starts them on boot. With socket activation systemd
+// listens on `$XDG_RUNTIME_DIR/gtr/gtd.sock` and starts the daemon on the first connection.
+// TODO: MACOS: launchd, WINDOWS: task scheduler
+
+pub const SERVICE_UNIT: &str = "gtd.service";
+pub const SOCKET_UNIT: &str = "gtd.socket";
+
+/// State of installed daemon as reported by systemd
+pub struct Status {
+    pub installed: bool,
+    // `is-enabled` and `is-active` of the unit started on login
+    pub enabled: String,
+    pub active: String,
+    pub socket: bool,
+}
+
+/// Directory of user units, `$XDG_CONFIG_HOME/systemd/user` or `~/.config/systemd/user`
+pub fn unit_dir() -> Option<PathBuf> {
+    // user units are not kept in the directory of gtr but next to it
+    config_dir()?.parent().map(|dir| dir.join("systemd").join("user"))
+}
+
+/// Writes units for `gtd` installed next to the running binary, enables and starts them
+pub async fn install(socket: bool) -> GtrResult<PathBuf> {
+    let gtd = match std::env::current_exe() {
+        Ok(exe) => exe.with_file_name("gtd"),
+        Err(e) => return Err(DaemonError::service_failed(&format!("can not locate gtd, {e}"))),
+    };
+    if !gtd.exists() {
+        return Err(DaemonError::service_failed(&format!("{} does not exist", gtd.display())))
+    }
+    let dir = match unit_dir() {
+        Some(dir) => dir,
+        None => return Err(DaemonError::service_failed("home directory is not known")),
+    };
+
+    // the socket starts the service, so only one of them is enabled
+    disable(&dir).await?;
+    write_unit(&dir.join(SERVICE_UNIT), &service_unit(&gtd, socket)).await?;
+    if socket {
+        write_unit(&dir.join(SOCKET_UNIT), &socket_unit()).await?;
+    } else {
+        remove_unit(&dir.join(SOCKET_UNIT)).await?;
+    }
+
+    systemctl(&["daemon-reload"]).await?;
+    systemctl(&["enable", "--now", started_unit(socket)]).await?;
+
+    Ok(dir.join(SERVICE_UNIT))
+}
+
+/// Stops and disables the daemon, removes its units
+pub async fn uninstall() -> GtrResult<()> {
+    let dir = match unit_dir() {
+        Some(dir) => dir,
+        None => return Err(DaemonError::service_failed("home directory is not known")),
+    };
+    if !dir.join(SERVICE_UNIT).exists() {
+        return Ok(())
+    }
+
+    disable(&dir).await?;
+    remove_unit(&dir.join(SERVICE_UNIT)).await?;
+    remove_unit(&dir.join(SOCKET_UNIT)).await?;
+    systemctl(&["daemon-reload"]).await?;
+
+    Ok(())
+}
+
+pub async fn status() -> GtrResult<Status> {
+    let dir = match unit_dir() {
+        Some(dir) => dir,
+        None => return Err(DaemonError::service_failed("home directory is not known")),
+    };
+    let installed = dir.join(SERVICE_UNIT).exists();
+    let socket = dir.join(SOCKET_UNIT).exists();
+    if !installed {
+        return Ok(Status { installed, enabled: String::from("disabled"), active: String::from("inactive"), socket })
+    }
+
+    // both exit with failure for disabled or inactive units, their output is the state
+    let enabled = query(&["is-enabled", started_unit(socket)]).await?;
+    let active = query(&["is-active", SERVICE_UNIT]).await?;
+    Ok(Status { installed, enabled, active, socket })
+}
+
+/// Stops and disables installed units, systemctl fails for the ones which do not exist
+async fn disable(dir: &Path) -> GtrResult<()> {
+    let installed: Vec<&str> = [SERVICE_UNIT, SOCKET_UNIT]
+        .into_iter()
+        .filter(|unit| dir.join(unit).exists())
+        .collect();
+    if installed.is_empty() {
+        return Ok(())
+    }
+    systemctl(&[&["disable", "--now"], installed.as_slice()].concat()).await
+}
+
+fn started_unit(socket: bool) -> &'static str {
+    match socket {
+        true => SOCKET_UNIT,
+        false => SERVICE_UNIT,
+    }
+}
+
+/// Service running `gtd` without paths, so it serves registered repositories
+fn service_unit(gtd: &Path, socket: bool) -> String {
+    let mut unit = String::from("[Unit]\nDescription=gtr daemon serving shared git repositories\n");
+    unit.push_str("After=network-online.target\nWants=network-online.target\n");
+    if socket {
+        unit.push_str(&format!("Requires={SOCKET_UNIT}\n"));
+    }
+    unit.push_str(&format!("\n[Service]\nExecStart={}\nRestart=on-failure\n", gtd.display()));
+    unit.push_str("\n[Install]\nWantedBy=default.target\n");
+    unit
+}
+
+fn socket_unit() -> String {
+    format!(
+        "[Unit]\nDescription=gtr daemon socket\n\n[Socket]\nListenStream=%t/gtr/gtd.sock\nService={SERVICE_UNIT}\n\n\
+        [Install]\nWantedBy=sockets.target\n"
+    )
+}
+
+async fn write_unit(path: &PathBuf, content: &str) -> GtrResult<()> {
+    if let Some(dir) = path.parent() {
+        if let Err(e) = fs::create_dir_all(dir).await {
+            return Err(DaemonError::service_failed(&format!("can not create {}, {e}", dir.display())))
+        }
+    }
+    match fs::write(path, content).await {
+        Ok(()) => Ok(()),
+        Err(e) => Err(DaemonError::service_failed(&format!("can not write {}, {e}", path.display()))),
+    }
+}
+
+async fn remove_unit(path: &PathBuf) -> GtrResult<()> {
+    match fs::remove_file(path).await {
+        Ok(()) => Ok(()),
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(DaemonError::service_failed(&format!("can not remove {}, {e}", path.display()))),
+    }
+}
+
+async fn systemctl(args: &[&str]) -> GtrResult<()> {
+    let output = match Command::new("systemctl").arg("--user").args(args).output().await {
+        Ok(output) => output,
+        Err(e) => return Err(DaemonError::service_failed(&format!("can not run systemctl, {e}"))),
+    };
+    if !output.status.success() {
+        return Err(DaemonError::service_failed(String::from_utf8_lossy(&output.stderr).trim_end()))
+    }
+    Ok(())
+}
+
+async fn query(args: &[&str]) -> GtrResult<String> {
+    match Command::new("systemctl").arg("--user").args(args).output().await {
+        Ok(output) => Ok(String::from_utf8_lossy(&output.stdout).trim().to_string()),
+        Err(e) => Err(DaemonError::service_failed(&format!("can not run systemctl, {e}"))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn generates_units() {
+        let unit = service_unit(&PathBuf::from("/usr/local/bin/gtd"), false);
+        assert!(unit.contains("\nExecStart=/usr/local/bin/gtd\n"));
+        assert!(unit.contains("\nWantedBy=default.target\n"));
+        assert!(!unit.contains("Requires="));
+
+        let unit = service_unit(&PathBuf::from("/usr/local/bin/gtd"), true);
+        assert!(unit.contains("\nRequires=gtd.socket\n"));
+        assert!(socket_unit().contains("\nListenStream=%t/gtr/gtd.sock\nService=gtd.service\n"));
+    }
+}
@@ -0,0 +1,140 @@
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use tokio::fs;
+use tokio::io::{AsyncBufReadExt, BufReader};
+
+use crate::git_interface::pack_cache::PackCache;
+use crate::git_interface::protocol_v2::shared_refs;
+use crate::git_interface::run_git;
+use crate::utils::error::{GtrResult, GitError};
+
+// NOTE: https://git-scm.com/docs/githooks
+const HOOKS: [&str; 3] = ["post-commit", "post-merge", "reference-transaction"];
+const MARKER: &str = "# gtr: precompute pack files of shared branches";
+// Existing hook is kept under this suffix and run by the installed one
+const ORIGINAL_SUFFIX: &str = ".gtr-orig";
+
+/// Installs git hooks which call `gtr hook <name>` whenever shared branches may have changed
+///
+/// Hooks run in background so that git is not blocked by pack generation. Installed hook is a
+/// wrapper owned by gtr, existing hook is renamed to `<hook>.gtr-orig` and run by the wrapper
+/// with the same arguments and input, its exit status is the one of the wrapper.
+pub async fn install(dir: &Path) -> GtrResult<()> {
+    let hooks_dir = match run_git(dir, &["rev-parse", "--git-path", "hooks"], Stdio::null(), None).await {
+        Ok(hooks_dir) => dir.join(hooks_dir.trim_end()),
+        Err(e) => return Err(GitError::hook_install_failed(e.into())),
+    };
+    let gtr = match std::env::current_exe() {
+        Ok(gtr) => gtr,
+        Err(e) => return Err(GitError::hook_install_failed(Box::new(e))),
+    };
+    if let Err(e) = fs::create_dir_all(&hooks_dir).await {
+        return Err(GitError::hook_install_failed(Box::new(e)))
+    }
+
+    for hook in HOOKS {
+        let path = hooks_dir.join(hook);
+        let original = hooks_dir.join(format!("{hook}{ORIGINAL_SUFFIX}"));
+        match fs::read(&path).await {
+            Ok(content) if String::from_utf8_lossy(&content).contains(MARKER) => continue,
+            Ok(_) if original.exists() => {
+                let message = format!("{} exists, can not keep {} as well", original.display(), path.display());
+                return Err(GitError::hook_install_failed(message.into()))
+            },
+            Ok(_) => if let Err(e) = fs::rename(&path, &original).await {
+                return Err(GitError::hook_install_failed(Box::new(e)))
+            },
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {},
+            Err(e) => return Err(GitError::hook_install_failed(Box::new(e))),
+        }
+
+        if let Err(e) = write_executable(&path, &hook_script(hook, &gtr)).await {
+            return Err(GitError::hook_install_failed(Box::new(e)))
+        }
+    }
+
+    Ok(())
+}
+
+/// Handles invocation of installed hook
+pub async fn run(dir: &PathBuf, hook: &str, args: &[String]) -> GtrResult<()> {
+    if hook.eq("reference-transaction") {
+        // hook is called for each transaction state, refs are updated only once it is committed
+        if !args.first().is_some_and(|state| state.eq("committed")) { return Ok(()) }
+
+        let shared = shared_refs(dir).await?;
+        let mut lines = BufReader::new(tokio::io::stdin()).lines();
+        let mut changed = false;
+        // <old-value> SP <new-value> SP <ref-name> LF
+        while let Ok(Some(line)) = lines.next_line().await {
+            changed |= line.split(' ').nth(2).is_some_and(|name| shared.iter().any(|s| s.name().eq(name)));
+        }
+        if !changed { return Ok(()) }
+    }
+
+    precompute_packs(dir).await?;
+    Ok(())
+}
+
+/// Generates packs for current tips of shared branches
+///
+/// Pack of a branch is the one peers ask for: all objects reachable from its tip, see
+/// `ut_gittorrent.rs`. Tips which did not change since last time are already in the cache.
+pub async fn precompute_packs(dir: &PathBuf) -> GtrResult<Vec<PathBuf>> {
+    let refs = shared_refs(dir).await?;
+    let cache = PackCache::open(dir).await?;
+
+    let mut packs = Vec::new();
+    for tip in refs {
+        packs.push(cache.get_or_create(&[*tip.id()], &[], &mut |_| {}).await?.path);
+    }
+
+    Ok(packs)
+}
+
+fn hook_script(hook: &str, gtr: &Path) -> String {
+    let gtr = shell_quote(&gtr.to_string_lossy());
+    let chain = match hook {
+        // input is read once and passed to both gtr and the original hook
+        "reference-transaction" => format!(
+            "refs=$(cat)\nif [ \"$1\" = committed ]; then\n    printf '%s\\n' \"$refs\" | {gtr} hook {hook} \"$@\" >/dev/null 2>&1 &\nfi\n\
+            if [ -x \"$original\" ]; then\n    printf '%s\\n' \"$refs\" | \"$original\" \"$@\"\nfi\n"
+        ),
+        _ => format!(
+            "{gtr} hook {hook} \"$@\" >/dev/null 2>&1 &\nif [ -x \"$original\" ]; then\n    exec \"$original\" \"$@\"\nfi\n"
+        ),
+    };
+    format!("#!/bin/sh\n{MARKER}\noriginal=\"$0{ORIGINAL_SUFFIX}\"\n{chain}")
+}
+
+/// Quotes string for sh, single quotes inside it are closed, escaped and reopened
+fn shell_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "'\\''"))
+}
+
+async fn write_executable(path: &PathBuf, script: &str) -> std::io::Result<()> {
+    fs::write(path, script).await?;
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(path, std::fs::Permissions::from_mode(0o755)).await?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn quotes_gtr_path_in_hook() {
+        assert_eq!(shell_quote("/opt/it's gtr"), "'/opt/it'\\''s gtr'");
+
+        let script = hook_script("post-commit", &PathBuf::from("/opt/it's gtr"));
+        assert!(script.starts_with(&format!("#!/bin/sh\n{MARKER}\n")));
+        assert!(script.contains("'/opt/it'\\''s gtr' hook post-commit"));
+        assert!(script.contains("exec \"$original\" \"$@\""));
+    }
+}
@@ -0,0 +1,617 @@
+pub mod hooks;
+#[cfg(feature = "native")]
+pub mod native;
+pub mod pack_cache;
+pub mod pkt_line;
+pub mod protocol_v2;
+pub mod refs;
+
+use std::collections::HashSet;
+use tokio::io::{AsyncReadExt, BufWriter, ErrorKind, AsyncWriteExt};
+use tokio::process::{Command, Child, ChildStdin, ChildStdout};
+use std::process::Stdio;
+use std::str;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use tokio::fs::{File, OpenOptions, remove_file, rename};
+use std::path::{Path, PathBuf};
+use regex::Regex;
+
+use crate::utils::error::{GtrResult, GitError, ProtocolError};
+use pack_cache::{CachedPack, PackCache};
+use pkt_line::{demux, Band, Packet, PktReader, PktWriter};
+use refs::{ObjectFormat, ObjectId, Ref};
+#[cfg(not(feature = "native"))]
+use refs::parse_ls_remote;
+
+pub(crate) const SETTINGS_DIR: &str = ".gtr";
+// Pack is requested multiplexed so that progress and errors do not get mixed with its content
+const CAPABILITIES: [&str; 4] = ["multi_ack_detailed", "side-band-64k", "thin-pack", "ofs-delta"];
+pub(crate) const AGENT: &str = "agent=gtr";
+// Number of haves sent before waiting for server acknowledgements
+const HAVES_PER_ROUND: usize = 32;
+
+/// Checks if directory is a git repository, adds service folder to gitignore
+///
+/// Optionally installs git hooks precomputing pack files of shared branches on each change.
+pub async fn gtr_setup(dir: &Path, install_hooks: bool) -> GtrResult<()>{
+    if !is_git(dir) { return Err(GitError::not_git_repo(dir)) };
+
+    ignore(dir, SETTINGS_DIR).await?;
+    if install_hooks { hooks::install(dir).await?; }
+    Ok(())
+}
+
+/// Selects only existing branches
+pub async fn select_exsiting_branches(dir: &str, branches: &Vec<&String>) -> GtrResult<Vec<String>> {
+    let requested: HashSet<String> = branches
+        .iter()
+        .map(|s| String::from("refs/heads/") + s)
+        .collect();
+    // Let server filter refs instead of listing all of them
+    let prefixes: Vec<String> = requested.iter().cloned().collect();
+    let availalbe: HashSet<String> = ls_refs(&PathBuf::from(dir), &prefixes).await?
+        .iter()
+        .map(|r| String::from(r.name()))
+        .collect();
+    Ok(availalbe.intersection(&requested).map(String::from).collect())
+}
+
+/// Lists references starting with any of the given prefixes, all of them if there are none
+#[cfg(not(feature = "native"))]
+pub async fn ls_refs(dir: &Path, prefixes: &[String]) -> GtrResult<Vec<Ref>> {
+    let mut upload_pack = protocol_v2::UploadPack::start(dir).await?;
+    let refs = upload_pack.ls_refs(prefixes).await?;
+    upload_pack.close().await?;
+    Ok(refs)
+}
+
+#[cfg(feature = "native")]
+pub use native::ls_refs;
+
+/// Hash algorithm used by given repository
+#[cfg(not(feature = "native"))]
+pub async fn object_format(dir: &Path) -> GtrResult<ObjectFormat> {
+    match run_git(dir, &["rev-parse", "--show-object-format"], Stdio::null(), None).await {
+        Ok(format) => format.trim_end().parse(),
+        Err(e) => Err(GitError::command_failed(e.into())),
+    }
+}
+
+#[cfg(feature = "native")]
+pub use native::object_format;
+
+/// Returns all refs of given repository including HEAD with the branch it points to
+#[cfg(feature = "native")]
+pub async fn ls_remote(dir: &str) -> GtrResult<Vec<Ref>> {
+    native::ls_refs(&PathBuf::from(dir), &[]).await
+}
+
+/// Returns all refs of given repository including HEAD with the branch it points to
+#[cfg(not(feature = "native"))]
+pub async fn ls_remote(dir: &str) -> GtrResult<Vec<Ref>> {
+    let output = match git_command().arg("ls-remote").arg("--symref").arg(dir).output().await {
+        Ok(output) => output,
+        Err(e) => { return Err(GitError::command_failed(Box::new(e))) }
+    };
+    if !output.status.success() {
+        return Err(GitError::command_failed(String::from_utf8_lossy(&output.stderr).trim_end().into()))
+    }
+
+    parse_ls_remote(&String::from_utf8_lossy(&output.stdout))
+}
+
+/// Generates pack file with objects reachable from `wants` which are not reachable from `haves`
+///
+/// Pack file is taken from or stored to the pack cache in `.gtr/packs/`, it is kept there while
+/// the returned pack is held. It is negotiated over protocol v2 when git-upload-pack supports it,
+/// v0 otherwise. Progress reported by git is passed to `progress` as is.
+pub async fn upload_pack(
+    dir: &PathBuf,
+    wants: &[ObjectId],
+    haves: &[ObjectId],
+    progress: &mut (impl FnMut(&str) + Send)) -> GtrResult<CachedPack>
+{
+    let cache = PackCache::open(dir).await?;
+    cache.get_or_create(wants, haves, progress).await
+}
+
+/// Applies pack received from a peer to local repository
+///
+/// Pack (possibly thin) is indexed into repository object storage, objects of advertised refs are
+/// checked to be fully connected and branches are stored as `refs/remotes/<peer>/<branch>`.
+/// All advertised `refs` are checked for connectivity but only branches are stored.
+pub async fn ingest_pack(dir: &Path, pack_path: &Path, peer: &str, refs: &[Ref]) -> GtrResult<()> {
+    if !is_git(dir) { return Err(GitError::not_git_repo(dir)) };
+
+    #[cfg(feature = "native")]
+    return native::ingest_pack(dir, pack_path, peer, refs).await;
+
+    #[cfg(not(feature = "native"))]
+    return ingest_pack_with_git(dir, pack_path, peer, refs).await;
+}
+
+#[cfg(not(feature = "native"))]
+async fn ingest_pack_with_git(dir: &Path, pack_path: &Path, peer: &str, refs: &[Ref]) -> GtrResult<()> {
+    store_pack(dir, pack_path).await?;
+
+    // the same check git does after fetch: all objects reachable from new tips must be present
+    let tips: String = refs.iter().map(|r| format!("{}\n", r.id())).collect();
+    if !tips.is_empty() {
+        let rev_list = ["rev-list", "--objects", "--stdin", "--not", "--all", "--quiet"];
+        if let Err(e) = run_git(dir, &rev_list, Stdio::piped(), Some(tips)).await {
+            return Err(GitError::pack_ingest_failed(&format!("pack is not connected: {e}")))
+        }
+    }
+
+    let updates: String = refs
+        .iter()
+        .filter_map(|r| r.branch().map(|branch| format!("update refs/remotes/{peer}/{branch} {}\n", r.id())))
+        .collect();
+    if updates.is_empty() { return Ok(()) }
+
+    // all refs are updated in a single transaction
+    if let Err(e) = run_git(dir, &["update-ref", "--stdin"], Stdio::piped(), Some(updates)).await {
+        return Err(GitError::pack_ingest_failed(&format!("can not update refs: {e}")))
+    }
+
+    Ok(())
+}
+
+/// Stores pack in object database of repository (its work tree or git dir), completing it if thin
+///
+/// Refs are not updated, objects are only kept by git once something references them.
+#[cfg(not(feature = "native"))]
+pub async fn store_pack(dir: &Path, pack_path: &Path) -> GtrResult<()> {
+    let pack = match std::fs::File::open(pack_path) {
+        Ok(pack) => pack,
+        Err(e) => return Err(GitError::pack_read_failed(Box::new(e))),
+    };
+    match run_git(dir, &["index-pack", "--stdin", "--fix-thin"], Stdio::from(pack), None).await {
+        Ok(_) => Ok(()),
+        Err(e) => Err(GitError::pack_ingest_failed(&e)),
+    }
+}
+
+#[cfg(feature = "native")]
+pub use native::store_pack;
+
+/// git command finding the repository from its working directory or arguments
+///
+/// git runs remote helpers and hooks with GIT_DIR set, possibly relative to another directory
+/// than the repository gtr runs git in, so it is not passed on.
+pub(crate) fn git_command() -> Command {
+    let mut command = Command::new("git");
+    command.env_remove("GIT_DIR");
+    command
+}
+
+/// Runs git command in repository, returns its stdout or stderr if it failed
+pub(crate) async fn run_git(dir: &Path, args: &[&str], stdin: Stdio, input: Option<String>) -> Result<String, String> {
+    let mut child = match git_command()
+        .current_dir(dir)
+        .args(args)
+        .stdin(stdin)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn() {
+            Ok(child) => child,
+            Err(e) => return Err(format!("{e}")),
+        };
+
+    if let (Some(mut child_stdin), Some(input)) = (child.stdin.take(), input) {
+        if let Err(e) = child_stdin.write_all(input.as_bytes()).await {
+            return Err(format!("{e}"))
+        }
+    }
+
+    match child.wait_with_output().await {
+        Ok(output) if output.status.success() => Ok(String::from_utf8_lossy(&output.stdout).to_string()),
+        Ok(output) => Err(String::from_utf8_lossy(&output.stderr).trim_end().to_string()),
+        Err(e) => Err(format!("{e}")),
+    }
+}
+
+/// Runs protocol v0 negotiation with git-upload-pack and stores received pack at `pack_path`
+#[cfg_attr(feature = "native", allow(dead_code))]
+pub(crate) async fn generate_pack(
+    dir: &Path,
+    wants: &[ObjectId],
+    haves: &[ObjectId],
+    pack_path: &PathBuf,
+    progress: &mut (impl FnMut(&str) + Send)) -> GtrResult<()>
+{
+    if wants.is_empty() {
+        return Err(ProtocolError::pkt_malformed("upload-pack requires at least one want"))
+    }
+    let mut pack_upload = start_pack_upload_process(dir, 0).await?;
+
+    let stdin = pack_upload.stdin.take().unwrap();
+    let stdout = pack_upload.stdout.take().unwrap();
+
+    let mut reader = PktReader::new(stdout);
+    let mut writer = PktWriter::new(stdin);
+    request_pack_file(&mut reader, &mut writer, wants, haves).await?;
+    write_pack_file(pack_path, &mut reader, progress).await?;
+
+    exited(&mut pack_upload).await
+}
+
+/// Waits for git process to exit, fails unless it succeeded
+pub(crate) async fn exited(process: &mut Child) -> GtrResult<()> {
+    match process.wait().await {
+        Ok(status) if status.success() => Ok(()),
+        Ok(status) => Err(GitError::command_failed(format!("git-upload-pack {status}").into())),
+        Err(e) => Err(GitError::command_failed(Box::new(e))),
+    }
+}
+
+/// Start protocol v0 git-upload-pack server advertising only `shared` branches
+///
+/// Other refs are hidden, and HEAD too unless it points to a shared branch, so peers can not
+/// want objects which are not reachable from shared branches.
+#[cfg(feature = "torrent")]
+pub(crate) async fn start_shared_upload_process(dir: &Path, shared: &[Ref]) -> GtrResult<Child> {
+    let git_dir = absolute_git_dir(dir).await?;
+    let head = run_git(dir, &["symbolic-ref", "-q", "HEAD"], Stdio::null(), None).await.unwrap_or_default();
+
+    let mut command = git_command();
+    for hidden in ["refs/", "HEAD"] {
+        command.args(["-c", &format!("uploadpack.hideRefs={hidden}")]);
+    }
+    for r in shared {
+        command.args(["-c", &format!("uploadpack.hideRefs=!{}", r.name())]);
+    }
+    if shared.iter().any(|r| r.name() == head.trim_end()) {
+        command.args(["-c", "uploadpack.hideRefs=!HEAD"]);
+    }
+    for allowed in ["allowTipSHA1InWant", "allowReachableSHA1InWant", "allowAnySHA1InWant"] {
+        command.args(["-c", &format!("uploadpack.{allowed}=false")]);
+    }
+    match command
+        .args(["upload-pack", "--strict"])
+        .arg(git_dir)
+        .env_remove("GIT_PROTOCOL")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn() {
+            Ok(res) => Ok(res),
+            Err(e) => Err(GitError::command_failed(Box::new(e)))
+        }
+}
+
+async fn absolute_git_dir(dir: &Path) -> GtrResult<PathBuf> {
+    match run_git(dir, &["rev-parse", "--absolute-git-dir"], Stdio::null(), None).await {
+        Ok(git_dir) => Ok(PathBuf::from(git_dir.trim_end())),
+        Err(e) => Err(GitError::command_failed(e.into())),
+    }
+}
+
+/// Start git-upload-pack server speaking given protocol version
+///
+/// `dir` is either a work tree or a bare repository.
+pub(crate) async fn start_pack_upload_process(dir: &Path, version: u8) -> GtrResult<Child> {
+    let git_dir = absolute_git_dir(dir).await?;
+    match Command::new("git-upload-pack")
+        .env_remove("GIT_DIR")
+        .arg("--strict")
+        .env("GIT_PROTOCOL", format!("version={version}"))
+        .arg(git_dir)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn() {
+            Ok(res) => Ok(res),
+            Err(e) => Err(GitError::command_failed(Box::new(e)))
+        }
+}
+
+
+/// Store pack file to fs
+///
+/// Pack data is streamed into a temporary file which is renamed to `pack_path` only once the
+/// whole pack is received and synced to disk, so a pack file is either complete or absent.
+pub(crate) async fn write_pack_file(
+    pack_path: &PathBuf,
+    reader: &mut PktReader<ChildStdout>,
+    progress: &mut (impl FnMut(&str) + Send)) -> GtrResult<()>
+{
+    let tmp_path = temporary_path(pack_path);
+
+    if let Err(e) = stream_pack(&tmp_path, reader, progress).await {
+        let _ = remove_file(&tmp_path).await;
+        return Err(e)
+    }
+
+    match rename(&tmp_path, pack_path).await {
+        Ok(_) => Ok(()),
+        Err(e) => Err(GitError::pack_write_failed(Box::new(e))),
+    }
+}
+
+/// Unique hidden path next to `path` to write its content to before renaming it
+///
+/// Several hooks, requests of peers or threads of one process may generate the same pack at once.
+pub(crate) fn temporary_path(path: &Path) -> PathBuf {
+    static TEMPORARY: AtomicUsize = AtomicUsize::new(0);
+    let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+    let count = TEMPORARY.fetch_add(1, Ordering::Relaxed);
+    path.with_file_name(format!(".{file_name}.{}.{count}.tmp", std::process::id()))
+}
+
+/// Demultiplex side-band pack stream into file at given path
+async fn stream_pack(
+    path: &PathBuf,
+    reader: &mut PktReader<ChildStdout>,
+    progress: &mut (impl FnMut(&str) + Send)) -> GtrResult<()>
+{
+    let mut file = match OpenOptions::new().write(true).create_new(true).open(path).await {
+        Ok(file) => BufWriter::new(file),
+        Err(e) => return Err(GitError::pack_write_failed(Box::new(e))),
+    };
+
+    loop {
+        match reader.expect_packet().await? {
+            Packet::Flush => break,
+            Packet::Data(payload) => match demux(&payload)? {
+                Band::Data(data) => if let Err(e) = file.write_all(data).await {
+                    return Err(GitError::pack_write_failed(Box::new(e)))
+                },
+                Band::Progress(message) => progress(&String::from_utf8_lossy(message)),
+                Band::Error(e) => return Err(ProtocolError::remote_failed(&String::from_utf8_lossy(e))),
+            },
+            packet => return Err(ProtocolError::pkt_malformed(&format!("unexpected {packet:?} in pack stream"))),
+        }
+    }
+
+    if let Err(e) = file.flush().await {
+        return Err(GitError::pack_write_failed(Box::new(e)))
+    }
+    if let Err(e) = file.get_ref().sync_all().await {
+        return Err(GitError::pack_write_failed(Box::new(e)))
+    }
+
+    Ok(())
+}
+
+/// Talk to git-upload-pack until it is ready to send pack files
+// NOTE: https://github.com/git/git/blob/b594c975c7e865be23477989d7f36157ad437dc7/Documentation/technical/pack-protocol.txt#L346-L393
+// NOTE: this is worth reading: https://github.com/git/git/blob/ebba6c0ca617352ceef5caa636ab243f0ef14cc3/Documentation/technical/pack-heuristics.txt
+async fn request_pack_file(
+    reader: &mut PktReader<ChildStdout>,
+    writer: &mut PktWriter<ChildStdin>,
+    wants: &[ObjectId],
+    haves: &[ObjectId]) -> GtrResult<()>
+{
+    // We do not need to check git server refs as we know them from ls
+    let (advertisement, _) = reader.read_section().await?;
+    let server_capabilities = advertised_capabilities(&advertisement)?;
+    if let Some(missing) = CAPABILITIES.iter().find(|c| !server_capabilities.contains(&String::from(**c))) {
+        return Err(ProtocolError::remote_failed(&format!("upload-pack does not support {missing}")))
+    }
+
+    // server without object-format capability only knows SHA-1
+    let format = match server_capabilities.iter().find_map(|c| c.strip_prefix("object-format=")) {
+        Some(format) => format.parse()?,
+        None => ObjectFormat::Sha1,
+    };
+    check_object_format(format, wants.iter().chain(haves))?;
+    let mut capabilities: Vec<String> = CAPABILITIES.iter().map(|c| String::from(*c)).collect();
+    capabilities.push(format!("object-format={format}"));
+
+    write_wants(wants, &capabilities, writer).await?;
+
+    // With multi_ack_detailed server answers each flush with ACKs of common objects and NAK.
+    // Once it has enough common objects to produce minimal pack it also sends ACK ready.
+    for round in haves.chunks(HAVES_PER_ROUND) {
+        for have in round {
+            writer.write_text(&format!("have {have}")).await?;
+        }
+        writer.write_flush().await?;
+        writer.flush().await?;
+
+        if read_acknowledgements(reader).await? { break }
+    }
+
+    writer.write_text("done").await?;
+    writer.flush().await?;
+
+    // Final ACK of the last common object or NAK precedes side-band pack stream
+    loop {
+        let line = match reader.peek_packet().await? {
+            Some(packet) => packet.as_text().map(String::from),
+            None => return Err(ProtocolError::pkt_malformed("upload-pack closed during negotiation")),
+        };
+
+        match line.as_deref().map(parse_acknowledgement) {
+            Some(Acknowledgement::Error(e)) => return Err(ProtocolError::remote_failed(&e)),
+            Some(Acknowledgement::Nak) | Some(Acknowledgement::Ack(_, _)) => { reader.read_packet().await?; },
+            _ => return Ok(()),
+        }
+    }
+}
+
+/// Response of git pack server for a negotiation round
+#[derive(Debug, PartialEq)]
+enum Acknowledgement {
+    /// `ACK <sha> [continue|common|ready]`
+    Ack(String, Option<String>),
+    Nak,
+    Error(String),
+    Other,
+}
+
+fn parse_acknowledgement(line: &str) -> Acknowledgement {
+    if line.eq("NAK") { return Acknowledgement::Nak }
+    if let Some(e) = line.strip_prefix("ERR ") { return Acknowledgement::Error(String::from(e)) }
+
+    let ack_regex = Regex::new("^ACK ([0-9a-f]+)(?: (continue|common|ready))?$").unwrap();
+    match ack_regex.captures(line) {
+        Some(c) => Acknowledgement::Ack(String::from(&c[1]), c.get(2).map(|s| String::from(s.as_str()))),
+        None => Acknowledgement::Other,
+    }
+}
+
+/// Reads server response for a round of haves up to NAK, returns true if server is ready to send pack
+async fn read_acknowledgements(reader: &mut PktReader<ChildStdout>) -> GtrResult<bool> {
+    let mut ready = false;
+    loop {
+        let packet = reader.expect_packet().await?;
+        match packet.as_text().map(parse_acknowledgement) {
+            Some(Acknowledgement::Nak) => return Ok(ready),
+            Some(Acknowledgement::Ack(_, Some(status))) => ready |= status.eq("ready"),
+            Some(Acknowledgement::Error(e)) => return Err(ProtocolError::remote_failed(&e)),
+            _ => return Err(ProtocolError::pkt_malformed(&format!("unexpected {packet:?} during negotiation"))),
+        }
+    }
+}
+
+/// Capabilities listed after NUL in the first line of reference advertisement
+fn advertised_capabilities(advertisement: &[Vec<u8>]) -> GtrResult<Vec<String>> {
+    let first = match advertisement.first() {
+        Some(line) => String::from_utf8_lossy(line),
+        None => return Err(ProtocolError::pkt_malformed("empty reference advertisement")),
+    };
+    if let Some(e) = first.strip_prefix("ERR ") { return Err(ProtocolError::remote_failed(e)) }
+
+    Ok(first
+        .split_once('\0')
+        .map(|(_, c)| c.split_whitespace().map(String::from).collect())
+        .unwrap_or_default())
+}
+
+/// Checks that requested objects are named with the hash algorithm of repository
+pub(crate) fn check_object_format<'a>(format: ObjectFormat, ids: impl Iterator<Item = &'a ObjectId>) -> GtrResult<()> {
+    match ids.map(ObjectId::format).find(|f| !f.eq(&format)) {
+        Some(other) => Err(ProtocolError::remote_failed(&format!("repository uses {format}, requested {other} objects"))),
+        None => Ok(()),
+    }
+}
+
+/// Request wanted objects, capabilities are sent along with the first want
+async fn write_wants(wants: &[ObjectId], capabilities: &[String], writer: &mut PktWriter<ChildStdin>) -> GtrResult<()> {
+    for (i, want) in wants.iter().enumerate() {
+        if i == 0 {
+            writer.write_text(&format!("want {} {} {}", want, capabilities.join(" "), AGENT)).await?;
+        } else {
+            writer.write_text(&format!("want {want}")).await?;
+        }
+    }
+    writer.write_flush().await
+}
+
+/// Add .gtr directory to gitignore in provided repository
+async fn ignore(dir: &Path, to_ignore: &str) -> GtrResult<()> {
+    let gitignore_path = dir.join(".gitignore");
+    match File::open(&gitignore_path).await {
+        Ok(mut file) => {
+            let mut data = String::new();
+            file.read_to_string(&mut data).await.expect("Can not read file content");
+
+            let gtr_ignored = data.split("\n").into_iter().any(|s| to_ignore.eq(s));
+            if !gtr_ignored {
+                store_in_gitignore(&gitignore_path, to_ignore).await?;
+            }
+
+            Ok(())
+        },
+        Err(e) => match e.kind() {
+            ErrorKind::NotFound => {
+                store_in_gitignore(&gitignore_path, to_ignore).await?;
+                Ok(())
+            },
+            _ => Err(GitError::ignore_failed(Box::new(e)))
+        }
+    }
+}
+
+/// Add gtr related files to gitignore
+async fn store_in_gitignore(gitignore_path: &PathBuf, to_ignore: &str) -> GtrResult<()>{
+    match OpenOptions::new().write(true).append(true).open(gitignore_path).await {
+        Ok(mut file) => file.write_all((String::from("\n") + to_ignore).as_bytes()).await.unwrap(),
+        Err(e) => match e.kind() {
+            ErrorKind::NotFound => {
+                let mut file = File::create(&gitignore_path).await.unwrap();
+                OpenOptions::new().write(true).append(true).open(gitignore_path).await.unwrap();
+                file.write_all((String::from("\n") + to_ignore).as_bytes()).await.unwrap();
+            },
+            _ => return Err(GitError::ignore_failed(Box::new(e)))
+        }
+    }
+
+    Ok(())
+}
+/// Checks if provided directory is a git repository, either a work tree or a bare one
+#[cfg(not(feature = "native"))]
+fn is_git(dir: &Path) -> bool {
+    // the same layout git itself looks for when checking a bare repository
+    let bare = dir.join("HEAD").is_file() && dir.join("objects").is_dir() && dir.join("refs").is_dir();
+    dir.join(".git").exists() || bare
+}
+
+#[cfg(feature = "native")]
+use native::is_git;
+
+// 
+// /// Clones git repo to bare repo with the same name + .git
+// /// assumes that provided path points to git repo
+// async fn clone_bare(dir: &PathBuf) -> GtrResult<String> {
+//     // TODO:
+//     // get dir name from path which can be either absolute or relative
+//     // git  clone --bare ${dir} ${dir}.git
+// }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_negotiation_responses() {
+        let sha = "66ef7ea67c18d2341afb8c1521afbab31014e62f";
+        assert_eq!(parse_acknowledgement("NAK"), Acknowledgement::Nak);
+        assert_eq!(parse_acknowledgement(&format!("ACK {sha}")), Acknowledgement::Ack(String::from(sha), None));
+        assert_eq!(
+            parse_acknowledgement(&format!("ACK {sha} ready")),
+            Acknowledgement::Ack(String::from(sha), Some(String::from("ready")))
+        );
+        assert_eq!(parse_acknowledgement("ERR oops"), Acknowledgement::Error(String::from("oops")));
+        assert_eq!(parse_acknowledgement("\x01PACK"), Acknowledgement::Other);
+    }
+
+    #[tokio::test]
+    async fn negotiates_haves_in_rounds() {
+        let dir = std::env::temp_dir().join(format!("gtr-negotiation-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let git = |args: &[&str]| git_command().current_dir(&dir).args(args).output();
+        assert!(git(&["init", "-q"]).await.unwrap().status.success());
+        let mut commits = Vec::new();
+        for file in ["first", "second"] {
+            std::fs::write(dir.join(file), file).unwrap();
+            assert!(git(&["add", file]).await.unwrap().status.success());
+            assert!(git(&["-c", "user.name=gtr", "-c", "user.email=gtr@localhost", "commit", "-q", "-m", file]).await.unwrap().status.success());
+            let head = git(&["rev-parse", "HEAD"]).await.unwrap().stdout;
+            commits.push(String::from_utf8(head).unwrap().trim().parse::<ObjectId>().unwrap());
+        }
+
+        // objects unknown to the server fill the first round, the common one is sent in the next
+        let mut haves: Vec<ObjectId> = (1..=HAVES_PER_ROUND + 8).map(|i| format!("{i:040x}").parse().unwrap()).collect();
+        haves.push(commits[0]);
+        let pack_path = dir.join("received.pack");
+        generate_pack(&dir, &commits[1..], &haves, &pack_path, &mut |_| {}).await.unwrap();
+
+        // only the second commit, its tree and blob are sent
+        let pack = std::fs::read(&pack_path).unwrap();
+        assert_eq!(&pack[..4], b"PACK");
+        assert_eq!(u32::from_be_bytes(pack[8..12].try_into().unwrap()), 3);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn reads_capabilities_from_advertisement() {
+        let advertisement = vec![
+            b"66ef7ea67c18d2341afb8c1521afbab31014e62f HEAD\0multi_ack_detailed side-band-64k\n".to_vec(),
+        ];
+        let capabilities = advertised_capabilities(&advertisement).unwrap();
+        assert_eq!(capabilities, vec!["multi_ack_detailed", "side-band-64k"]);
+    }
+}
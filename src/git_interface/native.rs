@@ -0,0 +1,302 @@
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use git2::{ObjectType, Odb, Oid, PackBuilderStage, Repository, TreeWalkMode, TreeWalkResult};
+use tokio::fs::{remove_file, rename};
+use tokio::sync::mpsc::{unbounded_channel, UnboundedSender};
+
+use crate::git_interface::refs::{ObjectFormat, ObjectId, Ref};
+use crate::git_interface::temporary_path;
+use crate::utils::error::{GtrResult, GitError};
+
+// NOTE: in-process implementation of git operations on top of libgit2, it is used instead of
+// spawning git binaries when built with `native` feature. libgit2 does not support SHA-256
+// repositories yet, opening them fails.
+
+/// Checks if provided directory is a git repository
+pub fn is_git(dir: &Path) -> bool {
+    Repository::open(dir).is_ok()
+}
+
+/// Hash algorithm used by given repository
+pub async fn object_format(dir: &Path) -> GtrResult<ObjectFormat> {
+    let dir = dir.to_path_buf();
+    let format = blocking(move || {
+        let repo = Repository::open(&dir)?;
+        let config = repo.config()?;
+        Ok(config.get_string("extensions.objectformat").unwrap_or(String::from("sha1")))
+    }).await;
+
+    match format {
+        Ok(format) => format.parse(),
+        Err(e) => Err(GitError::command_failed(e.into())),
+    }
+}
+
+/// Lists references starting with any of the given prefixes, all of them if there are none
+///
+/// HEAD goes first and annotated tags are peeled, the same as in `git ls-remote` output.
+pub async fn ls_refs(dir: &Path, prefixes: &[String]) -> GtrResult<Vec<Ref>> {
+    let dir = dir.to_path_buf();
+    let prefixes = prefixes.to_vec();
+    match blocking(move || list_refs(&dir, &prefixes)).await {
+        Ok(refs) => Ok(refs),
+        Err(e) => Err(GitError::command_failed(e.into())),
+    }
+}
+
+/// Builds pack with objects reachable from `wants` which are not reachable from `haves`
+///
+/// Unlike pack sent by git-upload-pack it is never thin. Progress of counting and compressing
+/// objects is passed to `progress` in the same form git reports it.
+pub async fn generate_pack(
+    dir: &Path,
+    wants: &[ObjectId],
+    haves: &[ObjectId],
+    pack_path: &PathBuf,
+    progress: &mut (impl FnMut(&str) + Send)) -> GtrResult<()>
+{
+    if wants.is_empty() {
+        return Err(GitError::pack_write_failed("pack requires at least one want".into()))
+    }
+
+    let tmp_path = temporary_path(pack_path);
+
+    let (sender, mut receiver) = unbounded_channel();
+    let (dir, wants, haves, path) = (dir.to_path_buf(), wants.to_vec(), haves.to_vec(), tmp_path.clone());
+    let built = tokio::spawn(blocking(move || build_pack(&dir, &wants, &haves, &path, sender)));
+    while let Some(message) = receiver.recv().await {
+        progress(&message);
+    }
+
+    match built.await {
+        Ok(Ok(())) => {},
+        Ok(Err(e)) => {
+            let _ = remove_file(&tmp_path).await;
+            return Err(GitError::pack_write_failed(e.into()))
+        },
+        Err(e) => return Err(GitError::pack_write_failed(Box::new(e))),
+    }
+
+    match rename(&tmp_path, pack_path).await {
+        Ok(_) => Ok(()),
+        Err(e) => Err(GitError::pack_write_failed(Box::new(e))),
+    }
+}
+
+/// Indexes `received` pack completing it if thin, stores it with its index as `pack_path`
+pub async fn index_pack(dir: &Path, received: &Path, pack_path: &Path) -> GtrResult<()> {
+    let (dir, received, pack_path) = (dir.to_path_buf(), received.to_path_buf(), pack_path.to_path_buf());
+    let indexed = blocking(move || {
+        let repo = Repository::open(&dir)?;
+        let odb = repo.odb()?;
+        let packs_dir = pack_path.parent().unwrap_or(Path::new("."));
+        let mut indexer = git2::Indexer::new(Some(&odb), packs_dir, 0o444, true)?;
+        copy(&received, &mut indexer)?;
+        // indexer names files by pack checksum
+        let name = format!("pack-{}", indexer.commit()?);
+        std::fs::rename(packs_dir.join(format!("{name}.idx")), pack_path.with_extension("idx")).map_err(io_error)?;
+        std::fs::rename(packs_dir.join(format!("{name}.pack")), &pack_path).map_err(io_error)?;
+        Ok(())
+    }).await;
+
+    match indexed {
+        Ok(()) => Ok(()),
+        Err(e) => Err(GitError::pack_index_failed(&e)),
+    }
+}
+
+/// Stores pack in repository object database completing it if thin
+pub async fn store_pack(dir: &Path, pack_path: &Path) -> GtrResult<()> {
+    let (dir, pack_path) = (dir.to_path_buf(), pack_path.to_path_buf());
+    let stored = blocking(move || {
+        let repo = Repository::open(&dir)?;
+        let odb = repo.odb()?;
+        write_to_odb(&odb, &pack_path)
+    }).await;
+
+    match stored {
+        Ok(()) => Ok(()),
+        Err(e) => Err(GitError::pack_ingest_failed(&e)),
+    }
+}
+
+/// Stores pack in repository object database, checks connectivity of `refs` and stores branches
+/// as `refs/remotes/<peer>/<branch>`, see `git_interface::ingest_pack`
+pub async fn ingest_pack(dir: &Path, pack_path: &Path, peer: &str, refs: &[Ref]) -> GtrResult<()> {
+    let (dir, pack_path, peer, refs) = (dir.to_path_buf(), pack_path.to_path_buf(), String::from(peer), refs.to_vec());
+    let ingested = blocking(move || {
+        let repo = Repository::open(&dir)?;
+        let odb = repo.odb()?;
+        write_to_odb(&odb, &pack_path)?;
+
+        check_connectivity(&repo, &odb, &refs)?;
+
+        // all refs are updated in a single transaction
+        let branches: Vec<(String, Oid)> = refs
+            .iter()
+            .filter_map(|r| r.branch().map(|branch| (format!("refs/remotes/{peer}/{branch}"), r.id())))
+            .map(|(name, id)| oid(id).map(|id| (name, id)))
+            .collect::<Result<_, _>>()?;
+        if branches.is_empty() { return Ok(()) }
+
+        let mut transaction = repo.transaction()?;
+        for (name, id) in &branches {
+            transaction.lock_ref(name)?;
+            transaction.set_target(name, *id, None, &format!("gtr: ingest pack from {peer}"))?;
+        }
+        transaction.commit()?;
+        Ok(())
+    }).await;
+
+    match ingested {
+        Ok(()) => Ok(()),
+        Err(e) => Err(GitError::pack_ingest_failed(&e)),
+    }
+}
+
+/// Runs libgit2 calls on blocking thread pool, errors are reported as messages
+async fn blocking<T, F>(f: F) -> Result<T, String>
+where
+    T: Send + 'static,
+    F: FnOnce() -> Result<T, git2::Error> + Send + 'static,
+{
+    match tokio::task::spawn_blocking(f).await {
+        Ok(Ok(result)) => Ok(result),
+        Ok(Err(e)) => Err(String::from(e.message())),
+        Err(e) => Err(format!("{e}")),
+    }
+}
+
+fn list_refs(dir: &PathBuf, prefixes: &[String]) -> Result<Vec<Ref>, git2::Error> {
+    let repo = Repository::open(dir)?;
+    let matches = |name: &str| prefixes.is_empty() || prefixes.iter().any(|p| name.starts_with(p.as_str()));
+
+    let mut refs = Vec::new();
+    let head = repo.find_reference("HEAD").into_iter();
+    for reference in head.map(Ok).chain(repo.references()?) {
+        let reference = reference?;
+        let name = match reference.name() {
+            Some(name) if matches(name) => String::from(name),
+            _ => continue,
+        };
+        // symbolic ref to unborn branch, e.g. HEAD of empty repository
+        let id = match reference.resolve().ok().and_then(|r| r.target()) {
+            Some(id) => id,
+            None => continue,
+        };
+
+        let peeled = match repo.find_object(id, None)?.kind() {
+            Some(ObjectType::Tag) => Some(object_id(reference.peel(ObjectType::Any)?.id())?),
+            _ => None,
+        };
+        refs.push(Ref::new(&name, object_id(id)?, reference.symbolic_target(), peeled));
+    }
+
+    Ok(refs)
+}
+
+fn build_pack(
+    dir: &PathBuf,
+    wants: &[ObjectId],
+    haves: &[ObjectId],
+    pack_path: &PathBuf,
+    progress: UnboundedSender<String>) -> Result<(), git2::Error>
+{
+    let repo = Repository::open(dir)?;
+    let odb = repo.odb()?;
+
+    let mut walk = repo.revwalk()?;
+    let mut tags = Vec::new();
+    for want in wants {
+        let want = oid(want)?;
+        if odb.read_header(want)?.1 == ObjectType::Tag { tags.push(want); }
+        walk.push(want)?;
+    }
+    // peer may know objects this repository does not have, they can not be excluded anyway
+    for have in haves {
+        let have = oid(have)?;
+        if odb.exists(have) { walk.hide(have)?; }
+    }
+
+    let mut builder = repo.packbuilder()?;
+    builder.set_progress_callback(move |stage, current, total| {
+        let stage = match stage {
+            PackBuilderStage::AddingObjects => "Counting objects",
+            PackBuilderStage::Deltafication => "Compressing objects",
+        };
+        // number of objects to count is not known in advance
+        let message = match total {
+            0 => format!("{stage}: {current}\r"),
+            _ => format!("{stage}: {current}/{total}\r"),
+        };
+        progress.send(message).is_ok()
+    })?;
+    builder.insert_walk(&mut walk)?;
+    for tag in tags {
+        builder.insert_object(tag, None)?;
+    }
+
+    let mut file = std::fs::File::create_new(pack_path).map_err(io_error)?;
+    let mut written = Ok(());
+    builder.foreach(|chunk| {
+        written = file.write_all(chunk);
+        written.is_ok()
+    })?;
+    written.map_err(io_error)?;
+    file.sync_all().map_err(io_error)
+}
+
+fn write_to_odb(odb: &Odb, pack_path: &PathBuf) -> Result<(), git2::Error> {
+    let mut writer = odb.packwriter()?;
+    copy(pack_path, &mut writer)?;
+    writer.commit()?;
+    Ok(())
+}
+
+/// The same check git does after fetch: all objects reachable from new tips must be present
+fn check_connectivity(repo: &Repository, odb: &Odb, refs: &[Ref]) -> Result<(), git2::Error> {
+    let mut walk = repo.revwalk()?;
+    for r in refs {
+        let id = oid(r.id())?;
+        if !odb.exists(id) { return Err(git2::Error::from_str(&format!("bad object {id}"))) }
+        if odb.read_header(id)?.1 == ObjectType::Commit { walk.push(id)?; }
+    }
+    // objects reachable from existing refs were checked when those refs were updated
+    walk.hide_glob("*")?;
+
+    for commit in walk {
+        let tree = repo.find_commit(commit?)?.tree()?;
+        let mut missing = None;
+        tree.walk(TreeWalkMode::PreOrder, |_, entry| {
+            // submodule commits are not part of this repository
+            if entry.kind() == Some(ObjectType::Commit) || odb.exists(entry.id()) {
+                return TreeWalkResult::Ok
+            }
+            missing = Some(entry.id());
+            TreeWalkResult::Abort
+        })?;
+        if let Some(id) = missing {
+            return Err(git2::Error::from_str(&format!("missing object {id}")))
+        }
+    }
+
+    Ok(())
+}
+
+fn copy(path: &PathBuf, writer: &mut impl Write) -> Result<(), git2::Error> {
+    let mut file = std::fs::File::open(path).map_err(io_error)?;
+    std::io::copy(&mut file, writer).map_err(io_error)?;
+    Ok(())
+}
+
+fn oid(id: &ObjectId) -> Result<Oid, git2::Error> {
+    Oid::from_bytes(id.as_bytes())
+}
+
+fn object_id(id: Oid) -> Result<ObjectId, git2::Error> {
+    ObjectId::from_bytes(id.as_bytes()).map_err(|e| git2::Error::from_str(&e.to_string()))
+}
+
+fn io_error(e: std::io::Error) -> git2::Error {
+    git2::Error::from_str(&e.to_string())
+}
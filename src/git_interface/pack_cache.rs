@@ -0,0 +1,481 @@
+use std::collections::{BTreeSet, HashMap};
+use std::fs::TryLockError;
+use std::io::{Read, Seek, SeekFrom};
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime};
+use sha1::{Digest, Sha1};
+use tokio::fs;
+
+use crate::config::config_file::{self, Packs};
+#[cfg(not(feature = "native"))]
+use crate::git_interface::protocol_v2::UploadPack;
+use crate::git_interface::refs::{ObjectFormat, ObjectId};
+use crate::git_interface::SETTINGS_DIR;
+#[cfg(not(feature = "native"))]
+use crate::git_interface::{generate_pack, git_command};
+#[cfg(not(feature = "native"))]
+use std::process::Stdio;
+#[cfg(feature = "native")]
+use crate::git_interface::native;
+use crate::utils::error::{GtrResult, GitError};
+
+const PACKS_DIR: &str = "packs";
+const LOCK_RETRY: Duration = Duration::from_millis(100);
+const LOCK_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Cache of generated pack files stored in `.gtr/packs`
+///
+/// Pack is identified by the set of wanted and the set of known objects, so repeated requests for
+/// the same branch tip are served without running git-upload-pack again. Each cached pack has its
+/// index generated by `git index-pack`, file name of both is the cache key.
+///
+/// Every key has a lock file (`<key>.lock`): readers of its pack share the lock, creating and
+/// evicting the pack take it exclusively.
+pub struct PackCache {
+    dir: PathBuf,
+    packs_dir: PathBuf,
+    limits: Packs,
+}
+
+impl PackCache {
+    /// Opens cache of given repository creating its directory if needed
+    pub async fn open(dir: &PathBuf) -> GtrResult<Self> {
+        let dir = match fs::canonicalize(dir).await {
+            Ok(dir) => dir,
+            Err(e) => return Err(GitError::pack_cache_failed(Box::new(e))),
+        };
+        let conf = config_file::read_or_create(&dir).await?;
+        let packs_dir = dir.join(SETTINGS_DIR).join(PACKS_DIR);
+        if let Err(e) = fs::create_dir_all(&packs_dir).await {
+            return Err(GitError::pack_cache_failed(Box::new(e)))
+        }
+
+        Ok(PackCache { dir, packs_dir, limits: conf.packs })
+    }
+
+    /// Cache key, it does not depend on order or duplicates of wants and haves
+    pub fn key(wants: &[ObjectId], haves: &[ObjectId]) -> String {
+        let wants: BTreeSet<&ObjectId> = wants.iter().collect();
+        let haves: BTreeSet<&ObjectId> = haves.iter().collect();
+
+        let mut hasher = Sha1::new();
+        for want in wants { hasher.update(format!("want {want}\n")); }
+        for have in haves { hasher.update(format!("have {have}\n")); }
+        format!("{:x}", hasher.finalize())
+    }
+
+    pub fn packs_dir(&self) -> &PathBuf {
+        &self.packs_dir
+    }
+
+    pub fn pack_path(&self, key: &str) -> PathBuf {
+        self.packs_dir.join(format!("{key}.pack"))
+    }
+
+    pub fn index_path(&self, key: &str) -> PathBuf {
+        self.packs_dir.join(format!("{key}.idx"))
+    }
+
+    fn lock_path(&self, key: &str) -> PathBuf {
+        self.packs_dir.join(format!("{key}.lock"))
+    }
+
+    /// Returns path of cached pack without generating it
+    pub fn get(&self, wants: &[ObjectId], haves: &[ObjectId]) -> Option<PathBuf> {
+        self.lookup(&PackCache::key(wants, haves), format(wants))
+    }
+
+    /// Returns cached pack, generating it with git-upload-pack if it is not cached yet
+    pub async fn get_or_create(
+        &self,
+        wants: &[ObjectId],
+        haves: &[ObjectId],
+        progress: &mut (impl FnMut(&str) + Send)) -> GtrResult<CachedPack>
+    {
+        let key = PackCache::key(wants, haves);
+        let started = Instant::now();
+        loop {
+            // identical requests, also from other processes (e.g. hooks), wait here for the one
+            // creating the pack instead of generating the same pack
+            let lock = self.lock(&key).await?;
+            if let Some(path) = self.lookup(&key, format(wants)) {
+                self.evict().await?;
+                return Ok(CachedPack { path, _lock: lock })
+            }
+            drop(lock);
+
+            // waiting for exclusive lock while holding the shared one elsewhere would deadlock
+            match self.try_lock(&key)? {
+                Some(lock) => {
+                    if self.lookup(&key, format(wants)).is_none() {
+                        self.create(&key, wants, haves, progress).await?;
+                    }
+                    drop(lock);
+                },
+                // readers of corrupted pack hold the lock
+                None if started.elapsed() > LOCK_TIMEOUT => {
+                    return Err(GitError::pack_cache_failed(format!("pack {key} is corrupted and in use").into()))
+                },
+                None => tokio::time::sleep(LOCK_RETRY).await,
+            }
+        }
+    }
+
+    /// Generates pack, completes it if thin and indexes it
+    ///
+    /// Indexing verifies the pack checksum and the object hashes, so a corrupted pack fails here.
+    async fn create(
+        &self,
+        key: &str,
+        wants: &[ObjectId],
+        haves: &[ObjectId],
+        progress: &mut (impl FnMut(&str) + Send)) -> GtrResult<PathBuf>
+    {
+        let received = self.packs_dir.join(format!("{key}.{}.thin", std::process::id()));
+        self.generate(&received, wants, haves, progress).await?;
+
+        // index-pack refuses to overwrite, files of corrupted pack or leftovers of interrupted run
+        // are removed first
+        let pack_path = self.pack_path(key);
+        for path in [&pack_path, &self.index_path(key), &self.packs_dir.join(format!("{key}.rev"))] {
+            let _ = fs::remove_file(path).await;
+        }
+        let indexed = self.index(&received, &pack_path).await;
+        let _ = fs::remove_file(&received).await;
+        indexed?;
+
+        Ok(pack_path)
+    }
+
+    /// Receives pack from git-upload-pack, over protocol v2 if it offers `fetch` and v0 otherwise
+    #[cfg(not(feature = "native"))]
+    async fn generate(
+        &self,
+        received: &PathBuf,
+        wants: &[ObjectId],
+        haves: &[ObjectId],
+        progress: &mut (impl FnMut(&str) + Send)) -> GtrResult<()>
+    {
+        // NOTE: git older than 2.18 ignores GIT_PROTOCOL and answers with v0 advertisement
+        match UploadPack::start(&self.dir).await {
+            Ok(mut upload_pack) if upload_pack.supports("fetch") => {
+                upload_pack.fetch(received, wants, haves, progress).await?;
+                upload_pack.close().await
+            },
+            Ok(upload_pack) => {
+                upload_pack.close().await?;
+                generate_pack(&self.dir, wants, haves, received, progress).await
+            },
+            Err(_) => generate_pack(&self.dir, wants, haves, received, progress).await,
+        }
+    }
+
+    /// Builds pack in-process, there is no protocol to speak
+    #[cfg(feature = "native")]
+    async fn generate(
+        &self,
+        received: &PathBuf,
+        wants: &[ObjectId],
+        haves: &[ObjectId],
+        progress: &mut (impl FnMut(&str) + Send)) -> GtrResult<()>
+    {
+        native::generate_pack(&self.dir, wants, haves, received, progress).await
+    }
+
+    /// Indexes pack in-process with libgit2
+    #[cfg(feature = "native")]
+    async fn index(&self, received: &Path, pack_path: &Path) -> GtrResult<()> {
+        native::index_pack(&self.dir, received, pack_path).await
+    }
+
+    /// Runs `git index-pack` which adds missing bases of thin pack from repository and writes
+    /// completed pack with its index
+    #[cfg(not(feature = "native"))]
+    async fn index(&self, received: &Path, pack_path: &Path) -> GtrResult<()> {
+        let input = match std::fs::File::open(received) {
+            Ok(file) => file,
+            Err(e) => return Err(GitError::pack_cache_failed(Box::new(e))),
+        };
+
+        let output = git_command()
+            .current_dir(&self.dir)
+            .arg("index-pack")
+            .arg("--stdin")
+            .arg("--fix-thin")
+            .arg(pack_path)
+            .stdin(Stdio::from(input))
+            .output()
+            .await;
+
+        match output {
+            Ok(output) if output.status.success() => Ok(()),
+            Ok(output) => Err(GitError::pack_index_failed(&String::from_utf8_lossy(&output.stderr))),
+            Err(e) => Err(GitError::command_failed(Box::new(e))),
+        }
+    }
+
+    /// Returns pack path if both pack and index exist and the pack is intact, marking pack as
+    /// recently used
+    fn lookup(&self, key: &str, format: ObjectFormat) -> Option<PathBuf> {
+        let pack_path = self.pack_path(key);
+        if !self.intact(key, format).unwrap_or(false) { return None }
+
+        // modification time is used as last use time by eviction
+        if let Ok(file) = std::fs::File::open(&pack_path) {
+            let _ = file.set_modified(SystemTime::now());
+        }
+
+        Some(pack_path)
+    }
+
+    /// Pack starts with its signature and ends with the checksum its index was made for
+    ///
+    /// Indexing verified the whole pack, truncated or replaced pack is caught without hashing it
+    /// again.
+    fn intact(&self, key: &str, format: ObjectFormat) -> std::io::Result<bool> {
+        let hash_len = format.hash_len();
+        let mut pack = std::fs::File::open(self.pack_path(key))?;
+        let mut index = std::fs::File::open(self.index_path(key))?;
+        // header of pack is 12 bytes, index ends with checksums of the pack and of itself
+        if pack.metadata()?.len() < 12 + hash_len as u64 || index.metadata()?.len() < 2 * hash_len as u64 {
+            return Ok(false)
+        }
+
+        let mut signature = [0; 4];
+        pack.read_exact(&mut signature)?;
+        let mut checksum = vec![0; hash_len];
+        pack.seek(SeekFrom::End(-(hash_len as i64)))?;
+        pack.read_exact(&mut checksum)?;
+        let mut indexed = vec![0; hash_len];
+        index.seek(SeekFrom::End(-2 * hash_len as i64))?;
+        index.read_exact(&mut indexed)?;
+
+        Ok(signature.eq(b"PACK") && checksum == indexed)
+    }
+
+    /// Removes packs unused for longer than configured age, then least recently used packs while
+    /// cache exceeds configured size, and leftovers of keys without pack (e.g. lock files)
+    ///
+    /// Packs of locked keys are being read or created, they are skipped.
+    async fn evict(&self) -> GtrResult<()> {
+        let mut entries = match fs::read_dir(&self.packs_dir).await {
+            Ok(entries) => entries,
+            Err(e) => return Err(GitError::pack_cache_failed(Box::new(e))),
+        };
+
+        // key -> (its files but the lock, their size, last use)
+        let mut packs: HashMap<String, (Vec<PathBuf>, u64, Option<SystemTime>)> = HashMap::new();
+        while let Ok(Some(entry)) = entries.next_entry().await {
+            let name = entry.file_name().to_string_lossy().to_string();
+            let (key, extension) = match name.split_once('.') {
+                Some((key, extension)) if !key.is_empty() => (String::from(key), String::from(extension)),
+                _ => continue,
+            };
+            let pack = packs.entry(key).or_insert((Vec::new(), 0, None));
+            if extension.eq("lock") { continue }
+            let metadata = match entry.metadata().await {
+                Ok(metadata) => metadata,
+                Err(_) => continue,
+            };
+
+            pack.0.push(entry.path());
+            pack.1 += metadata.len();
+            if extension.eq("pack") { pack.2 = metadata.modified().ok(); }
+        }
+
+        let mut total: u64 = packs.values().map(|(_, size, _)| size).sum();
+        let (mut by_use, leftovers): (Vec<_>, Vec<_>) = packs.into_iter().partition(|(_, (_, _, used))| used.is_some());
+        by_use.sort_by_key(|(_, (_, _, used))| *used);
+
+        let now = SystemTime::now();
+        let max_age = Duration::from_secs(self.limits.max_age);
+        for (key, (files, size, used)) in by_use {
+            let expired = used.is_some_and(|used| now.duration_since(used).unwrap_or_default() > max_age);
+            if (expired || total > self.limits.max_size) && self.try_remove(&key, &files).await? {
+                total -= size;
+            }
+        }
+        for (key, (files, _, _)) in leftovers {
+            self.try_remove(&key, &files).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Removes files of key unless it is locked, the lock file goes last while it is held
+    ///
+    /// Processes which opened the lock file before find out it is removed once they get it, see
+    /// `lock`.
+    async fn try_remove(&self, key: &str, files: &[PathBuf]) -> GtrResult<bool> {
+        let lock = match self.try_lock(key)? {
+            Some(lock) => lock,
+            None => return Ok(false),
+        };
+        for path in files.iter().chain([&self.lock_path(key)]) {
+            match fs::remove_file(path).await {
+                Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+                    return Err(GitError::pack_cache_failed(Box::new(e)))
+                },
+                _ => continue,
+            }
+        }
+        drop(lock);
+        Ok(true)
+    }
+
+    /// Takes shared lock of the key for reading its pack, it is released when returned file is
+    /// dropped
+    async fn lock(&self, key: &str) -> GtrResult<std::fs::File> {
+        let lock_path = self.lock_path(key);
+        loop {
+            let file = self.open_lock(&lock_path)?;
+            let locked = tokio::task::spawn_blocking(move || file.lock_shared().map(|_| file)).await;
+            let file = match locked {
+                Ok(Ok(file)) => file,
+                Ok(Err(e)) => return Err(GitError::pack_cache_failed(Box::new(e))),
+                Err(e) => return Err(GitError::pack_cache_failed(Box::new(e))),
+            };
+            // eviction removed the lock file while this process waited for it
+            if is_same_file(&file, &lock_path) {
+                return Ok(file)
+            }
+        }
+    }
+
+    /// Takes exclusive lock of the key for creating or removing its pack, if nobody holds it
+    fn try_lock(&self, key: &str) -> GtrResult<Option<std::fs::File>> {
+        let lock_path = self.lock_path(key);
+        let file = self.open_lock(&lock_path)?;
+        match file.try_lock() {
+            Ok(()) if is_same_file(&file, &lock_path) => Ok(Some(file)),
+            Ok(()) | Err(TryLockError::WouldBlock) => Ok(None),
+            Err(TryLockError::Error(e)) => Err(GitError::pack_cache_failed(Box::new(e))),
+        }
+    }
+
+    fn open_lock(&self, lock_path: &Path) -> GtrResult<std::fs::File> {
+        match std::fs::File::options().create(true).truncate(false).write(true).open(lock_path) {
+            Ok(file) => Ok(file),
+            Err(e) => Err(GitError::pack_cache_failed(Box::new(e))),
+        }
+    }
+}
+
+/// Pack of the cache, it is not evicted while this is held
+pub struct CachedPack {
+    pub path: PathBuf,
+    // shared lock of its key
+    _lock: std::fs::File,
+}
+
+/// Object format of pack with given wants, it decides length of checksums
+fn format(wants: &[ObjectId]) -> ObjectFormat {
+    wants.first().map(ObjectId::format).unwrap_or(ObjectFormat::Sha1)
+}
+
+/// Whether locked file is still the one at `path`
+fn is_same_file(file: &std::fs::File, path: &Path) -> bool {
+    match (file.metadata(), std::fs::metadata(path)) {
+        (Ok(locked), Ok(current)) => locked.dev() == current.dev() && locked.ino() == current.ino(),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::git_interface::git_command;
+
+    #[test]
+    fn key_ignores_order_and_duplicates() {
+        let a: ObjectId = "66ef7ea67c18d2341afb8c1521afbab31014e62f".parse().unwrap();
+        let b: ObjectId = "da13823f7206ed470cdab7c98285cd706ae1dcbe".parse().unwrap();
+
+        assert_eq!(PackCache::key(&[a, b], &[b]), PackCache::key(&[b, a, a], &[b, b]));
+        assert_ne!(PackCache::key(&[a], &[b]), PackCache::key(&[b], &[a]));
+        assert_ne!(PackCache::key(&[a, b], &[]), PackCache::key(&[a], &[b]));
+    }
+
+    /// Repository with one commit, returns its directory and the commit
+    async fn repository(name: &str) -> (PathBuf, ObjectId) {
+        let dir = std::env::temp_dir().join(format!("gtr-pack-cache-{name}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let git = |args: &'static [&'static str]| git_command().current_dir(&dir).args(args).output();
+        assert!(git(&["init", "-q"]).await.unwrap().status.success());
+        assert!(git(&["-c", "user.name=gtr", "-c", "user.email=gtr@localhost", "commit", "-q", "--allow-empty", "-m", "packed"]).await.unwrap().status.success());
+        let head = git(&["rev-parse", "HEAD"]).await.unwrap().stdout;
+        (dir, String::from_utf8(head).unwrap().trim().parse().unwrap())
+    }
+
+    #[tokio::test]
+    async fn evicts_least_recently_used_packs_which_are_not_read() {
+        let dir = std::env::temp_dir().join(format!("gtr-pack-cache-evict-{}", std::process::id()));
+        let packs_dir = dir.join(PACKS_DIR);
+        std::fs::create_dir_all(&packs_dir).unwrap();
+        let cache = PackCache { dir: dir.clone(), packs_dir, limits: Packs { max_size: 250, max_age: 3600 } };
+
+        let now = SystemTime::now();
+        for (key, age) in [("old", 30), ("older", 20), ("new", 10)] {
+            std::fs::write(cache.pack_path(key), [0; 100]).unwrap();
+            std::fs::write(cache.lock_path(key), []).unwrap();
+            let pack = std::fs::File::open(cache.pack_path(key)).unwrap();
+            pack.set_modified(now - Duration::from_secs(age)).unwrap();
+        }
+        // left by a process which failed to create its pack
+        std::fs::write(cache.lock_path("failed"), []).unwrap();
+
+        let reading = cache.lock("old").await.unwrap();
+        cache.evict().await.unwrap();
+        assert!(cache.pack_path("old").exists());
+        assert!(!cache.pack_path("older").exists() && !cache.lock_path("older").exists());
+        assert!(cache.pack_path("new").exists());
+        assert!(!cache.lock_path("failed").exists());
+
+        // under the size limit now
+        drop(reading);
+        cache.evict().await.unwrap();
+        assert!(cache.pack_path("old").exists());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn creates_pack_of_identical_requests_once() {
+        let (dir, head) = repository("dedupe").await;
+        let cache = PackCache::open(&dir).await.unwrap();
+
+        let (mut first, mut second) = (0, 0);
+        let (mut count_first, mut count_second) = (|_: &str| first += 1, |_: &str| second += 1);
+        let wants = [head];
+        let (a, b) = tokio::join!(
+            cache.get_or_create(&wants, &[], &mut count_first),
+            cache.get_or_create(&wants, &[], &mut count_second),
+        );
+        let (a, b) = (a.unwrap(), b.unwrap());
+        assert_eq!(a.path, b.path);
+        // only the request which created the pack got progress of git
+        assert!(first + second > 0 && (first == 0 || second == 0), "{first} {second}");
+
+        drop((a, b));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn replaces_corrupted_cached_pack() {
+        let (dir, head) = repository("corrupted").await;
+        let cache = PackCache::open(&dir).await.unwrap();
+        let pack = cache.get_or_create(&[head], &[], &mut |_| {}).await.unwrap();
+        let content = std::fs::read(&pack.path).unwrap();
+        drop(pack);
+
+        std::fs::write(cache.pack_path(&PackCache::key(&[head], &[])), &content[..content.len() - 1]).unwrap();
+        assert!(cache.get(&[head], &[]).is_none());
+        let pack = cache.get_or_create(&[head], &[], &mut |_| {}).await.unwrap();
+        assert_eq!(std::fs::read(&pack.path).unwrap(), content);
+
+        drop(pack);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
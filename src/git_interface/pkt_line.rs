@@ -0,0 +1,303 @@
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+use crate::utils::error::{GtrResult, ProtocolError};
+
+// NOTE: https://github.com/git/git/blob/master/Documentation/gitprotocol-common.txt#L47-L97
+// NOTE: https://github.com/git/git/blob/master/Documentation/gitprotocol-v2.txt#L33-L51
+
+/// Maximum length of a single pkt-line including its four bytes of length header
+pub const MAX_PKT_LEN: usize = 65520;
+/// Maximum length of pkt-line payload
+pub const MAX_DATA_LEN: usize = MAX_PKT_LEN - 4;
+
+const HEADER_LEN: usize = 4;
+
+/// Side-band channel carrying pack data
+pub const BAND_DATA: u8 = 1;
+/// Side-band channel carrying progress messages
+pub const BAND_PROGRESS: u8 = 2;
+/// Side-band channel carrying fatal error message
+pub const BAND_ERROR: u8 = 3;
+
+/// Single frame of git wire format
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Packet {
+    /// Length prefixed payload
+    Data(Vec<u8>),
+    /// `0000` - end of message
+    Flush,
+    /// `0001` - separates sections of a message (protocol v2)
+    Delim,
+    /// `0002` - end of response for stateless connections (protocol v2)
+    ResponseEnd,
+}
+
+impl Packet {
+    /// Returns payload as text without trailing new line, if packet is a text line
+    pub fn as_text(&self) -> Option<&str> {
+        match self {
+            Packet::Data(data) => {
+                let data = data.strip_suffix(b"\n").unwrap_or(data);
+                std::str::from_utf8(data).ok()
+            },
+            _ => None,
+        }
+    }
+
+    /// Returns payload of data packet
+    pub fn as_data(&self) -> Option<&[u8]> {
+        match self {
+            Packet::Data(data) => Some(data),
+            _ => None,
+        }
+    }
+}
+
+/// Message received over a side-band multiplexed stream
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Band<'a> {
+    /// Pack data (band 1)
+    Data(&'a [u8]),
+    /// Progress information meant for the user (band 2)
+    Progress(&'a [u8]),
+    /// Fatal error, the remote aborts after sending it (band 3)
+    Error(&'a [u8]),
+}
+
+/// Splits side-band payload into band and its content
+pub fn demux(payload: &[u8]) -> GtrResult<Band<'_>> {
+    match payload.split_first() {
+        Some((&BAND_DATA, rest)) => Ok(Band::Data(rest)),
+        Some((&BAND_PROGRESS, rest)) => Ok(Band::Progress(rest)),
+        Some((&BAND_ERROR, rest)) => Ok(Band::Error(rest)),
+        Some((band, _)) => Err(ProtocolError::pkt_malformed(&format!("unknown side-band {band}"))),
+        None => Err(ProtocolError::pkt_malformed("empty side-band packet")),
+    }
+}
+
+/// Reads pkt-line frames from underlying stream
+///
+/// Reads exactly as many bytes as frames announce, thus underlying stream can be taken back with
+/// `into_inner` and used for non pkt-line data.
+pub struct PktReader<R> {
+    inner: R,
+    peeked: Option<Packet>,
+}
+
+impl<R: AsyncRead + Unpin> PktReader<R> {
+    pub fn new(inner: R) -> Self {
+        PktReader { inner, peeked: None }
+    }
+
+    /// Reads next packet, returns `None` if stream ended on a frame boundary
+    pub async fn read_packet(&mut self) -> GtrResult<Option<Packet>> {
+        if let Some(packet) = self.peeked.take() {
+            return Ok(Some(packet));
+        }
+
+        let mut header = [0; HEADER_LEN];
+        let mut filled = 0;
+        while filled < HEADER_LEN {
+            match self.inner.read(&mut header[filled..]).await {
+                Ok(0) if filled == 0 => return Ok(None),
+                Ok(0) => return Err(ProtocolError::pkt_malformed("stream ended inside pkt-line header")),
+                Ok(n) => filled += n,
+                Err(e) => return Err(ProtocolError::pkt_read_failed(Box::new(e))),
+            }
+        }
+
+        let len = parse_len(&header)?;
+        let packet = match len {
+            0 => Packet::Flush,
+            1 => Packet::Delim,
+            2 => Packet::ResponseEnd,
+            3 => return Err(ProtocolError::pkt_malformed("reserved pkt-line length 0003")),
+            _ => {
+                let mut data = vec![0; len - HEADER_LEN];
+                if let Err(e) = self.inner.read_exact(&mut data).await {
+                    return Err(ProtocolError::pkt_read_failed(Box::new(e)));
+                }
+                Packet::Data(data)
+            }
+        };
+
+        Ok(Some(packet))
+    }
+
+    /// Reads next packet and fails if stream ended
+    pub async fn expect_packet(&mut self) -> GtrResult<Packet> {
+        match self.read_packet().await? {
+            Some(packet) => Ok(packet),
+            None => Err(ProtocolError::pkt_malformed("unexpected end of stream")),
+        }
+    }
+
+    /// Returns next packet without consuming it
+    pub async fn peek_packet(&mut self) -> GtrResult<Option<&Packet>> {
+        if self.peeked.is_none() {
+            self.peeked = self.read_packet().await?;
+        }
+        Ok(self.peeked.as_ref())
+    }
+
+    /// Reads data packets until flush-pkt, delim-pkt or response-end-pkt, which is consumed
+    pub async fn read_section(&mut self) -> GtrResult<(Vec<Vec<u8>>, Packet)> {
+        let mut section = Vec::new();
+        loop {
+            match self.expect_packet().await? {
+                Packet::Data(data) => section.push(data),
+                terminator => return Ok((section, terminator)),
+            }
+        }
+    }
+
+    pub fn get_mut(&mut self) -> &mut R {
+        &mut self.inner
+    }
+
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+}
+
+/// Writes pkt-line frames to underlying stream
+pub struct PktWriter<W> {
+    inner: W,
+}
+
+impl<W: AsyncWrite + Unpin> PktWriter<W> {
+    pub fn new(inner: W) -> Self {
+        PktWriter { inner }
+    }
+
+    /// Writes binary payload as a single frame
+    pub async fn write_data(&mut self, data: &[u8]) -> GtrResult<()> {
+        if data.is_empty() || data.len() > MAX_DATA_LEN {
+            return Err(ProtocolError::pkt_malformed(&format!("can not frame {} bytes", data.len())));
+        }
+        let header = format!("{:04x}", data.len() + HEADER_LEN);
+        self.write_raw(header.as_bytes()).await?;
+        self.write_raw(data).await
+    }
+
+    /// Writes text line, terminating it with new line as the protocol recommends
+    pub async fn write_text(&mut self, line: &str) -> GtrResult<()> {
+        let mut data = Vec::with_capacity(line.len() + 1);
+        data.extend_from_slice(line.as_bytes());
+        data.push(b'\n');
+        self.write_data(&data).await
+    }
+
+    /// Writes payload to a side-band channel splitting it into frames of maximum size
+    pub async fn write_band(&mut self, band: u8, data: &[u8]) -> GtrResult<()> {
+        for chunk in data.chunks(MAX_DATA_LEN - 1) {
+            let mut frame = Vec::with_capacity(chunk.len() + 1);
+            frame.push(band);
+            frame.extend_from_slice(chunk);
+            self.write_data(&frame).await?;
+        }
+        Ok(())
+    }
+
+    pub async fn write_flush(&mut self) -> GtrResult<()> {
+        self.write_raw(b"0000").await
+    }
+
+    pub async fn write_delim(&mut self) -> GtrResult<()> {
+        self.write_raw(b"0001").await
+    }
+
+    pub async fn write_response_end(&mut self) -> GtrResult<()> {
+        self.write_raw(b"0002").await
+    }
+
+    pub async fn write_packet(&mut self, packet: &Packet) -> GtrResult<()> {
+        match packet {
+            Packet::Data(data) => self.write_data(data).await,
+            Packet::Flush => self.write_flush().await,
+            Packet::Delim => self.write_delim().await,
+            Packet::ResponseEnd => self.write_response_end().await,
+        }
+    }
+
+    /// Flushes underlying stream, not to be confused with flush-pkt
+    pub async fn flush(&mut self) -> GtrResult<()> {
+        match self.inner.flush().await {
+            Ok(_) => Ok(()),
+            Err(e) => Err(ProtocolError::pkt_write_failed(Box::new(e))),
+        }
+    }
+
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.inner
+    }
+
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+
+    async fn write_raw(&mut self, bytes: &[u8]) -> GtrResult<()> {
+        match self.inner.write_all(bytes).await {
+            Ok(_) => Ok(()),
+            Err(e) => Err(ProtocolError::pkt_write_failed(Box::new(e))),
+        }
+    }
+}
+
+fn parse_len(header: &[u8; HEADER_LEN]) -> GtrResult<usize> {
+    let len = std::str::from_utf8(header)
+        .ok()
+        .and_then(|h| usize::from_str_radix(h, 16).ok());
+
+    match len {
+        Some(len) if len <= MAX_PKT_LEN => Ok(len),
+        _ => Err(ProtocolError::pkt_malformed(&format!("invalid pkt-line length {header:?}"))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn reads_frames_and_special_packets() {
+        let input: &[u8] = b"000ahello\n0005\x00000100020";
+        let mut reader = PktReader::new(input);
+
+        assert_eq!(reader.read_packet().await.unwrap().unwrap().as_text(), Some("hello"));
+        assert_eq!(reader.read_packet().await.unwrap(), Some(Packet::Data(vec![0])));
+        assert_eq!(reader.read_packet().await.unwrap(), Some(Packet::Delim));
+        assert_eq!(reader.read_packet().await.unwrap(), Some(Packet::ResponseEnd));
+        assert!(reader.read_packet().await.is_err());
+    }
+
+    #[tokio::test]
+    async fn writes_what_it_reads() {
+        let mut writer = PktWriter::new(Vec::new());
+        writer.write_text("want 66ef7ea67c18d2341afb8c1521afbab31014e62f").await.unwrap();
+        writer.write_delim().await.unwrap();
+        writer.write_band(BAND_PROGRESS, b"counting").await.unwrap();
+        writer.write_flush().await.unwrap();
+        let written = writer.into_inner();
+        assert!(written.starts_with(b"0032want 66ef"));
+
+        let mut reader = PktReader::new(written.as_slice());
+        let (section, end) = reader.read_section().await.unwrap();
+        assert_eq!(section.len(), 1);
+        assert_eq!(end, Packet::Delim);
+
+        let (section, end) = reader.read_section().await.unwrap();
+        assert_eq!(demux(&section[0]).unwrap(), Band::Progress(b"counting"));
+        assert_eq!(end, Packet::Flush);
+        assert_eq!(reader.read_packet().await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn rejects_malformed_length() {
+        let input: &[u8] = b"zzzz";
+        assert!(PktReader::new(input).read_packet().await.is_err());
+
+        let input: &[u8] = b"0003";
+        assert!(PktReader::new(input).read_packet().await.is_err());
+    }
+}
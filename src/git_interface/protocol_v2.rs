@@ -0,0 +1,169 @@
+use std::path::{Path, PathBuf};
+use tokio::process::{Child, ChildStdin, ChildStdout};
+
+use crate::config::config_file;
+use crate::git_interface::pkt_line::{Packet, PktReader, PktWriter};
+use crate::git_interface::refs::{parse_ls_refs_line, ObjectFormat, ObjectId, Ref};
+use crate::git_interface::{check_object_format, exited, ls_refs, start_pack_upload_process, write_pack_file, AGENT};
+use crate::utils::error::{GtrResult, ProtocolError};
+
+// NOTE: https://github.com/git/git/blob/master/Documentation/gitprotocol-v2.txt
+
+/// Features requested from `fetch` command
+///  - thin-pack: pack may reference objects the peer already has, it is fixed on ingestion
+///  - ofs-delta: deltas reference their bases by offset which makes packs smaller
+///  - include-tag: annotated tags pointing to sent objects are included
+const FETCH_ARGUMENTS: [&str; 3] = ["thin-pack", "ofs-delta", "include-tag"];
+
+/// Session with git-upload-pack speaking protocol version 2
+///
+/// Server advertises its capabilities once and then accepts any number of commands until its
+/// input is closed.
+pub struct UploadPack {
+    process: Child,
+    reader: PktReader<ChildStdout>,
+    writer: PktWriter<ChildStdin>,
+    capabilities: Vec<String>,
+    format: ObjectFormat,
+}
+
+impl UploadPack {
+    /// Starts git-upload-pack for given repository and reads its capability advertisement
+    pub async fn start(dir: &Path) -> GtrResult<Self> {
+        let mut process = start_pack_upload_process(dir, 2).await?;
+        let stdin = process.stdin.take().unwrap();
+        let stdout = process.stdout.take().unwrap();
+
+        let mut reader = PktReader::new(stdout);
+        let (advertisement, _) = reader.read_section().await?;
+        let capabilities: Vec<String> = advertisement
+            .iter()
+            .map(|l| String::from(String::from_utf8_lossy(l).trim_end()))
+            .collect();
+
+        match capabilities.first().map(String::as_str) {
+            Some("version 2") => {},
+            Some(line) => return Err(ProtocolError::pkt_malformed(&format!("expected protocol v2, got {line}"))),
+            None => return Err(ProtocolError::pkt_malformed("empty capability advertisement")),
+        }
+
+        // server without object-format capability only knows SHA-1
+        let format = match capabilities.iter().find_map(|c| c.strip_prefix("object-format=")) {
+            Some(format) => format.parse()?,
+            None => ObjectFormat::Sha1,
+        };
+
+        Ok(UploadPack { process, reader, writer: PktWriter::new(stdin), capabilities, format })
+    }
+
+    /// Checks if server advertised given capability (e.g. `ls-refs` or `fetch`)
+    pub fn supports(&self, capability: &str) -> bool {
+        self.capabilities
+            .iter()
+            .any(|c| c == capability || c.starts_with(&format!("{capability}=")))
+    }
+
+    /// Hash algorithm of served repository
+    pub fn object_format(&self) -> ObjectFormat {
+        self.format
+    }
+
+    /// Lists references starting with any of the given prefixes, all of them if there are none
+    ///
+    /// Symbolic refs come with their targets and annotated tags with objects they point to.
+    pub async fn ls_refs(&mut self, prefixes: &[String]) -> GtrResult<Vec<Ref>> {
+        let mut arguments = vec![String::from("symrefs"), String::from("peel")];
+        arguments.extend(prefixes.iter().map(|p| format!("ref-prefix {p}")));
+        self.command("ls-refs", &arguments).await?;
+
+        let (lines, _) = self.reader.read_section().await?;
+        lines
+            .iter()
+            .map(|line| parse_ls_refs_line(&String::from_utf8_lossy(line)))
+            .collect()
+    }
+
+    /// Requests pack containing `wants` but not `haves` and stores it at `pack_path`
+    pub async fn fetch(
+        &mut self,
+        pack_path: &PathBuf,
+        wants: &[ObjectId],
+        haves: &[ObjectId],
+        progress: &mut (impl FnMut(&str) + Send)) -> GtrResult<()>
+    {
+        if wants.is_empty() {
+            return Err(ProtocolError::pkt_malformed("fetch requires at least one want"))
+        }
+        check_object_format(self.format, wants.iter().chain(haves))?;
+
+        let mut arguments: Vec<String> = FETCH_ARGUMENTS.iter().map(|a| String::from(*a)).collect();
+        arguments.extend(wants.iter().map(|w| format!("want {w}")));
+        arguments.extend(haves.iter().map(|h| format!("have {h}")));
+        // Negotiation is done in single round, server sends pack right away
+        arguments.push(String::from("done"));
+        self.command("fetch", &arguments).await?;
+
+        // Sections preceding the pack (shallow-info, wanted-refs, ...) are not requested
+        loop {
+            let packet = self.reader.expect_packet().await?;
+            match packet.as_text() {
+                Some("packfile") => break,
+                Some(line) => if let Some(e) = line.strip_prefix("ERR ") {
+                    return Err(ProtocolError::remote_failed(e))
+                },
+                None => continue,
+            }
+        }
+
+        write_pack_file(pack_path, &mut self.reader, progress).await
+    }
+
+    /// Closes server input and waits for it to exit
+    pub async fn close(mut self) -> GtrResult<()> {
+        drop(self.writer);
+        exited(&mut self.process).await
+    }
+
+    /// Writes command request: command name and capabilities, delimiter, arguments, flush
+    async fn command(&mut self, command: &str, arguments: &[String]) -> GtrResult<()> {
+        if !self.supports(command) {
+            return Err(ProtocolError::remote_failed(&format!("server does not support {command}")));
+        }
+
+        self.writer.write_text(&format!("command={command}")).await?;
+        self.writer.write_text(AGENT).await?;
+        // server assumes SHA-1 unless told otherwise and rejects requests to SHA-256 repository
+        if self.supports("object-format") {
+            self.writer.write_text(&format!("object-format={}", self.format)).await?;
+        }
+        self.writer.write_delim().await?;
+        for argument in arguments {
+            self.writer.write_text(argument).await?;
+        }
+        self.writer.write_flush().await?;
+        self.writer.flush().await?;
+
+        if let Some(Packet::Data(line)) = self.reader.peek_packet().await? {
+            if let Some(e) = String::from_utf8_lossy(line).strip_prefix("ERR ") {
+                return Err(ProtocolError::remote_failed(e.trim_end()));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Lists only branches shared via `.gtr/config.toml`
+pub async fn shared_refs(dir: &Path) -> GtrResult<Vec<Ref>> {
+    let conf = config_file::read_or_create(dir).await?;
+    if conf.branches.is_empty() { return Ok(Vec::new()) }
+
+    let prefixes: Vec<String> = conf.branches.iter().map(|b| format!("refs/heads/{b}")).collect();
+    // prefix matching may return e.g. `refs/heads/master-old` for `refs/heads/master`
+    let refs = ls_refs(dir, &prefixes).await?
+        .into_iter()
+        .filter(|r| prefixes.iter().any(|p| p.eq(r.name())))
+        .collect();
+
+    Ok(refs)
+}
@@ -0,0 +1,293 @@
+use std::fmt;
+use std::str::FromStr;
+
+use crate::utils::error::{GtrError, GtrResult, GitError};
+use crate::utils::hex::hex;
+
+const SHA1_LEN: usize = 20;
+const SHA256_LEN: usize = 32;
+/// Length of DHT keys (infohashes, node ids)
+pub const DHT_KEY_LEN: usize = 20;
+
+/// Hash algorithm of repository objects, `git init --object-format=<format>`
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ObjectFormat {
+    Sha1,
+    Sha256,
+}
+
+impl ObjectFormat {
+    /// Name used by git in `object-format` capability and config
+    pub fn name(&self) -> &'static str {
+        match self {
+            ObjectFormat::Sha1 => "sha1",
+            ObjectFormat::Sha256 => "sha256",
+        }
+    }
+
+    /// Length of object id and of pack checksum in bytes
+    pub fn hash_len(&self) -> usize {
+        match self {
+            ObjectFormat::Sha1 => SHA1_LEN,
+            ObjectFormat::Sha256 => SHA256_LEN,
+        }
+    }
+}
+
+impl FromStr for ObjectFormat {
+    type Err = GtrError;
+
+    fn from_str(name: &str) -> GtrResult<Self> {
+        match name {
+            "sha1" => Ok(ObjectFormat::Sha1),
+            "sha256" => Ok(ObjectFormat::Sha256),
+            _ => Err(GitError::unsupported_object_format(name)),
+        }
+    }
+}
+
+impl fmt::Display for ObjectFormat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+/// Name of git object, SHA-1 or SHA-256 depending on repository object format
+#[derive(Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum ObjectId {
+    Sha1([u8; SHA1_LEN]),
+    Sha256([u8; SHA256_LEN]),
+}
+
+impl ObjectId {
+    pub fn from_bytes(bytes: &[u8]) -> GtrResult<Self> {
+        if let Ok(bytes) = <[u8; SHA1_LEN]>::try_from(bytes) { return Ok(ObjectId::Sha1(bytes)) }
+        if let Ok(bytes) = <[u8; SHA256_LEN]>::try_from(bytes) { return Ok(ObjectId::Sha256(bytes)) }
+        Err(GitError::invalid_object_id(&format!("{} bytes", bytes.len())))
+    }
+
+    pub fn format(&self) -> ObjectFormat {
+        match self {
+            ObjectId::Sha1(_) => ObjectFormat::Sha1,
+            ObjectId::Sha256(_) => ObjectFormat::Sha256,
+        }
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        match self {
+            ObjectId::Sha1(bytes) => bytes,
+            ObjectId::Sha256(bytes) => bytes,
+        }
+    }
+
+    pub fn to_hex(&self) -> String {
+        hex(self.as_bytes())
+    }
+
+    /// All zeros id used by git for missing objects, e.g. old value of created ref
+    pub fn is_null(&self) -> bool {
+        self.as_bytes().iter().all(|b| *b == 0)
+    }
+
+    /// Key of the object in DHT which only has 20 bytes keys
+    ///
+    /// SHA-256 ids are truncated to their first 20 bytes, the same way BitTorrent v2 (BEP 52)
+    /// maps its SHA-256 infohashes onto the DHT.
+    pub fn dht_key(&self) -> [u8; DHT_KEY_LEN] {
+        let mut key = [0; DHT_KEY_LEN];
+        key.copy_from_slice(&self.as_bytes()[..DHT_KEY_LEN]);
+        key
+    }
+}
+
+impl FromStr for ObjectId {
+    type Err = GtrError;
+
+    fn from_str(hex: &str) -> GtrResult<Self> {
+        // from_str_radix also accepts sign, e.g. `+f`
+        if !(hex.len() == SHA1_LEN * 2 || hex.len() == SHA256_LEN * 2) || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(GitError::invalid_object_id(hex))
+        }
+
+        let bytes: Option<Vec<u8>> = (0..hex.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).ok())
+            .collect();
+        match bytes {
+            Some(bytes) => ObjectId::from_bytes(&bytes),
+            None => Err(GitError::invalid_object_id(hex)),
+        }
+    }
+}
+
+impl fmt::Display for ObjectId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.to_hex())
+    }
+}
+
+impl fmt::Debug for ObjectId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "ObjectId({})", self.to_hex())
+    }
+}
+
+/// Git reference as advertised by ls-remote or ls-refs, names are always full (`refs/heads/...`)
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Ref {
+    /// `refs/heads/<branch>`
+    Branch { name: String, id: ObjectId },
+    /// `refs/tags/<tag>`, annotated tag is peeled to the object it points to (`<tag>^{}`)
+    Tag { name: String, id: ObjectId, peeled: Option<ObjectId> },
+    /// Reference pointing to another reference, e.g. `HEAD` to `refs/heads/master`
+    Symbolic { name: String, target: String, id: ObjectId },
+    /// Any other reference, e.g. `refs/notes/commits` or detached `HEAD`
+    Other { name: String, id: ObjectId },
+}
+
+impl Ref {
+    /// Classifies reference by its name
+    pub fn new(name: &str, id: ObjectId, symref_target: Option<&str>, peeled: Option<ObjectId>) -> Self {
+        let name = String::from(name);
+        if let Some(target) = symref_target {
+            return Ref::Symbolic { name, target: String::from(target), id }
+        }
+        if name.starts_with("refs/heads/") { return Ref::Branch { name, id } }
+        if name.starts_with("refs/tags/") { return Ref::Tag { name, id, peeled } }
+        Ref::Other { name, id }
+    }
+
+    pub fn name(&self) -> &str {
+        match self {
+            Ref::Branch { name, .. } | Ref::Tag { name, .. } | Ref::Symbolic { name, .. } | Ref::Other { name, .. } => name,
+        }
+    }
+
+    /// Object the reference points to, for annotated tag it is the tag object itself
+    pub fn id(&self) -> &ObjectId {
+        match self {
+            Ref::Branch { id, .. } | Ref::Tag { id, .. } | Ref::Symbolic { id, .. } | Ref::Other { id, .. } => id,
+        }
+    }
+
+    /// Branch name without `refs/heads/` prefix
+    pub fn branch(&self) -> Option<&str> {
+        match self {
+            Ref::Branch { name, .. } => name.strip_prefix("refs/heads/"),
+            _ => None,
+        }
+    }
+}
+
+/// Parses output of `git ls-remote --symref`, each line is `<oid> TAB <name>`
+///
+/// Symbolic ref is preceded by `ref: <target> TAB <name>` line and annotated tag is followed by
+/// `<oid> TAB <tag>^{}` line with the object it points to.
+pub fn parse_ls_remote(output: &str) -> GtrResult<Vec<Ref>> {
+    let mut refs: Vec<Ref> = Vec::new();
+    let mut symrefs: Vec<(String, String)> = Vec::new();
+
+    for line in output.lines().filter(|l| !l.is_empty()) {
+        let (value, name) = match line.split_once('\t') {
+            Some(parts) => parts,
+            None => return Err(GitError::malformed_ref(line)),
+        };
+
+        if let Some(target) = value.strip_prefix("ref: ") {
+            symrefs.push((String::from(name), String::from(target)));
+            continue;
+        }
+
+        let id: ObjectId = value.parse()?;
+        if let Some(tag) = name.strip_suffix("^{}") {
+            match refs.iter_mut().rev().find(|r| r.name().eq(tag)) {
+                Some(Ref::Tag { peeled, .. }) => *peeled = Some(id),
+                _ => return Err(GitError::malformed_ref(line)),
+            }
+            continue;
+        }
+
+        let target = symrefs.iter().find(|(symref, _)| symref.eq(name)).map(|(_, target)| target.as_str());
+        refs.push(Ref::new(name, id, target, None));
+    }
+
+    Ok(refs)
+}
+
+/// Parses line of protocol v2 `ls-refs` response
+///
+/// `<oid> <name>[ symref-target:<target>][ peeled:<oid>]`
+pub fn parse_ls_refs_line(line: &str) -> GtrResult<Ref> {
+    let mut parts = line.trim_end().split(' ');
+    let (id, name) = match (parts.next(), parts.next()) {
+        (Some(id), Some(name)) => (id.parse()?, name),
+        _ => return Err(GitError::malformed_ref(line)),
+    };
+
+    let mut symref_target = None;
+    let mut peeled = None;
+    for attribute in parts {
+        if let Some(target) = attribute.strip_prefix("symref-target:") {
+            symref_target = Some(target);
+        } else if let Some(id) = attribute.strip_prefix("peeled:") {
+            peeled = Some(id.parse()?);
+        }
+    }
+
+    Ok(Ref::new(name, id, symref_target, peeled))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MASTER: &str = "66ef7ea67c18d2341afb8c1521afbab31014e62f";
+    const TAG: &str = "da13823f7206ed470cdab7c98285cd706ae1dcbe";
+
+    #[test]
+    fn parses_and_formats_object_ids() {
+        let id: ObjectId = MASTER.parse().unwrap();
+        assert_eq!(id.to_string(), MASTER);
+        assert_eq!(id.as_bytes().len(), 20);
+
+        let sha256 = "a".repeat(64);
+        assert!(matches!(sha256.parse::<ObjectId>().unwrap(), ObjectId::Sha256(_)));
+
+        assert_eq!(id.dht_key(), id.as_bytes());
+        assert_eq!(sha256.parse::<ObjectId>().unwrap().dht_key(), [0xaa; DHT_KEY_LEN]);
+
+        assert!("66ef7ea".parse::<ObjectId>().is_err());
+        assert!("zz".repeat(20).parse::<ObjectId>().is_err());
+        assert!(format!("+{}", &MASTER[1..]).parse::<ObjectId>().is_err());
+    }
+
+    #[test]
+    fn parses_ls_remote_output() {
+        let output = format!(
+            "ref: refs/heads/master\tHEAD\n{MASTER}\tHEAD\n{MASTER}\trefs/heads/master\n{TAG}\trefs/tags/v1\n{MASTER}\trefs/tags/v1^{{}}\n"
+        );
+        let refs = parse_ls_remote(&output).unwrap();
+        let master: ObjectId = MASTER.parse().unwrap();
+
+        assert_eq!(refs.len(), 3);
+        assert_eq!(refs[0], Ref::Symbolic {
+            name: String::from("HEAD"), target: String::from("refs/heads/master"), id: master
+        });
+        assert_eq!(refs[1].branch(), Some("master"));
+        assert_eq!(refs[2], Ref::Tag {
+            name: String::from("refs/tags/v1"), id: TAG.parse().unwrap(), peeled: Some(master)
+        });
+
+        assert!(parse_ls_remote("garbage").is_err());
+        assert!(parse_ls_remote(&format!("{MASTER}\trefs/heads/x^{{}}")).is_err());
+    }
+
+    #[test]
+    fn parses_ls_refs_line() {
+        let head = parse_ls_refs_line(&format!("{MASTER} HEAD symref-target:refs/heads/master\n")).unwrap();
+        assert!(matches!(head, Ref::Symbolic { .. }));
+
+        let tag = parse_ls_refs_line(&format!("{TAG} refs/tags/v1 peeled:{MASTER}")).unwrap();
+        assert!(matches!(tag, Ref::Tag { peeled: Some(_), .. }));
+    }
+}
@@ -17,13 +17,17 @@ pub fn cli() -> Command {
         .value_delimiter(',')
         .action(ArgAction::Append);
 
+    let hooks_arg = arg!(--hooks "install git hooks precomputing pack files on each change");
+
     let init = Command::new("init")
         .about("create settings file and include master branch for sharing")
+        .arg(&hooks_arg)
         .arg(&path_arg);
 
     let share = Command::new("share")
         .about("create settings file if not exists and share branch")
         .arg(&branches_arg)
+        .arg(&hooks_arg)
         .arg(&path_arg);
 
     let list = Command::new("list")
@@ -35,16 +39,89 @@ pub fn cli() -> Command {
         .arg(&branches_arg)
         .arg(&path_arg);
 
+    let repos = Command::new("repos")
+        .about("list repositories shared by the user and served by gtd")
+        .arg(Arg::new("scan")
+            .long("scan")
+            .help("register repositories with shared branches under the directory first")
+            .value_parser(value_parser!(PathBuf)));
+
+    let daemon = Command::new("daemon")
+        .about("run gtd on login as systemd user service")
+        .subcommand_required(true)
+        .subcommand(Command::new("install")
+            .about("install, enable and start the service")
+            .arg(arg!(--socket "start the daemon on the first connection to its socket")))
+        .subcommand(Command::new("uninstall")
+            .about("stop, disable and remove the service"))
+        .subcommand(Command::new("status")
+            .about("report whether the daemon is installed and active"))
+        .subcommand(Command::new("reload")
+            .about("make running daemon serve repository with its current settings")
+            .arg(&path_arg))
+        .subcommand(Command::new("republish")
+            .about("make running daemon announce shared refs now")
+            .arg(Arg::new("path")
+                .short('p')
+                .long("path")
+                .help("Path to repository to announce, all served ones by default")
+                .value_parser(value_parser!(PathBuf))))
+        .subcommand(Command::new("stats")
+            .about("show peers and transferred data of running daemon"));
+
+    let identity = Command::new("identity")
+        .about("manage key identifying the user in addresses of published repositories")
+        .subcommand_required(true)
+        .subcommand(Command::new("new")
+            .about("generate new identity key")
+            .arg(arg!(--passphrase "encrypt the key with passphrase"))
+            .arg(arg!(--force "replace existing key, addresses of published repositories change")))
+        .subcommand(Command::new("show")
+            .about("show public key and its hash used in repository addresses"))
+        .subcommand(Command::new("export")
+            .about("print secret key as hex for backup or another device"))
+        .subcommand(Command::new("import")
+            .about("store secret key exported by `gtr identity export`")
+            .arg(arg!(file: [FILE] "file with the key, standard input by default")
+                .value_parser(value_parser!(PathBuf)))
+            .arg(arg!(--passphrase "encrypt the key with passphrase"))
+            .arg(arg!(--force "replace existing key, addresses of published repositories change")));
+
+    let contacts = Command::new("contacts")
+        .about("manage petnames of users, `torrent://<petname>/reponame`")
+        .subcommand_required(true)
+        .subcommand(Command::new("add")
+            .about("name user given by hex sha1 of repository addresses or public key")
+            .arg(arg!(name: <NAME>))
+            .arg(arg!(key: <KEY>)))
+        .subcommand(Command::new("remove")
+            .about("forget petname")
+            .arg(arg!(name: <NAME>)))
+        .subcommand(Command::new("rename")
+            .about("change petname")
+            .arg(arg!(name: <NAME>))
+            .arg(arg!(new_name: <NEW_NAME>)))
+        .subcommand(Command::new("list")
+            .about("list petnames with hashes of users"));
+
     let _pack = Command::new("pack")
         .about("ONLY FOR TESTING generate pack files")
-        .arg(arg!(want: [WANT]))
-        .arg(arg!(have: [HAVE]))
+        .arg(arg!(want: <WANT> "object to include into pack"))
+        .arg(arg!(have: [HAVE] "object known to the receiver"))
         .arg(&path_arg);
 
     let _setup = Command::new("setup")
         .about("ONLY FOR TESTING setup gtr")
         .arg(arg!(want: [WANT]))
         .arg(arg!(have: [HAVE]))
+        .arg(&hooks_arg)
+        .arg(&path_arg);
+
+    let hook = Command::new("hook")
+        .about("called by installed git hooks")
+        .hide(true)
+        .arg(arg!(name: <NAME>))
+        .arg(arg!(args: [ARGS]).num_args(0..).allow_hyphen_values(true))
         .arg(&path_arg);
 
     command!()
@@ -55,6 +132,11 @@ pub fn cli() -> Command {
         .subcommand(share)
         .subcommand(list)
         .subcommand(remove)
+        .subcommand(repos)
+        .subcommand(daemon)
+        .subcommand(identity)
+        .subcommand(contacts)
         .subcommand(_pack)
         .subcommand(_setup)
+        .subcommand(hook)
 }
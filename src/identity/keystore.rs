@@ -0,0 +1,245 @@
+use std::env;
+use std::io::{self, ErrorKind, IsTerminal};
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use chacha20poly1305::aead::{Aead, KeyInit};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use tokio::fs::{self, OpenOptions};
+use tokio::io::AsyncWriteExt;
+
+use crate::config::user_dirs::config_dir;
+use crate::identity::{Identity, SEED_LEN};
+use crate::utils::error::{GtrResult, IdentityError};
+
+// NOTE: the key is kept in `$XDG_CONFIG_HOME/gtr/identity.key` and, as ssh does, it is refused
+// when other users may read it. Unencrypted key is its 32 bytes seed, encrypted one is
+//   `gtrkey1\n` | scrypt log2(N) (1) | salt (16) | nonce (12) | public key (32) | sealed seed (48)
+// sealed with ChaCha20-Poly1305 under the key derived from passphrase with scrypt. Public key is
+// stored in clear, so that the identity can be shown without the passphrase.
+
+const IDENTITY_FILE: &str = "identity.key";
+const MAGIC: &[u8] = b"gtrkey1\n";
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 12;
+const TAG_LEN: usize = 16;
+// recommended for interactive logins, N = 2^15 and r = 8 take 32 MiB
+const LOG_N: u8 = 15;
+/// Passphrase of encrypted key for non-interactive use, e.g. by `gtd`
+pub const PASSPHRASE_VAR: &str = "GTR_PASSPHRASE";
+
+/// Public part of stored identity
+pub struct Stored {
+    pub public_key: [u8; 32],
+    pub encrypted: bool,
+}
+
+/// Location of the key, `$XDG_CONFIG_HOME/gtr/identity.key`
+pub fn identity_path() -> GtrResult<PathBuf> {
+    match config_dir() {
+        Some(dir) => Ok(dir.join(IDENTITY_FILE)),
+        None => Err(IdentityError::identity_failed("home directory is not known".into())),
+    }
+}
+
+/// Stored identity, generated and stored unencrypted if there is none yet
+pub async fn load_or_generate() -> GtrResult<Identity> {
+    if let Some(identity) = load().await? {
+        return Ok(identity)
+    }
+    let identity = Identity::generate()?;
+    store(&identity, None, false).await?;
+    Ok(identity)
+}
+
+/// Stored identity, the passphrase of encrypted one is asked for
+pub async fn load() -> GtrResult<Option<Identity>> {
+    let (path, data) = match read().await? {
+        Some(read) => read,
+        None => return Ok(None),
+    };
+    let seed = match data.strip_prefix(MAGIC) {
+        Some(sealed) => open(&path, sealed, &passphrase("Passphrase of gtr identity: ")?)?,
+        None => match <[u8; SEED_LEN]>::try_from(data.as_slice()) {
+            Ok(seed) => seed,
+            Err(_) => return Err(IdentityError::invalid_key(&path)),
+        },
+    };
+    Ok(Some(Identity::from_seed(&seed)))
+}
+
+/// Public key of stored identity, read without the passphrase
+pub async fn stored() -> GtrResult<Option<Stored>> {
+    let (path, data) = match read().await? {
+        Some(read) => read,
+        None => return Ok(None),
+    };
+    if let Some(sealed) = data.strip_prefix(MAGIC) {
+        let public_key = sealed.get(1 + SALT_LEN + NONCE_LEN..1 + SALT_LEN + NONCE_LEN + 32);
+        return match public_key.and_then(|key| key.try_into().ok()) {
+            Some(public_key) => Ok(Some(Stored { public_key, encrypted: true })),
+            None => Err(IdentityError::invalid_key(&path)),
+        }
+    }
+    match <[u8; SEED_LEN]>::try_from(data.as_slice()) {
+        Ok(seed) => Ok(Some(Stored { public_key: Identity::from_seed(&seed).public_key(), encrypted: false })),
+        Err(_) => Err(IdentityError::invalid_key(&path)),
+    }
+}
+
+/// Stores identity, encrypted if passphrase is given, existing one is replaced only if `replace`
+pub async fn store(identity: &Identity, passphrase: Option<&str>, replace: bool) -> GtrResult<PathBuf> {
+    let path = identity_path()?;
+    if !replace && path.exists() {
+        return Err(IdentityError::identity_exists(&path))
+    }
+    let content = match passphrase {
+        Some(passphrase) => seal(identity, passphrase, LOG_N)?,
+        None => identity.seed().to_vec(),
+    };
+
+    // written aside and renamed, so that the old key is not lost if writing fails
+    let written = path.with_extension("key.new");
+    let write = async {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).await?;
+        }
+        let _ = fs::remove_file(&written).await;
+        let mut file = OpenOptions::new().write(true).create_new(true).mode(0o600).open(&written).await?;
+        file.write_all(&content).await?;
+        file.sync_all().await?;
+        fs::rename(&written, &path).await
+    };
+    match write.await {
+        Ok(()) => Ok(path),
+        Err(e) => Err(IdentityError::identity_failed(Box::new(e))),
+    }
+}
+
+/// Fails if stored identity is encrypted, `GTR_PASSPHRASE` is unset and stdin is not a terminal
+///
+/// `gtd` checks it once when it starts, instead of failing on the first publish.
+pub async fn check_passphrase() -> GtrResult<()> {
+    // NOTE: a daemon started in background still has controlling terminal, but it must not wait
+    // for the passphrase on it, hence stdin and not the terminal is checked here
+    match stored().await? {
+        Some(Stored { encrypted: true, .. }) if env::var_os(PASSPHRASE_VAR).is_none() && !io::stdin().is_terminal() => {
+            Err(IdentityError::passphrase_unset())
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Passphrase from `GTR_PASSPHRASE`, asked on terminal without it
+pub fn passphrase(prompt: &str) -> GtrResult<String> {
+    if let Ok(passphrase) = env::var(PASSPHRASE_VAR) {
+        return Ok(passphrase)
+    }
+    // git runs remote helper with stdin piped, so it is asked on controlling terminal if any
+    if !io::stdin().is_terminal() && std::fs::File::open("/dev/tty").is_err() {
+        return Err(IdentityError::passphrase_unset())
+    }
+    match rpassword::prompt_password(prompt) {
+        Ok(passphrase) => Ok(passphrase),
+        Err(e) => Err(IdentityError::identity_failed(format!("can not read passphrase, {e}").into())),
+    }
+}
+
+/// New passphrase, asked twice on terminal
+pub fn new_passphrase() -> GtrResult<String> {
+    if let Ok(passphrase) = env::var(PASSPHRASE_VAR) {
+        return Ok(passphrase)
+    }
+    let passphrase = passphrase("New passphrase of gtr identity: ")?;
+    if passphrase.is_empty() {
+        return Err(IdentityError::identity_failed("passphrase is empty".into()))
+    }
+    if passphrase != self::passphrase("Repeat the passphrase: ")? {
+        return Err(IdentityError::identity_failed("passphrases do not match".into()))
+    }
+    Ok(passphrase)
+}
+
+/// Content of key file if it exists and other users can not access it
+async fn read() -> GtrResult<Option<(PathBuf, Vec<u8>)>> {
+    let path = identity_path()?;
+    let metadata = match fs::metadata(&path).await {
+        Ok(metadata) => metadata,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(IdentityError::identity_failed(Box::new(e))),
+    };
+    let mode = metadata.permissions().mode();
+    if mode & 0o077 != 0 {
+        return Err(IdentityError::key_exposed(&path, mode & 0o777))
+    }
+    match fs::read(&path).await {
+        Ok(data) => Ok(Some((path, data))),
+        Err(e) => Err(IdentityError::identity_failed(Box::new(e))),
+    }
+}
+
+fn seal(identity: &Identity, passphrase: &str, log_n: u8) -> GtrResult<Vec<u8>> {
+    let (mut salt, mut nonce) = ([0; SALT_LEN], [0; NONCE_LEN]);
+    if let Err(e) = getrandom::getrandom(&mut salt).and_then(|_| getrandom::getrandom(&mut nonce)) {
+        return Err(IdentityError::identity_failed(format!("can not generate salt, {e}").into()))
+    }
+    let cipher = ChaCha20Poly1305::new(&derive(passphrase, &salt, log_n)?);
+    let sealed = match cipher.encrypt(Nonce::from_slice(&nonce), identity.seed().as_slice()) {
+        Ok(sealed) => sealed,
+        Err(e) => return Err(IdentityError::identity_failed(format!("can not encrypt key, {e}").into())),
+    };
+    Ok([MAGIC, &[log_n], &salt, &nonce, &identity.public_key(), &sealed].concat())
+}
+
+/// Seed of key sealed by `seal`, without magic
+fn open(path: &Path, sealed: &[u8], passphrase: &str) -> GtrResult<[u8; SEED_LEN]> {
+    if sealed.len() != 1 + SALT_LEN + NONCE_LEN + 32 + SEED_LEN + TAG_LEN {
+        return Err(IdentityError::invalid_key(path))
+    }
+    let (log_n, rest) = (sealed[0], &sealed[1..]);
+    let (salt, rest) = rest.split_at(SALT_LEN);
+    let (nonce, rest) = rest.split_at(NONCE_LEN);
+    let (public_key, sealed) = rest.split_at(32);
+
+    let cipher = ChaCha20Poly1305::new(&derive(passphrase, salt, log_n)?);
+    let seed = match cipher.decrypt(Nonce::from_slice(nonce), sealed) {
+        Ok(seed) => seed,
+        Err(_) => return Err(IdentityError::wrong_passphrase()),
+    };
+    let seed: [u8; SEED_LEN] = match seed.try_into() {
+        Ok(seed) => seed,
+        Err(_) => return Err(IdentityError::invalid_key(path)),
+    };
+    if Identity::from_seed(&seed).public_key() != public_key {
+        return Err(IdentityError::invalid_key(path))
+    }
+    Ok(seed)
+}
+
+fn derive(passphrase: &str, salt: &[u8], log_n: u8) -> GtrResult<Key> {
+    let mut key = [0; 32];
+    let derived = scrypt::Params::new(log_n, 8, 1, key.len())
+        .map_err(|e| e.to_string())
+        .and_then(|params| scrypt::scrypt(passphrase.as_bytes(), salt, &params, &mut key).map_err(|e| e.to_string()));
+    match derived {
+        Ok(()) => Ok(Key::from(key)),
+        Err(e) => Err(IdentityError::identity_failed(format!("can not derive key from passphrase, {e}").into())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encrypts_key_with_passphrase() {
+        let identity = Identity::from_seed(&[7; SEED_LEN]);
+        let path = PathBuf::from("identity.key");
+        // cheap scrypt parameters, tests are not optimized
+        let sealed = seal(&identity, "correct horse", 4).unwrap();
+        let sealed = sealed.strip_prefix(MAGIC).unwrap();
+
+        assert_eq!(open(&path, sealed, "correct horse").unwrap(), identity.seed());
+        assert!(open(&path, sealed, "battery staple").is_err());
+        assert!(open(&path, &sealed[1..], "correct horse").is_err());
+    }
+}
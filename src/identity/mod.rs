@@ -0,0 +1,74 @@
+pub mod keystore;
+
+use ed25519_dalek::SigningKey;
+use sha1::{Digest, Sha1};
+
+use crate::utils::error::{GtrResult, IdentityError};
+
+// NOTE: the same as in gittorrent, the user is identified by ed25519 key signing mutable DHT items
+// (BEP 44). SHA-1 of its public key is the target of the profile item and the user part of
+// `torrent://<hex sha1>/<repository>` addresses. nostr keys are secp256k1 ones which can not sign
+// BEP 44 items, so they are not reused.
+
+pub const SEED_LEN: usize = 32;
+
+/// Key pair of the user
+pub struct Identity {
+    key: SigningKey,
+}
+
+impl Identity {
+    pub fn generate() -> GtrResult<Self> {
+        let mut seed = [0; SEED_LEN];
+        if let Err(e) = getrandom::getrandom(&mut seed) {
+            return Err(IdentityError::identity_failed(format!("can not generate key, {e}").into()))
+        }
+        Ok(Identity::from_seed(&seed))
+    }
+
+    /// Identity of secret key seed, as exported by `gtr identity export`
+    pub fn from_seed(seed: &[u8; SEED_LEN]) -> Self {
+        Identity { key: SigningKey::from_bytes(seed) }
+    }
+
+    pub fn seed(&self) -> [u8; SEED_LEN] {
+        self.key.to_bytes()
+    }
+
+    pub fn signing_key(&self) -> &SigningKey {
+        &self.key
+    }
+
+    pub fn public_key(&self) -> [u8; 32] {
+        self.key.verifying_key().to_bytes()
+    }
+
+    /// Hash of public key addressing the user
+    pub fn hash(&self) -> [u8; 20] {
+        key_hash(&self.public_key())
+    }
+}
+
+/// SHA-1 of public key, the same as BEP 44 target of mutable item without salt
+pub fn key_hash(public_key: &[u8; 32]) -> [u8; 20] {
+    Sha1::digest(public_key).into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::hex::hex;
+
+    #[test]
+    fn hashes_public_key() {
+        // RFC 8032, test 1
+        let seed = [
+            0x9d, 0x61, 0xb1, 0x9d, 0xef, 0xfd, 0x5a, 0x60, 0xba, 0x84, 0x4a, 0xf4, 0x92, 0xec, 0x2c, 0xc4,
+            0x44, 0x49, 0xc5, 0x69, 0x7b, 0x32, 0x69, 0x19, 0x70, 0x3b, 0xac, 0x03, 0x1c, 0xae, 0x7f, 0x60,
+        ];
+        let identity = Identity::from_seed(&seed);
+        assert_eq!(hex(&identity.public_key()), "d75a980182b10ab7d54bfed3c964073a0ee172f3daa62325af021a68f707511a");
+        assert_eq!(identity.hash(), <[u8; 20]>::from(Sha1::digest(identity.public_key())));
+        assert_eq!(identity.seed(), seed);
+    }
+}
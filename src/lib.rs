@@ -1,7 +1,7 @@
 pub mod git_interface;
 pub mod config;
+pub mod daemon;
 pub mod gti;
+pub mod identity;
 pub mod transports;
 pub mod utils;
-#[cfg(feature = "torrent")]
-pub mod torrent;
@@ -1,8 +1,17 @@
 // use std::env;
-use gtr::git_interface::{gtr_setup, upload_pack};
+use std::fmt::Display;
+use std::path::{Path, PathBuf};
+use std::process::exit;
+use gtr::git_interface::{gtr_setup, hooks, upload_pack};
+use gtr::git_interface::refs::ObjectId;
 use gtr::config::branches::{include, remove, list};
+use gtr::config::{contacts, repositories};
+use gtr::daemon::control::{self, Request};
+use gtr::daemon::service;
+use gtr::identity::{key_hash, keystore, Identity};
+use gtr::transports::url::{RepoUrl, Scheme};
+use gtr::utils::hex::{from_hex, hex};
 // TODO: use a feature and inject in a different place
-use gtr::transports::default;
 use gtr::gti::cli;
 
 // XXX UX:
@@ -33,7 +42,6 @@ use gtr::gti::cli;
 //
 // TODO: create nested "test" directory to run tests within it.
 // TODO: (what is this) read branches from file? get their hash from git follow original gittorrent
-// TODO: write integration test
 
 
 #[tokio::main]
@@ -47,40 +55,204 @@ async fn main() {
         //
         // TODO: allow remote access?
         // sshd, etc
-        Some(("init", sub_matches)) => include(
-            sub_matches.get_one("path").unwrap(),
-            &vec![&String::from("master")]
-        ).await.unwrap(),
+        Some(("init", sub_matches)) => {
+            let dir: &PathBuf = sub_matches.get_one("path").unwrap();
+            or_exit(include(dir, &vec![&String::from("master")]).await);
+            if sub_matches.get_flag("hooks") { or_exit(hooks::install(dir).await); }
+            or_exit(repositories::update(dir).await);
+            reload(dir).await;
+        }
         Some(("share", sub_matches)) => {
             let branches = sub_matches
                 .get_many::<String>("branches")
                 .unwrap_or_default()
                 .collect::<Vec<_>>();
 
-            include(sub_matches.get_one("path").unwrap(), &branches).await.unwrap();
+            let dir: &PathBuf = sub_matches.get_one("path").unwrap();
+            or_exit(include(dir, &branches).await);
+            if sub_matches.get_flag("hooks") { or_exit(hooks::install(dir).await); }
+            or_exit(repositories::update(dir).await);
+            reload(dir).await;
         }
         Some(("list", sub_matches)) => {
-            let branches = list(sub_matches.get_one("path").unwrap()).await;
-            println!("shared branches: {branches:?}");
+            let dir: &PathBuf = sub_matches.get_one("path").unwrap();
+            let branches = or_exit(list(dir).await);
+            println!("shared branches: {}", branches.join(", "));
+            if let Some(url) = address(dir).await {
+                println!("address: {url}");
+            }
+            match control::request(&Request::Status(dir.clone())).await {
+                Ok(lines) => println!("announce status: {}", lines.join(", ")),
+                Err(e) => println!("announce status: unknown, {e}"),
+            }
         },
         Some(("remove", sub_matches)) => {
             let branches = sub_matches
                 .get_many::<String>("branches")
                 .unwrap_or_default()
                 .collect::<Vec<_>>();
-            remove(sub_matches.get_one("path").unwrap(), &branches).await.unwrap();
+            let dir: &PathBuf = sub_matches.get_one("path").unwrap();
+            or_exit(remove(dir, &branches).await);
+            // the repository is not served anymore once it shares no branch
+            or_exit(repositories::update(dir).await);
+            reload(dir).await;
+        }
+        Some(("repos", sub_matches)) => {
+            if let Some(root) = sub_matches.get_one::<PathBuf>("scan") {
+                for dir in or_exit(repositories::scan(root).await) {
+                    println!("registered {}", dir.display());
+                }
+            }
+            for dir in or_exit(repositories::list().await) {
+                match list(&dir).await {
+                    Ok(branches) => println!("{}: {}", dir.display(), branches.join(", ")),
+                    Err(e) => println!("{}: {e}", dir.display()),
+                }
+            }
         }
+        Some(("daemon", sub_matches)) => match sub_matches.subcommand() {
+            Some(("install", install_matches)) => {
+                let unit = or_exit(service::install(install_matches.get_flag("socket")).await);
+                println!("installed {}", unit.display());
+            }
+            Some(("uninstall", _)) => or_exit(service::uninstall().await),
+            Some(("status", _)) => {
+                let status = or_exit(service::status().await);
+                if !status.installed {
+                    println!("{} is not installed", service::SERVICE_UNIT);
+                } else {
+                    let started = if status.socket { service::SOCKET_UNIT } else { service::SERVICE_UNIT };
+                    println!("{}: {}, {started} {}", service::SERVICE_UNIT, status.active, status.enabled);
+                }
+            }
+            Some(("reload", reload_matches)) => {
+                let dir: &PathBuf = reload_matches.get_one("path").unwrap();
+                let lines = or_exit(control::request(&Request::Reload(dir.clone())).await);
+                lines.iter().for_each(|line| println!("{line}"));
+            }
+            Some(("republish", republish_matches)) => {
+                let dir = republish_matches.get_one::<PathBuf>("path").cloned();
+                let lines = or_exit(control::request(&Request::Republish(dir)).await);
+                lines.iter().for_each(|line| println!("{line}"));
+            }
+            Some(("stats", _)) => {
+                let lines = or_exit(control::request(&Request::Stats).await);
+                lines.iter().for_each(|line| println!("{line}"));
+            }
+            _ => unreachable!(),
+        },
+        Some(("identity", sub_matches)) => match sub_matches.subcommand() {
+            Some(("new", new_matches)) => {
+                let passphrase = or_exit(new_matches.get_flag("passphrase").then(keystore::new_passphrase).transpose());
+                let identity = or_exit(Identity::generate());
+                let path = or_exit(keystore::store(&identity, passphrase.as_deref(), new_matches.get_flag("force")).await);
+                println!("stored {}, hash {}", path.display(), hex(&identity.hash()));
+            }
+            Some(("show", _)) => match or_exit(keystore::stored().await) {
+                Some(stored) => {
+                    let hash = key_hash(&stored.public_key);
+                    println!("public key: {}", hex(&stored.public_key));
+                    println!("hash: {}", hex(&hash));
+                    println!("addresses: {}", RepoUrl::new(Scheme::Torrent, &hash, "<repository>"));
+                    println!("encrypted: {}", if stored.encrypted { "yes" } else { "no" });
+                }
+                None => println!("no identity, it is generated by `gtr identity new` or the first publish"),
+            },
+            Some(("export", _)) => match or_exit(keystore::load().await) {
+                Some(identity) => println!("{}", hex(&identity.seed())),
+                None => println!("no identity, it is generated by `gtr identity new` or the first publish"),
+            },
+            Some(("import", import_matches)) => {
+                let exported = match import_matches.get_one::<PathBuf>("file") {
+                    Some(file) => or_exit(std::fs::read_to_string(file)),
+                    None => or_exit(std::io::read_to_string(std::io::stdin())),
+                };
+                let seed = or_exit(from_hex(exported.trim()).ok_or("key is 64 hex digits as printed by `gtr identity export`"));
+                let identity = Identity::from_seed(&seed);
+                let passphrase = or_exit(import_matches.get_flag("passphrase").then(keystore::new_passphrase).transpose());
+                let path = or_exit(keystore::store(&identity, passphrase.as_deref(), import_matches.get_flag("force")).await);
+                println!("stored {}, hash {}", path.display(), hex(&identity.hash()));
+            }
+            _ => unreachable!(),
+        },
+        Some(("contacts", sub_matches)) => match sub_matches.subcommand() {
+            Some(("add", add_matches)) => {
+                let name: &String = add_matches.get_one("name").unwrap();
+                or_exit(contacts::add(name, add_matches.get_one::<String>("key").unwrap()).await);
+            }
+            Some(("remove", remove_matches)) => {
+                or_exit(contacts::remove(remove_matches.get_one::<String>("name").unwrap()).await);
+            }
+            Some(("rename", rename_matches)) => {
+                let name: &String = rename_matches.get_one("name").unwrap();
+                or_exit(contacts::rename(name, rename_matches.get_one::<String>("new_name").unwrap()).await);
+            }
+            Some(("list", _)) => {
+                for (name, key) in or_exit(contacts::list().await).contacts {
+                    match contacts::parse_key(&key) {
+                        Ok(hash) => println!("{name}: {}", hex(&hash)),
+                        Err(e) => println!("{name}: {e}"),
+                    }
+                }
+            }
+            _ => unreachable!(),
+        },
         Some(("pack", sub_matches)) => {
-            let want = "66ef7ea67c18d2341afb8c1521afbab31014e62f"; // refs/heads/test/want
-            let have = Some("da13823f7206ed470cdab7c98285cd706ae1dcbe"); // refs/heads/test/have
+            // SHA-1 or SHA-256 depending on object format of the repository
+            let want: ObjectId = or_exit(sub_matches.get_one::<String>("want").unwrap().parse());
+            let haves: Vec<ObjectId> = or_exit(sub_matches
+                .get_one::<String>("have")
+                .map(|have| have.parse())
+                .into_iter()
+                .collect());
 
-            let dir = sub_matches.get_one("path").unwrap();
-            upload_pack(dir, want, have).await.unwrap();
+            let dir: &PathBuf = sub_matches.get_one("path").unwrap();
+            let mut progress = |message: &str| eprint!("{message}");
+            let pack = or_exit(upload_pack(dir, &[want], &haves, &mut progress).await);
+            println!("pack file: {}", pack.path.display());
         }
         Some(("setup", sub_matches)) => {
-            let dir = sub_matches.get_one("path").unwrap();
-            gtr_setup(dir).await.unwrap();
+            let dir: &PathBuf = sub_matches.get_one("path").unwrap();
+            or_exit(gtr_setup(dir, sub_matches.get_flag("hooks")).await);
+        }
+        Some(("hook", sub_matches)) => {
+            let dir: &PathBuf = sub_matches.get_one("path").unwrap();
+            let name: &String = sub_matches.get_one("name").unwrap();
+            let args = sub_matches
+                .get_many::<String>("args")
+                .unwrap_or_default()
+                .cloned()
+                .collect::<Vec<_>>();
+            or_exit(hooks::run(dir, name, &args).await);
         }
         _ => unreachable!(), // If all subcommands are defined above, anything else is unreachabe!()
     }
 }
+
+/// Value of successful result, otherwise the error is printed and gtr exits with non-zero status
+fn or_exit<T, E: Display>(result: Result<T, E>) -> T {
+    match result {
+        Ok(value) => value,
+        Err(e) => {
+            eprintln!("error: {e}");
+            exit(1)
+        }
+    }
+}
+
+/// Makes running daemon act on changed settings of repository right away, without the daemon it is
+/// served on the next start
+async fn reload(dir: &Path) {
+    if let Ok(lines) = control::request(&Request::Reload(dir.to_path_buf())).await {
+        lines.iter().for_each(|line| println!("gtd: {line}"));
+    }
+}
+
+/// Address peers clone repository from, none if there is no identity yet
+async fn address(dir: &PathBuf) -> Option<RepoUrl> {
+    let stored = keystore::stored().await.ok()??;
+    // the same name as in published profile, the name of repository directory
+    let dir = std::fs::canonicalize(dir).ok()?;
+    let name = dir.file_name()?.to_string_lossy();
+    Some(RepoUrl::new(Scheme::Torrent, &key_hash(&stored.public_key), &name))
+}
@@ -0,0 +1,58 @@
+use std::path::{Path, PathBuf};
+use async_trait::async_trait;
+
+use crate::git_interface::ls_remote;
+#[cfg(not(feature = "native"))]
+use crate::git_interface::generate_pack;
+#[cfg(feature = "native")]
+use crate::git_interface::native::generate_pack;
+use crate::git_interface::refs::{ObjectId, Ref};
+use crate::transports::{Progress, Transport};
+use crate::utils::error::GtrResult;
+
+pub const SCHEME: &str = "file";
+
+/// Exchange with repositories on local file system, `file:///path/to/repo` or just the path
+///
+/// Repository is read directly so there is nothing to announce or serve.
+pub struct Local;
+
+impl Local {
+    fn path(url: &str) -> PathBuf {
+        PathBuf::from(url.strip_prefix("file://").unwrap_or(url))
+    }
+}
+
+#[async_trait]
+impl Transport for Local {
+    fn scheme(&self) -> &'static str {
+        SCHEME
+    }
+
+    async fn announce(&self, _dir: &Path, _refs: &[Ref]) -> GtrResult<()> {
+        Ok(())
+    }
+
+    async fn resolve(&self, url: &str) -> GtrResult<Vec<Ref>> {
+        ls_remote(&Local::path(url).to_string_lossy()).await
+    }
+
+    async fn fetch_pack(
+        &self,
+        url: &str,
+        wants: &[ObjectId],
+        haves: &[ObjectId],
+        pack_path: &Path,
+        mut progress: &mut Progress) -> GtrResult<()>
+    {
+        generate_pack(&Local::path(url), wants, haves, &pack_path.to_path_buf(), &mut progress).await
+    }
+
+    async fn serve(&self, _dir: &Path) -> GtrResult<()> {
+        Ok(())
+    }
+
+    async fn shutdown(&self) -> GtrResult<()> {
+        Ok(())
+    }
+}
@@ -1 +1,164 @@
 pub mod default;
+pub mod remote_helper;
+pub mod url;
+#[cfg(feature = "torrent")]
+pub mod torrent;
+
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::Arc;
+use async_trait::async_trait;
+use tokio::io::{AsyncRead, AsyncWrite};
+
+use crate::config::config_file::Config;
+use crate::git_interface::refs::{ObjectId, Ref};
+use crate::utils::error::{GtrError, GtrResult, TransportError};
+
+/// Receives progress messages as reported by git, e.g. `Counting objects: 10\r`
+pub type Progress<'a> = dyn FnMut(&str) + Send + 'a;
+
+/// Way of exchanging repositories with peers, e.g. local file system or BitTorrent
+///
+/// Transport is picked by scheme of repository address (`<scheme>://...`), addresses without
+/// scheme are local paths.
+#[async_trait]
+pub trait Transport: Send + Sync {
+    /// Scheme of addresses handled by the transport
+    fn scheme(&self) -> &'static str;
+
+    /// Makes shared refs of local repository discoverable by peers
+    async fn announce(&self, dir: &Path, refs: &[Ref]) -> GtrResult<()>;
+
+    /// Resolves repository address into refs advertised by the peer
+    async fn resolve(&self, url: &str) -> GtrResult<Vec<Ref>>;
+
+    /// Receives pack with objects reachable from `wants` from repository at `url` and stores it at
+    /// `pack_path`, pack may be thin
+    ///
+    /// `haves` let the peer leave out objects reachable from them, transports may ignore them and
+    /// send whole history of `wants` (e.g. torrent seeds full packs of announced commits).
+    async fn fetch_pack(
+        &self,
+        url: &str,
+        wants: &[ObjectId],
+        haves: &[ObjectId],
+        pack_path: &Path,
+        progress: &mut Progress) -> GtrResult<()>;
+
+    /// Connects to git `service` (e.g. `git-upload-pack`) of repository at `url`, none lets git
+    /// fall back to `fetch` and `push`
+    async fn connect(&self, _url: &str, _service: &str) -> GtrResult<Option<Tunnel>> {
+        Ok(None)
+    }
+
+    /// Starts answering pack requests of peers for local repository
+    async fn serve(&self, dir: &Path) -> GtrResult<()>;
+
+    /// Stops serving and releases resources (sockets, DHT node, ...)
+    async fn shutdown(&self) -> GtrResult<()>;
+
+    /// Connected peers and transferred pack data, transports without peers have none
+    fn stats(&self) -> Stats {
+        Stats::default()
+    }
+
+    /// Errors of serving peers since the last call, the daemon reports them
+    fn failures(&self) -> Vec<GtrError> {
+        Vec::new()
+    }
+}
+
+/// Connection to git service of peer repository, requests written to `writer` reach the service
+/// and its responses are read from `reader`
+pub struct Tunnel {
+    pub reader: Box<dyn AsyncRead + Send + Unpin>,
+    pub writer: Box<dyn AsyncWrite + Send + Unpin>,
+}
+
+/// Activity of transport since it started
+#[derive(Default, Debug)]
+pub struct Stats {
+    /// Addresses of currently connected peers
+    pub peers: Vec<String>,
+    /// Bytes of packs sent to peers
+    pub uploaded: u64,
+    /// Bytes of packs received from peers
+    pub downloaded: u64,
+}
+
+/// Transports available in this build, keyed by scheme
+#[derive(Default)]
+pub struct Registry {
+    transports: HashMap<&'static str, Arc<dyn Transport>>,
+}
+
+impl Registry {
+    /// Adds transport, replacing one registered for the same scheme
+    pub fn register(&mut self, transport: Arc<dyn Transport>) {
+        self.transports.insert(transport.scheme(), transport);
+    }
+
+    pub fn get(&self, scheme: &str) -> Option<Arc<dyn Transport>> {
+        self.transports.get(scheme).cloned()
+    }
+
+    /// Transport handling given repository address
+    pub fn for_url(&self, url: &str) -> GtrResult<Arc<dyn Transport>> {
+        let scheme = scheme(url);
+        match self.get(scheme) {
+            Some(transport) => Ok(transport),
+            None => Err(TransportError::unsupported_scheme(scheme)),
+        }
+    }
+
+    pub fn transports(&self) -> impl Iterator<Item = &Arc<dyn Transport>> {
+        self.transports.values()
+    }
+
+    /// Shuts all transports down, continuing past failures and returning the first of them
+    pub async fn shutdown(&self) -> GtrResult<()> {
+        let mut result = Ok(());
+        for transport in self.transports.values() {
+            let shutdown = transport.shutdown().await;
+            if result.is_ok() { result = shutdown; }
+        }
+        result
+    }
+}
+
+/// Creates registry with transports enabled by cargo features and configured in `.gtr/config.toml`
+#[cfg_attr(not(feature = "torrent"), allow(unused_variables))]
+pub async fn registry(conf: &Config) -> GtrResult<Registry> {
+    let mut registry = Registry::default();
+    registry.register(Arc::new(default::Local));
+    #[cfg(feature = "torrent")]
+    if let Some(torrent) = &conf.transport.torrent {
+        registry.register(Arc::new(torrent::Torrent::start(torrent)?));
+    }
+
+    Ok(registry)
+}
+
+/// Scheme of repository address, addresses without one are local paths
+pub fn scheme(url: &str) -> &str {
+    match url.split_once("://") {
+        Some((scheme, _)) => scheme,
+        None => default::SCHEME,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::config_file::{Packs, Transport as TransportConfig};
+
+    #[tokio::test]
+    async fn selects_transport_by_scheme() {
+        let conf = Config { branches: vec![], transport: TransportConfig { torrent: None }, packs: Packs::default() };
+        let registry = registry(&conf).await.unwrap();
+
+        assert_eq!(registry.for_url("file:///srv/repo").unwrap().scheme(), "file");
+        assert_eq!(registry.for_url("../repo").unwrap().scheme(), "file");
+        assert!(registry.for_url("nostr://npub/repo").is_err());
+    }
+}
@@ -0,0 +1,405 @@
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, Lines};
+
+use crate::config::{branches, repositories};
+use crate::daemon::control::{self, Request};
+use crate::daemon::{ANNOUNCE_FAILED, NOT_SERVED};
+use crate::git_interface::hooks::precompute_packs;
+use crate::git_interface::protocol_v2::shared_refs;
+use crate::git_interface::refs::{ObjectId, Ref};
+use crate::git_interface::{ls_remote, store_pack};
+use crate::transports::{Transport, Tunnel};
+use crate::utils::error::{GtrResult, TransportError};
+
+// NOTE: https://git-scm.com/docs/gitremote-helpers
+// git runs `git-remote-<scheme> <remote> <url>` inside the repository (GIT_DIR is set) and talks
+// to it with line based commands over stdin/stdout.
+
+const CAPABILITIES: [&str; 4] = ["connect", "fetch", "push", "option"];
+
+/// git remote helper exchanging objects through given transport
+pub struct RemoteHelper {
+    transport: Arc<dyn Transport>,
+    url: String,
+    // the only ref offered by the remote, e.g. from `#<ref>` fragment of its address
+    reference: Option<String>,
+    git_dir: PathBuf,
+    progress: bool,
+    object_format: bool,
+    dry_run: bool,
+}
+
+impl RemoteHelper {
+    pub fn new(transport: Arc<dyn Transport>, url: &str, git_dir: &Path) -> Self {
+        RemoteHelper {
+            transport,
+            url: String::from(url),
+            reference: None,
+            git_dir: git_dir.to_path_buf(),
+            progress: true,
+            object_format: false,
+            dry_run: false,
+        }
+    }
+
+    /// Offers only given ref of the remote, with HEAD pointing to it if it is a branch
+    pub fn with_reference(mut self, reference: Option<String>) -> Self {
+        self.reference = reference;
+        self
+    }
+
+    /// Answers git commands until input is closed or git sends empty line
+    pub async fn run(
+        &mut self,
+        input: impl AsyncBufRead + Unpin,
+        mut output: impl AsyncWrite + Unpin) -> GtrResult<()>
+    {
+        let mut lines = input.lines();
+        while let Some(line) = next_line(&mut lines).await? {
+            let (command, arguments) = line.split_once(' ').unwrap_or((&line, ""));
+            let response = match command {
+                "" => break,
+                "capabilities" => CAPABILITIES.iter().map(|c| format!("{c}\n")).collect::<String>() + "\n",
+                "option" => self.option(arguments),
+                "list" => self.list(arguments).await?,
+                "fetch" => self.fetch(arguments, &mut lines).await?,
+                "push" => self.push(arguments, &mut lines).await?,
+                "connect" => match self.connect(arguments).await? {
+                    Some(tunnel) => {
+                        respond(&mut output, "\n").await?;
+                        return relay(lines.into_inner(), output, tunnel).await
+                    },
+                    None => String::from("fallback\n"),
+                },
+                _ => return Err(TransportError::transport_failed(&format!("unsupported remote helper command: {line}"))),
+            };
+            respond(&mut output, &response).await?;
+        }
+
+        Ok(())
+    }
+
+    /// `connect <service>`, git talks to the service of the peer directly through the tunnel
+    ///
+    /// Fragment of the address limits refs of the remote, those are offered only by `list`.
+    async fn connect(&self, service: &str) -> GtrResult<Option<Tunnel>> {
+        if self.reference.is_some() {
+            return Ok(None)
+        }
+        self.transport.connect(&self.url, service).await
+    }
+
+    /// `option <name> <value>`
+    fn option(&mut self, arguments: &str) -> String {
+        let (name, value) = arguments.split_once(' ').unwrap_or((arguments, ""));
+        match name {
+            "progress" => self.progress = value.eq("true"),
+            "object-format" => self.object_format = value.eq("true"),
+            "dry-run" => self.dry_run = value.eq("true"),
+            // accepted so that git does not complain, output goes to stderr anyway
+            "verbosity" => {},
+            _ => return String::from("unsupported\n"),
+        }
+        String::from("ok\n")
+    }
+
+    /// Refs advertised by the peer, `<sha> <name>` or `@<target> <name>` for symbolic refs
+    ///
+    /// `list for-push` lists branches shared by local repository instead, those are what push
+    /// updates.
+    async fn list(&self, arguments: &str) -> GtrResult<String> {
+        let refs = match arguments {
+            "for-push" => shared_refs(&work_tree(&self.git_dir)).await?,
+            _ => match &self.reference {
+                Some(name) => only_reference(self.transport.resolve(&self.url).await?, name)?,
+                None => self.transport.resolve(&self.url).await?,
+            },
+        };
+
+        let mut response = String::new();
+        if self.object_format {
+            if let Some(r) = refs.first() {
+                response.push_str(&format!(":object-format {}\n", r.id().format()));
+            }
+        }
+        for r in &refs {
+            match r {
+                Ref::Symbolic { name, target, .. } => response.push_str(&format!("@{target} {name}\n")),
+                _ => response.push_str(&format!("{} {}\n", r.id(), r.name())),
+            }
+        }
+        response.push('\n');
+
+        Ok(response)
+    }
+
+    /// Batch of `fetch <sha> <name>` lines terminated by empty line, objects are requested in one
+    /// pack and stored in repository, git updates refs itself
+    async fn fetch<R: AsyncBufRead + Unpin>(&self, first: &str, lines: &mut Lines<R>) -> GtrResult<String> {
+        let mut wants: Vec<ObjectId> = Vec::new();
+        for arguments in read_batch("fetch", first, lines).await? {
+            match arguments.split_once(' ') {
+                Some((id, _)) => wants.push(id.parse()?),
+                None => return Err(TransportError::transport_failed(&format!("invalid fetch command: {arguments}"))),
+            }
+        }
+
+        // tips of local refs let peer send only missing objects
+        let haves: Vec<ObjectId> = ls_remote(&self.git_dir.to_string_lossy()).await?
+            .iter()
+            .map(|r| *r.id())
+            .filter(|id| wants.first().is_some_and(|want| want.format() == id.format()))
+            .collect();
+
+        let pack_path = self.git_dir.join(format!("gtr-fetch-{}.pack", std::process::id()));
+        let show_progress = self.progress;
+        let mut progress = |message: &str| if show_progress { eprint!("{message}") };
+        let fetched = self.transport.fetch_pack(&self.url, &wants, &haves, &pack_path, &mut progress).await;
+        let stored = match fetched {
+            Ok(()) => store_pack(&self.git_dir, &pack_path).await,
+            Err(e) => Err(e),
+        };
+        let _ = tokio::fs::remove_file(&pack_path).await;
+        stored?;
+
+        Ok(String::from("\n"))
+    }
+
+    /// Batch of `push [+]<src>:<dst>` lines terminated by empty line, answered with `ok <dst>` or
+    /// `error <dst> <why>` for each of them
+    ///
+    /// Peer repository is the local one as published by the transport, so pushing a branch shares
+    /// it: the branch is added to `.gtr/config.toml`, the repository is registered for `gtd`, packs
+    /// are regenerated and shared refs are announced again. Pushing empty source (`git push --delete`) stops sharing the branch.
+    async fn push<R: AsyncBufRead + Unpin>(&self, first: &str, lines: &mut Lines<R>) -> GtrResult<String> {
+        let local = ls_remote(&self.git_dir.to_string_lossy()).await?;
+        let id_of = |name: &str| local.iter().find(|r| r.name().eq(name)).map(|r| *r.id());
+
+        let mut included: Vec<String> = Vec::new();
+        let mut removed: Vec<String> = Vec::new();
+        // destination ref and error if it can not be pushed
+        let mut results: Vec<(String, Option<String>)> = Vec::new();
+        for refspec in read_batch("push", first, lines).await? {
+            // published refs always follow local branches, force is implied
+            let (src, dst) = match refspec.trim_start_matches('+').split_once(':') {
+                Some((src, dst)) => (String::from(src), String::from(dst)),
+                None => return Err(TransportError::transport_failed(&format!("invalid push command: {refspec}"))),
+            };
+            let branch = match dst.strip_prefix("refs/heads/") {
+                Some(branch) => String::from(branch),
+                None => {
+                    results.push((dst, Some(String::from("only branches can be published"))));
+                    continue
+                },
+            };
+
+            if src.is_empty() {
+                removed.push(branch);
+                results.push((dst, None));
+                continue
+            }
+            // peers get local branch tips, other names or commits can not be published
+            match (id_of(&src), id_of(&dst)) {
+                (Some(src_id), Some(branch_id)) if src_id == branch_id => {
+                    included.push(branch);
+                    results.push((dst, None));
+                },
+                _ => results.push((dst.clone(), Some(format!("publish local branch as {dst}:{dst}")))),
+            }
+        }
+
+        let changed = !(included.is_empty() && removed.is_empty());
+        if changed && !self.dry_run {
+            if let Err(e) = self.publish(&included, &removed).await {
+                for (_, error) in results.iter_mut().filter(|(_, error)| error.is_none()) {
+                    *error = Some(e.to_string());
+                }
+            }
+        }
+
+        let mut response: String = results
+            .iter()
+            .map(|(dst, error)| match error {
+                Some(error) => format!("error {dst} {error}\n"),
+                None => format!("ok {dst}\n"),
+            })
+            .collect();
+        response.push('\n');
+
+        Ok(response)
+    }
+
+    /// Updates shared branches, regenerates their packs and announces them
+    ///
+    /// Running `gtd` announces them with its DHT node and pack server, the transport of this
+    /// process is used only without it. Peers would not reach this process once git is done.
+    async fn publish(&self, included: &[String], removed: &[String]) -> GtrResult<()> {
+        let dir = work_tree(&self.git_dir);
+        if !included.is_empty() {
+            branches::include(&dir, &included.iter().collect()).await?;
+        }
+        if !removed.is_empty() {
+            branches::remove(&dir, &removed.iter().collect()).await?;
+        }
+        repositories::update(&dir).await?;
+
+        precompute_packs(&dir).await?;
+        if control::running().await {
+            let status = control::request(&Request::Reload(dir.clone())).await?.join(", ");
+            if let Some(e) = status.strip_prefix(ANNOUNCE_FAILED) {
+                return Err(TransportError::transport_failed(&format!("gtd: {e}")))
+            }
+            // e.g. gtd started for other repositories
+            if status != NOT_SERVED {
+                return Ok(())
+            }
+        }
+        let refs = shared_refs(&dir).await?;
+        self.transport.announce(&dir, &refs).await
+    }
+}
+
+/// Lines of a batch of `command` (its first line is already read) up to empty line
+async fn read_batch<R: AsyncBufRead + Unpin>(command: &str, first: &str, lines: &mut Lines<R>) -> GtrResult<Vec<String>> {
+    let mut batch = vec![String::from(first)];
+    loop {
+        match next_line(lines).await? {
+            Some(line) if line.is_empty() => return Ok(batch),
+            Some(line) => match line.strip_prefix(&format!("{command} ")) {
+                Some(arguments) => batch.push(String::from(arguments)),
+                None => return Err(TransportError::transport_failed(&format!("unexpected command in {command} batch: {line}"))),
+            },
+            None => return Ok(batch),
+        }
+    }
+}
+
+async fn respond(output: &mut (impl AsyncWrite + Unpin), response: &str) -> GtrResult<()> {
+    if let Err(e) = output.write_all(response.as_bytes()).await {
+        return Err(TransportError::transport_failed(&format!("{e}")))
+    }
+    if let Err(e) = output.flush().await {
+        return Err(TransportError::transport_failed(&format!("{e}")))
+    }
+    Ok(())
+}
+
+/// Passes input of git to the tunnel and its output back until both of them end
+async fn relay(mut input: impl AsyncRead + Unpin, mut output: impl AsyncWrite + Unpin, tunnel: Tunnel) -> GtrResult<()> {
+    let Tunnel { mut reader, mut writer } = tunnel;
+    let requests = async {
+        tokio::io::copy(&mut input, &mut writer).await?;
+        writer.shutdown().await
+    };
+    let responses = async {
+        tokio::io::copy(&mut reader, &mut output).await?;
+        output.flush().await
+    };
+    match tokio::try_join!(requests, responses) {
+        Ok(_) => Ok(()),
+        Err(e) => Err(TransportError::transport_failed(&format!("{e}"))),
+    }
+}
+
+/// Work tree of repository with given git dir, gtr settings are stored there
+pub fn work_tree(git_dir: &Path) -> PathBuf {
+    match git_dir.file_name() {
+        Some(name) if name == ".git" => git_dir.parent().map(PathBuf::from).unwrap_or_default(),
+        // bare repository
+        _ => git_dir.to_path_buf(),
+    }
+}
+
+async fn next_line<R: AsyncBufRead + Unpin>(lines: &mut Lines<R>) -> GtrResult<Option<String>> {
+    match lines.next_line().await {
+        Ok(line) => Ok(line),
+        Err(e) => Err(TransportError::transport_failed(&format!("{e}"))),
+    }
+}
+
+/// The only ref of remote, HEAD points to it so that clone checks out the branch
+fn only_reference(refs: Vec<Ref>, name: &str) -> GtrResult<Vec<Ref>> {
+    let found = match refs.into_iter().find(|r| r.name() == name) {
+        Some(found) => found,
+        None => return Err(TransportError::transport_failed(&format!("{name} is not shared by the remote"))),
+    };
+    if found.branch().is_none() {
+        return Ok(vec![found])
+    }
+    Ok(vec![Ref::new("HEAD", *found.id(), Some(name), None), found])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use async_trait::async_trait;
+    use crate::transports::Progress;
+
+    const MASTER: &str = "66ef7ea67c18d2341afb8c1521afbab31014e62f";
+
+    struct Advertising(Vec<Ref>);
+
+    #[async_trait]
+    impl Transport for Advertising {
+        fn scheme(&self) -> &'static str { "test" }
+        async fn announce(&self, _dir: &Path, _refs: &[Ref]) -> GtrResult<()> { Ok(()) }
+        async fn resolve(&self, _url: &str) -> GtrResult<Vec<Ref>> { Ok(self.0.clone()) }
+        async fn fetch_pack(
+            &self,
+            _url: &str,
+            _wants: &[ObjectId],
+            _haves: &[ObjectId],
+            _pack_path: &Path,
+            _progress: &mut Progress) -> GtrResult<()>
+        {
+            Err(TransportError::transport_failed("not implemented"))
+        }
+        async fn serve(&self, _dir: &Path) -> GtrResult<()> { Ok(()) }
+        async fn shutdown(&self) -> GtrResult<()> { Ok(()) }
+    }
+
+    #[tokio::test]
+    async fn answers_capabilities_options_and_list() {
+        let master: ObjectId = MASTER.parse().unwrap();
+        let transport = Advertising(vec![
+            Ref::new("HEAD", master, Some("refs/heads/master"), None),
+            Ref::new("refs/heads/master", master, None, None),
+        ]);
+        let mut helper = RemoteHelper::new(Arc::new(transport), "test://peer/repo", &PathBuf::from("."));
+
+        let input = "capabilities\noption object-format true\noption depth 1\nconnect git-upload-pack\nlist\n\n";
+        let mut output = Vec::new();
+        helper.run(input.as_bytes(), &mut output).await.unwrap();
+
+        let expected = format!(
+            "connect\nfetch\npush\noption\n\nok\nunsupported\nfallback\n:object-format sha1\n@refs/heads/master HEAD\n{MASTER} refs/heads/master\n\n"
+        );
+        assert_eq!(String::from_utf8(output).unwrap(), expected);
+
+        let transport = Advertising(vec![
+            Ref::new("HEAD", master, Some("refs/heads/master"), None),
+            Ref::new("refs/heads/master", master, None, None),
+            Ref::new("refs/heads/dev", master, None, None),
+        ]);
+        let mut helper = RemoteHelper::new(Arc::new(transport), "test://peer/repo", &PathBuf::from("."))
+            .with_reference(Some(String::from("refs/heads/dev")));
+        let mut output = Vec::new();
+        helper.run("list\n\n".as_bytes(), &mut output).await.unwrap();
+        assert_eq!(String::from_utf8(output).unwrap(), format!("@refs/heads/dev HEAD\n{MASTER} refs/heads/dev\n\n"));
+    }
+
+    #[tokio::test]
+    async fn reports_each_pushed_refspec() {
+        let mut helper = RemoteHelper::new(Arc::new(Advertising(vec![])), "test://peer/repo", &PathBuf::from("."));
+
+        let input = "option dry-run true\npush refs/tags/v1:refs/tags/v1\npush +HEAD~1:refs/heads/gtr-missing\npush :refs/heads/gtr-missing\n\n";
+        let mut output = Vec::new();
+        helper.run(input.as_bytes(), &mut output).await.unwrap();
+
+        let expected = "ok\nerror refs/tags/v1 only branches can be published\n\
+            error refs/heads/gtr-missing publish local branch as refs/heads/gtr-missing:refs/heads/gtr-missing\n\
+            ok refs/heads/gtr-missing\n\n";
+        assert_eq!(String::from_utf8(output).unwrap(), expected);
+    }
+}
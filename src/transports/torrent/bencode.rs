@@ -0,0 +1,168 @@
+use std::collections::BTreeMap;
+
+use crate::utils::error::{GtrResult, TransportError};
+
+// NOTE: https://www.bittorrent.org/beps/bep_0003.html#bencoding
+// Only what KRPC messages and DHT items need, dictionaries are kept sorted by key as required.
+
+/// Bencoded value
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Value {
+    Int(i64),
+    Bytes(Vec<u8>),
+    List(Vec<Value>),
+    Dict(BTreeMap<Vec<u8>, Value>),
+}
+
+impl Value {
+    /// Dictionary with given string keys
+    pub fn dict<const N: usize>(entries: [(&str, Value); N]) -> Value {
+        Value::Dict(entries.into_iter().map(|(key, value)| (key.as_bytes().to_vec(), value)).collect())
+    }
+
+    pub fn bytes(bytes: &[u8]) -> Value {
+        Value::Bytes(bytes.to_vec())
+    }
+
+    /// Value of dictionary entry
+    pub fn get(&self, key: &str) -> Option<&Value> {
+        match self {
+            Value::Dict(entries) => entries.get(key.as_bytes()),
+            _ => None,
+        }
+    }
+
+    pub fn as_bytes(&self) -> Option<&[u8]> {
+        match self {
+            Value::Bytes(bytes) => Some(bytes),
+            _ => None,
+        }
+    }
+
+    pub fn as_int(&self) -> Option<i64> {
+        match self {
+            Value::Int(int) => Some(*int),
+            _ => None,
+        }
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut encoded = Vec::new();
+        self.write(&mut encoded);
+        encoded
+    }
+
+    fn write(&self, out: &mut Vec<u8>) {
+        match self {
+            Value::Int(int) => out.extend_from_slice(format!("i{int}e").as_bytes()),
+            Value::Bytes(bytes) => {
+                out.extend_from_slice(format!("{}:", bytes.len()).as_bytes());
+                out.extend_from_slice(bytes);
+            },
+            Value::List(values) => {
+                out.push(b'l');
+                values.iter().for_each(|value| value.write(out));
+                out.push(b'e');
+            },
+            Value::Dict(entries) => {
+                out.push(b'd');
+                for (key, value) in entries {
+                    Value::bytes(key).write(out);
+                    value.write(out);
+                }
+                out.push(b'e');
+            },
+        }
+    }
+
+    /// Decodes single value, trailing data is an error
+    pub fn decode(data: &[u8]) -> GtrResult<Value> {
+        let (value, rest) = parse(data)?;
+        if !rest.is_empty() {
+            return Err(TransportError::malformed_message("trailing data after bencoded value"))
+        }
+        Ok(value)
+    }
+
+    /// Decodes value at the start of data, returns it with the rest of data
+    pub fn decode_prefix(data: &[u8]) -> GtrResult<(Value, &[u8])> {
+        parse(data)
+    }
+}
+
+fn parse(data: &[u8]) -> GtrResult<(Value, &[u8])> {
+    match data.first() {
+        Some(b'i') => {
+            let (int, rest) = split_at_byte(&data[1..], b'e')?;
+            Ok((Value::Int(parse_int(int)?), rest))
+        },
+        Some(b'l') => {
+            let mut values = Vec::new();
+            let mut rest = &data[1..];
+            while rest.first() != Some(&b'e') {
+                let (value, remaining) = parse(rest)?;
+                values.push(value);
+                rest = remaining;
+            }
+            Ok((Value::List(values), &rest[1..]))
+        },
+        Some(b'd') => {
+            let mut entries = BTreeMap::new();
+            let mut rest = &data[1..];
+            while rest.first() != Some(&b'e') {
+                let (key, remaining) = match parse(rest)? {
+                    (Value::Bytes(key), remaining) => (key, remaining),
+                    _ => return Err(TransportError::malformed_message("dictionary key is not a string")),
+                };
+                let (value, remaining) = parse(remaining)?;
+                entries.insert(key, value);
+                rest = remaining;
+            }
+            Ok((Value::Dict(entries), &rest[1..]))
+        },
+        Some(b'0'..=b'9') => {
+            let (len, rest) = split_at_byte(data, b':')?;
+            let len = match usize::try_from(parse_int(len)?) {
+                Ok(len) if len <= rest.len() => len,
+                _ => return Err(TransportError::malformed_message("string is longer than data")),
+            };
+            Ok((Value::bytes(&rest[..len]), &rest[len..]))
+        },
+        _ => Err(TransportError::malformed_message("unexpected end of bencoded data")),
+    }
+}
+
+fn split_at_byte(data: &[u8], byte: u8) -> GtrResult<(&[u8], &[u8])> {
+    match data.iter().position(|b| *b == byte) {
+        Some(position) => Ok((&data[..position], &data[position + 1..])),
+        None => Err(TransportError::malformed_message("unterminated bencoded value")),
+    }
+}
+
+fn parse_int(digits: &[u8]) -> GtrResult<i64> {
+    match std::str::from_utf8(digits).ok().and_then(|digits| digits.parse().ok()) {
+        Some(int) => Ok(int),
+        None => Err(TransportError::malformed_message("invalid integer")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encodes_and_decodes_values() {
+        let value = Value::dict([
+            ("y", Value::bytes(b"q")),
+            ("a", Value::dict([("seq", Value::Int(-3)), ("nodes", Value::List(vec![Value::bytes(b"")]))])),
+        ]);
+
+        let encoded = value.encode();
+        assert_eq!(encoded, b"d1:ad5:nodesl0:e3:seqi-3ee1:y1:qe");
+        assert_eq!(Value::decode(&encoded).unwrap(), value);
+
+        assert!(Value::decode(b"5:abc").is_err());
+        assert!(Value::decode(b"i1ei2e").is_err());
+        assert!(Value::decode(b"d1:ae").is_err());
+    }
+}
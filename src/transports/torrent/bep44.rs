@@ -0,0 +1,256 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU16, Ordering};
+use std::time::Duration;
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
+use sha1::{Digest, Sha1};
+use tokio::net::UdpSocket;
+use tokio::time::{timeout_at, Instant};
+
+use crate::transports::torrent::bencode::Value;
+use crate::transports::torrent::krpc::{self, distance, NodeId};
+use crate::utils::error::{GtrResult, TransportError};
+
+// NOTE: https://www.bittorrent.org/beps/bep_0044.html
+// `get` and `put` queries are sent from a separate socket by iterative lookup similar to the one of
+// `get_peers`, the client is a read-only node (BEP 43) as it does not answer queries. Items are
+// stored without salt, so target of an item is SHA-1 of its public key.
+
+/// Size limit of bencoded value of an item
+pub const MAX_VALUE_LEN: usize = 1000;
+// number of closest nodes items are stored on
+const CLOSEST: usize = 8;
+const MAX_ROUNDS: usize = 10;
+const ROUND_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Signed mutable item, newer versions of it have higher `seq`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MutableItem {
+    pub key: [u8; 32],
+    pub seq: i64,
+    pub value: Value,
+    pub signature: [u8; 64],
+}
+
+impl MutableItem {
+    pub fn sign(key: &SigningKey, seq: i64, value: Value) -> GtrResult<Self> {
+        let len = value.encode().len();
+        if len > MAX_VALUE_LEN {
+            return Err(TransportError::item_too_large(len, MAX_VALUE_LEN))
+        }
+
+        let signature = key.sign(&signed_part(seq, &value)).to_bytes();
+        Ok(MutableItem { key: key.verifying_key().to_bytes(), seq, value, signature })
+    }
+
+    pub fn verify(&self) -> bool {
+        match VerifyingKey::from_bytes(&self.key) {
+            Ok(key) => key.verify(&signed_part(self.seq, &self.value), &Signature::from_bytes(&self.signature)).is_ok(),
+            Err(_) => false,
+        }
+    }
+
+    pub fn target(&self) -> [u8; 20] {
+        target(&self.key)
+    }
+
+    /// Item from arguments of `put` query or `get` response
+    pub(crate) fn from_message(arguments: &Value) -> Option<Self> {
+        Some(MutableItem {
+            key: arguments.get("k")?.as_bytes()?.try_into().ok()?,
+            seq: arguments.get("seq")?.as_int()?,
+            value: arguments.get("v")?.clone(),
+            signature: arguments.get("sig")?.as_bytes()?.try_into().ok()?,
+        })
+    }
+}
+
+/// DHT address of items signed with given public key
+pub fn target(key: &[u8; 32]) -> NodeId {
+    Sha1::digest(key).into()
+}
+
+/// Node which answered lookup, it may store items if it gave a token
+struct Responder {
+    addr: SocketAddr,
+    id: NodeId,
+    token: Option<Vec<u8>>,
+    item: Option<MutableItem>,
+}
+
+/// Sends `get` and `put` queries to DHT nodes
+pub struct Client {
+    socket: UdpSocket,
+    id: NodeId,
+    bootstrap: Vec<SocketAddr>,
+    transaction: AtomicU16,
+}
+
+impl Client {
+    /// Client with socket bound to `addr`, lookups start from `bootstrap` nodes
+    pub async fn bind(addr: SocketAddr, bootstrap: Vec<SocketAddr>) -> GtrResult<Self> {
+        let socket = match UdpSocket::bind(addr).await {
+            Ok(socket) => socket,
+            Err(e) => return Err(TransportError::dht_failed(&format!("can not bind {addr}, {e}"))),
+        };
+        let mut id = [0; 20];
+        if let Err(e) = getrandom::getrandom(&mut id) {
+            return Err(TransportError::dht_failed(&format!("can not generate node id, {e}")))
+        }
+
+        Ok(Client { socket, id, bootstrap, transaction: AtomicU16::new(0) })
+    }
+
+    /// Item with the highest `seq` among valid ones stored under `target`
+    pub async fn get(&self, target: &[u8; 20]) -> GtrResult<Option<MutableItem>> {
+        let item = self.lookup(target).await?
+            .into_iter()
+            .filter_map(|responder| responder.item)
+            .filter(|item| item.target().eq(target) && item.verify())
+            .max_by_key(|item| item.seq);
+
+        Ok(item)
+    }
+
+    /// Stores item on nodes closest to its target, returns number of nodes which accepted it
+    pub async fn put(&self, item: &MutableItem) -> GtrResult<usize> {
+        let mut pending = HashMap::new();
+        for responder in self.lookup(&item.target()).await?.iter().filter(|r| r.token.is_some()).take(CLOSEST) {
+            let token = responder.token.as_deref().unwrap_or_default();
+            let arguments = Value::dict([
+                ("id", Value::bytes(&self.id)),
+                ("k", Value::bytes(&item.key)),
+                ("ro", Value::Int(1)),
+                ("seq", Value::Int(item.seq)),
+                ("sig", Value::bytes(&item.signature)),
+                ("token", Value::bytes(token)),
+                ("v", item.value.clone()),
+            ]);
+            pending.insert(self.send(responder.addr, "put", arguments).await, responder.addr);
+        }
+
+        let stored = self.receive(&mut pending).await
+            .iter()
+            .filter(|(_, message)| message.get("y").eq(&Some(&Value::bytes(b"r"))))
+            .count();
+        Ok(stored)
+    }
+
+    /// Queries nodes closer and closer to `target`, responders are ordered by distance to it
+    async fn lookup(&self, target: &[u8; 20]) -> GtrResult<Vec<Responder>> {
+        let mut queried: HashSet<SocketAddr> = HashSet::new();
+        let mut candidates: BTreeMap<[u8; 20], SocketAddr> = BTreeMap::new();
+        let mut responders: Vec<Responder> = Vec::new();
+
+        let mut next = self.bootstrap.clone();
+        for _ in 0..MAX_ROUNDS {
+            if next.is_empty() { break }
+
+            let mut pending = HashMap::new();
+            for addr in next {
+                queried.insert(addr);
+                let arguments = Value::dict([("id", Value::bytes(&self.id)), ("ro", Value::Int(1)), ("target", Value::bytes(target))]);
+                pending.insert(self.send(addr, "get", arguments).await, addr);
+            }
+
+            for (addr, message) in self.receive(&mut pending).await {
+                let response = match message.get("r") {
+                    Some(response) => response,
+                    None => continue,
+                };
+                let id = match krpc::sender_id(response) {
+                    Some(id) => id,
+                    None => continue,
+                };
+                for (node_id, node_addr) in krpc::compact_nodes(response.get("nodes")) {
+                    candidates.insert(distance(&node_id, target), node_addr);
+                }
+                let token = response.get("token").and_then(Value::as_bytes).map(<[u8]>::to_vec);
+                responders.push(Responder { addr, id, token, item: MutableItem::from_message(response) });
+            }
+            responders.sort_by_key(|responder| distance(&responder.id, target));
+
+            // lookup goes on while there are nodes closer than the closest responders
+            let bound = responders.get(CLOSEST - 1).map(|responder| distance(&responder.id, target));
+            next = candidates
+                .iter()
+                .filter(|(node_distance, addr)| !queried.contains(addr) && bound.is_none_or(|bound| **node_distance < bound))
+                .take(CLOSEST)
+                .map(|(_, addr)| *addr)
+                .collect();
+        }
+
+        Ok(responders)
+    }
+
+    /// Sends query, returns its transaction id
+    async fn send(&self, addr: SocketAddr, query: &str, arguments: Value) -> [u8; 2] {
+        let transaction = self.transaction.fetch_add(1, Ordering::Relaxed).to_be_bytes();
+        // unreachable nodes do not answer, the same as ones which are offline
+        let _ = self.socket.send_to(&krpc::query(&transaction, query, arguments).encode(), addr).await;
+        transaction
+    }
+
+    /// Answers to pending queries received before timeout
+    async fn receive(&self, pending: &mut HashMap<[u8; 2], SocketAddr>) -> Vec<(SocketAddr, Value)> {
+        let deadline = Instant::now() + ROUND_TIMEOUT;
+        let mut received = Vec::new();
+        let mut buffer = [0; 1500];
+        while !pending.is_empty() {
+            let (len, addr) = match timeout_at(deadline, self.socket.recv_from(&mut buffer)).await {
+                Ok(Ok(received)) => received,
+                Ok(Err(_)) => continue,
+                Err(_) => break,
+            };
+            let message = match Value::decode(&buffer[..len]) {
+                Ok(message) => message,
+                Err(_) => continue,
+            };
+            if let Some(transaction) = krpc::transaction(&message) {
+                if pending.get(&transaction).eq(&Some(&addr)) {
+                    pending.remove(&transaction);
+                    received.push((addr, message));
+                }
+            }
+        }
+
+        received
+    }
+}
+
+fn signed_part(seq: i64, value: &Value) -> Vec<u8> {
+    let mut signed = format!("3:seqi{seq}e1:v").into_bytes();
+    signed.extend(value.encode());
+    signed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::config_file::{AddressPort, Torrent};
+    use crate::transports::torrent::dht::DhtNode;
+
+    #[tokio::test]
+    async fn puts_and_gets_signed_items() {
+        let key = SigningKey::from_bytes(&[7; 32]);
+        let item = MutableItem::sign(&key, 1, Value::bytes(b"profile")).unwrap();
+        assert!(item.verify());
+        let mut forged = item.clone();
+        forged.seq = 2;
+        assert!(!forged.verify());
+        assert!(MutableItem::sign(&key, 1, Value::Bytes(vec![0; MAX_VALUE_LEN])).is_err());
+
+        // a single node which does not know any other, it stores items anyway
+        let address = |port| AddressPort { addr: String::from("127.0.0.1"), port };
+        let node = DhtNode::builder(&Torrent { router: address(9), bind: address(0) }).start().unwrap();
+        let client = Client::bind("127.0.0.1:0".parse().unwrap(), vec![node.local_addr().unwrap()]).await.unwrap();
+        assert_eq!(client.get(&item.target()).await.unwrap(), None);
+        assert_eq!(client.put(&item).await.unwrap(), 1);
+        assert_eq!(client.get(&item.target()).await.unwrap(), Some(item.clone()));
+
+        let older = MutableItem::sign(&key, 0, Value::bytes(b"older")).unwrap();
+        assert_eq!(client.put(&older).await.unwrap(), 0);
+        assert_eq!(client.get(&item.target()).await.unwrap(), Some(item));
+        node.shutdown();
+    }
+}
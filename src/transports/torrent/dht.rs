@@ -0,0 +1,841 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::io::Write;
+use std::net::{IpAddr, SocketAddr, ToSocketAddrs};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU16, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use serde::{Deserialize, Serialize};
+use sha1::{Digest, Sha1};
+use tokio::net::UdpSocket;
+use tokio::sync::{oneshot, watch};
+use tokio::task::{JoinHandle, JoinSet};
+use tokio::time::{sleep, timeout, Instant};
+
+use crate::config::config_file::{AddressPort, Torrent};
+use crate::config::user_dirs::cache_dir;
+use crate::transports::torrent::bencode::Value;
+use crate::transports::torrent::bep44::{MutableItem, MAX_VALUE_LEN};
+use crate::transports::torrent::krpc::{self, distance, NodeId};
+use crate::transports::torrent::wire::InfoHash;
+use crate::utils::error::{GtrResult, TransportError};
+
+// NOTE: https://www.bittorrent.org/beps/bep_0005.html
+// The node answers queries of other nodes, `get` and `put` of BEP 44 included so that profiles of
+// gtr users are stored by gtr nodes, and runs iterative lookups for announcing and searching info
+// hashes. Each lookup waits only for its own queries, responses are matched to them by
+// transaction id.
+
+// NOTE: routing table is a bucket of up to K nodes for each length of prefix shared with the id
+// of this node. Nodes which answer queries or send them are added while their bucket has room,
+// nodes which do not answer are dropped. Only these nodes are persisted, peers found by lookups
+// are BitTorrent peers and may not run DHT node at all.
+
+const K: usize = 8;
+const MAX_ROUNDS: usize = 10;
+const QUERY_TIMEOUT: Duration = Duration::from_secs(2);
+// bootstrap is retried until some node answers, then routing table is refreshed from time to time
+const BOOTSTRAP_RETRY: Duration = Duration::from_secs(2);
+const REFRESH_INTERVAL: Duration = Duration::from_secs(15 * 60);
+// announces of peers expire after this time, they are repeated by the peers before it
+const PEER_TTL: Duration = Duration::from_secs(30 * 60);
+// tokens are accepted in the interval they were given in and the next one, as by mainline DHT
+const TOKEN_INTERVAL: Duration = Duration::from_secs(5 * 60);
+// BEP 44 values alone are up to 1000 bytes, longer datagrams are dropped rather than read truncated
+const MAX_MESSAGE_LEN: usize = 8 * 1024;
+// limits of what the node stores for others
+const MAX_STORED_HASHES: usize = 1000;
+const MAX_PEERS_PER_HASH: usize = 100;
+const MAX_STORED_ITEMS: usize = 1000;
+const MAX_PERSISTED_NODES: usize = 64;
+const NODES_FILE: &str = "dht_nodes.toml";
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Status {
+    Bootstrapping,
+    Ready,
+    ShutDown,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+struct Nodes {
+    nodes: Vec<SocketAddr>,
+}
+
+/// Node which answered a lookup query
+struct Responder {
+    addr: SocketAddr,
+    id: NodeId,
+    token: Option<Vec<u8>>,
+    peers: Vec<SocketAddr>,
+}
+
+struct RoutingTable {
+    id: NodeId,
+    // index is the length of prefix shared with `id`
+    buckets: Vec<Vec<(NodeId, SocketAddr)>>,
+}
+
+/// What the node knows and stores, shared by its lookups and the task receiving messages
+struct State {
+    table: RoutingTable,
+    // queries waiting for response by transaction id, with the node they were sent to
+    pending: HashMap<[u8; 2], (SocketAddr, oneshot::Sender<Value>)>,
+    peers: HashMap<InfoHash, HashMap<SocketAddr, Instant>>,
+    items: HashMap<NodeId, MutableItem>,
+}
+
+struct Inner {
+    socket: UdpSocket,
+    id: NodeId,
+    read_only: bool,
+    announce_port: u16,
+    // router and persisted nodes, lookups start from them while routing table is empty
+    bootstrap: Vec<SocketAddr>,
+    // tokens given to querying nodes are hashes of it, their address and the token interval
+    secret: [u8; 20],
+    started: Instant,
+    transaction: AtomicU16,
+    state: Mutex<State>,
+}
+
+/// Mainline DHT node with async API for announcing and searching info hashes
+pub struct DhtNode {
+    inner: Arc<Inner>,
+    status: Arc<watch::Sender<Status>>,
+    tasks: Vec<JoinHandle<()>>,
+    nodes_file: Option<PathBuf>,
+}
+
+/// Settings of DHT node, see `DhtNode::builder`
+pub struct DhtNodeBuilder {
+    router: AddressPort,
+    bind: AddressPort,
+    read_only: bool,
+    announce_port: Option<u16>,
+    nodes_file: Option<PathBuf>,
+}
+
+impl DhtNode {
+    /// Node bootstrapping from `router` and listening on `bind` address of the configuration
+    pub fn builder(conf: &Torrent) -> DhtNodeBuilder {
+        DhtNodeBuilder {
+            router: conf.router.clone(),
+            bind: conf.bind.clone(),
+            read_only: false,
+            announce_port: None,
+            nodes_file: None,
+        }
+    }
+
+    /// Waits until node joins the DHT
+    pub async fn bootstrapped(&self) -> GtrResult<()> {
+        let mut status = self.status.subscribe();
+        loop {
+            let current = *status.borrow_and_update();
+            match current {
+                Status::Ready => return Ok(()),
+                Status::ShutDown => return Err(TransportError::dht_failed("node is shut down")),
+                Status::Bootstrapping => {},
+            }
+            if status.changed().await.is_err() {
+                return Err(TransportError::dht_failed("node is shut down"))
+            }
+        }
+    }
+
+    /// Announces this node as a peer of given info hashes, returns after all announces are done
+    pub async fn announce(&self, hashes: &[InfoHash]) -> GtrResult<()> {
+        self.running()?;
+        let mut announces = JoinSet::new();
+        for hash in hashes {
+            let (inner, hash) = (self.inner.clone(), *hash);
+            announces.spawn(async move { (hash, inner.announce(hash).await) });
+        }
+        while let Some(announced) = announces.join_next().await {
+            match announced {
+                Ok((_, stored)) if stored > 0 => continue,
+                Ok((hash, _)) => return Err(TransportError::dht_failed(&format!("no node stored announce of {hash:?}"))),
+                Err(e) => return Err(TransportError::dht_failed(&format!("{e}"))),
+            }
+        }
+        Ok(())
+    }
+
+    /// Addresses of peers announced for given info hash
+    pub async fn search(&self, hash: InfoHash) -> GtrResult<Vec<SocketAddr>> {
+        self.running()?;
+        let target: NodeId = hash.as_ref().try_into().unwrap_or_default();
+        let mut peers: HashSet<SocketAddr> = self.inner.stored_peers(&hash).into_iter().collect();
+        for responder in self.inner.lookup(target, "get_peers").await {
+            peers.extend(responder.peers);
+        }
+        Ok(peers.into_iter().collect())
+    }
+
+    /// Nodes of routing table, or the ones used for bootstrap while it is empty, other DHT clients
+    /// may start from them
+    pub fn nodes(&self) -> Vec<SocketAddr> {
+        self.inner.start_nodes(&self.inner.id)
+    }
+
+    /// Address the node is bound to
+    pub fn local_addr(&self) -> GtrResult<SocketAddr> {
+        match self.inner.socket.local_addr() {
+            Ok(addr) => Ok(addr),
+            Err(e) => Err(TransportError::dht_failed(&format!("{e}"))),
+        }
+    }
+
+    /// Stops the node and persists its routing table
+    pub fn shutdown(&self) {
+        if self.status.send_replace(Status::ShutDown) == Status::ShutDown { return }
+        self.tasks.iter().for_each(JoinHandle::abort);
+        // pending queries fail once their senders are dropped
+        self.inner.state.lock().unwrap().pending.clear();
+        persist(&self.inner, self.nodes_file.as_ref());
+    }
+
+    fn running(&self) -> GtrResult<()> {
+        match *self.status.borrow() {
+            Status::ShutDown => Err(TransportError::dht_failed("node is shut down")),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl Drop for DhtNode {
+    fn drop(&mut self) {
+        self.tasks.iter().for_each(JoinHandle::abort);
+    }
+}
+
+impl DhtNodeBuilder {
+    /// Asks other nodes not to put this one into their routing tables, e.g. behind restrictive NAT
+    ///
+    /// Read-only node does not answer queries (BEP 43).
+    pub fn read_only(mut self, read_only: bool) -> Self {
+        self.read_only = read_only;
+        self
+    }
+
+    /// Port peers connect to for exchanging data, the bind port by default
+    pub fn announce_port(mut self, port: u16) -> Self {
+        self.announce_port = Some(port);
+        self
+    }
+
+    /// File to restore known nodes from and persist them to, see `nodes_path`
+    pub fn nodes_file(mut self, path: PathBuf) -> Self {
+        self.nodes_file = Some(path);
+        self
+    }
+
+    /// Binds node socket and starts joining the DHT, it has to be called within tokio runtime
+    pub fn start(self) -> GtrResult<DhtNode> {
+        let router = socket_addr(&self.router)?;
+        let bind = socket_addr(&self.bind)?;
+        let socket = match std::net::UdpSocket::bind(bind).and_then(|socket| {
+            socket.set_nonblocking(true)?;
+            UdpSocket::from_std(socket)
+        }) {
+            Ok(socket) => socket,
+            Err(e) => return Err(TransportError::dht_failed(&format!("can not start node on {bind}, {e}"))),
+        };
+
+        let mut random = [0; 40];
+        if let Err(e) = getrandom::getrandom(&mut random) {
+            return Err(TransportError::dht_failed(&format!("can not generate node id, {e}")))
+        }
+        let (id, secret) = random.split_at(20);
+        let id: NodeId = id.try_into().unwrap();
+
+        let mut bootstrap = match &self.nodes_file {
+            Some(path) => read_nodes(path),
+            None => vec![],
+        };
+        bootstrap.push(router);
+
+        let inner = Arc::new(Inner {
+            socket,
+            id,
+            read_only: self.read_only,
+            announce_port: self.announce_port.unwrap_or(bind.port()),
+            bootstrap,
+            secret: secret.try_into().unwrap(),
+            started: Instant::now(),
+            transaction: AtomicU16::new(0),
+            state: Mutex::new(State {
+                table: RoutingTable { id, buckets: vec![Vec::new(); 8 * id.len() + 1] },
+                pending: HashMap::new(),
+                peers: HashMap::new(),
+                items: HashMap::new(),
+            }),
+        });
+
+        let status = Arc::new(watch::channel(Status::Bootstrapping).0);
+        let tasks = vec![
+            tokio::spawn(receive(inner.clone())),
+            tokio::spawn(maintain(inner.clone(), status.clone(), self.nodes_file.clone())),
+        ];
+
+        Ok(DhtNode { inner, status, tasks, nodes_file: self.nodes_file })
+    }
+}
+
+impl Inner {
+    /// Sends query and waits for its response, returns its values (`r` dictionary)
+    async fn query(&self, addr: SocketAddr, name: &str, arguments: Value) -> Option<Value> {
+        let mut arguments = arguments;
+        if let Value::Dict(entries) = &mut arguments {
+            entries.insert(b"id".to_vec(), Value::bytes(&self.id));
+            if self.read_only { entries.insert(b"ro".to_vec(), Value::Int(1)); }
+        }
+        let transaction = self.transaction.fetch_add(1, Ordering::Relaxed).to_be_bytes();
+        let (sender, receiver) = oneshot::channel();
+        self.state.lock().unwrap().pending.insert(transaction, (addr, sender));
+
+        // unreachable nodes do not answer, the same as ones which are offline
+        let _ = self.socket.send_to(&krpc::query(&transaction, name, arguments).encode(), addr).await;
+        let answer = timeout(QUERY_TIMEOUT, receiver).await;
+
+        let mut state = self.state.lock().unwrap();
+        state.pending.remove(&transaction);
+        let response = match answer {
+            Ok(Ok(message)) => message,
+            _ => {
+                state.table.remove(&addr);
+                return None
+            },
+        };
+        let values = response.get("r")?;
+        state.table.insert(krpc::sender_id(values)?, addr);
+        Some(values.clone())
+    }
+
+    /// Passes response or error to the query waiting for it
+    fn respond(&self, addr: SocketAddr, message: Value) {
+        let transaction = match krpc::transaction(&message) {
+            Some(transaction) => transaction,
+            None => return,
+        };
+        let mut state = self.state.lock().unwrap();
+        if state.pending.get(&transaction).is_some_and(|(queried, _)| *queried == addr) {
+            if let Some((_, sender)) = state.pending.remove(&transaction) {
+                let _ = sender.send(message);
+            }
+        }
+    }
+
+    /// Queries nodes closer and closer to `target`, responders are ordered by distance to it
+    async fn lookup(self: &Arc<Self>, target: NodeId, name: &'static str) -> Vec<Responder> {
+        let key = if name == "find_node" { "target" } else { "info_hash" };
+        let mut queried: HashSet<SocketAddr> = HashSet::new();
+        let mut candidates: BTreeMap<NodeId, SocketAddr> = BTreeMap::new();
+        let mut responders: Vec<Responder> = Vec::new();
+
+        let mut next = self.start_nodes(&target);
+        for _ in 0..MAX_ROUNDS {
+            if next.is_empty() { break }
+
+            let mut queries = JoinSet::new();
+            for addr in next {
+                queried.insert(addr);
+                let inner = self.clone();
+                let arguments = Value::dict([(key, Value::bytes(&target))]);
+                queries.spawn(async move { (addr, inner.query(addr, name, arguments).await) });
+            }
+
+            while let Some(answered) = queries.join_next().await {
+                let (addr, values) = match answered {
+                    Ok((addr, Some(values))) => (addr, values),
+                    _ => continue,
+                };
+                let id = match krpc::sender_id(&values) {
+                    Some(id) => id,
+                    None => continue,
+                };
+                for (node_id, node_addr) in krpc::compact_nodes(values.get("nodes")) {
+                    if node_id != self.id {
+                        candidates.insert(distance(&node_id, &target), node_addr);
+                    }
+                }
+                let token = values.get("token").and_then(Value::as_bytes).map(<[u8]>::to_vec);
+                let peers = krpc::compact_peers(values.get("values"));
+                responders.push(Responder { addr, id, token, peers });
+            }
+            responders.sort_by_key(|responder| distance(&responder.id, &target));
+
+            // lookup goes on while there are nodes closer than the closest responders
+            let bound = responders.get(K - 1).map(|responder| distance(&responder.id, &target));
+            next = candidates
+                .iter()
+                .filter(|(node_distance, addr)| !queried.contains(addr) && bound.is_none_or(|bound| **node_distance < bound))
+                .take(K)
+                .map(|(_, addr)| *addr)
+                .collect();
+        }
+
+        responders
+    }
+
+    /// Announces the node on nodes closest to `hash`, returns number of nodes which accepted it
+    async fn announce(self: &Arc<Self>, hash: InfoHash) -> usize {
+        let target: NodeId = hash.as_ref().try_into().unwrap_or_default();
+        let mut announces = JoinSet::new();
+        for responder in self.lookup(target, "get_peers").await.into_iter().filter(|r| r.token.is_some()).take(K) {
+            let inner = self.clone();
+            let arguments = Value::dict([
+                ("implied_port", Value::Int(0)),
+                ("info_hash", Value::bytes(&target)),
+                ("port", Value::Int(self.announce_port as i64)),
+                ("token", Value::Bytes(responder.token.unwrap_or_default())),
+            ]);
+            announces.spawn(async move { inner.query(responder.addr, "announce_peer", arguments).await });
+        }
+        let mut stored = 0;
+        while let Some(answered) = announces.join_next().await {
+            if matches!(answered, Ok(Some(_))) { stored += 1; }
+        }
+        stored
+    }
+
+    /// Closest nodes of routing table, the bootstrap ones if it is empty
+    fn start_nodes(&self, target: &NodeId) -> Vec<SocketAddr> {
+        let closest = self.state.lock().unwrap().table.closest(target, K);
+        match closest.is_empty() {
+            true => self.bootstrap.clone(),
+            false => closest.into_iter().map(|(_, addr)| addr).collect(),
+        }
+    }
+
+    fn stored_peers(&self, hash: &InfoHash) -> Vec<SocketAddr> {
+        let state = self.state.lock().unwrap();
+        match state.peers.get(hash) {
+            Some(peers) => peers.iter().filter(|(_, at)| at.elapsed() < PEER_TTL).map(|(addr, _)| *addr).collect(),
+            None => Vec::new(),
+        }
+    }
+
+    /// Token for node at `ip` given at time `at`
+    fn token(&self, ip: IpAddr, at: Instant) -> Vec<u8> {
+        let interval = at.duration_since(self.started).as_secs() / TOKEN_INTERVAL.as_secs();
+        let mut hasher = Sha1::new();
+        hasher.update(self.secret);
+        hasher.update(ip.to_string());
+        hasher.update(interval.to_be_bytes());
+        hasher.finalize().to_vec()
+    }
+
+    /// Checks token presented at time `at` was given to node at `ip` in this or previous interval
+    fn token_valid(&self, ip: IpAddr, token: Option<&[u8]>, at: Instant) -> bool {
+        let previous = at.checked_sub(TOKEN_INTERVAL).filter(|previous| *previous >= self.started);
+        [Some(at), previous].into_iter().flatten().any(|given| token == Some(self.token(ip, given).as_slice()))
+    }
+
+    /// Answers query of another node
+    fn answer(&self, addr: SocketAddr, query: &Value) -> Value {
+        let transaction = query.get("t").cloned().unwrap_or(Value::bytes(b""));
+        let arguments = query.get("a");
+        let (arguments, id) = match arguments.zip(arguments.and_then(krpc::sender_id)) {
+            Some(sender) => sender,
+            None => return krpc::error(&transaction, krpc::PROTOCOL_ERROR, "missing id"),
+        };
+        // a read-only node can not be queried, so it does not belong to routing table
+        if arguments.get("ro").and_then(Value::as_int) != Some(1) {
+            self.state.lock().unwrap().table.insert(id, addr);
+        }
+        let target = |key: &str| arguments.get(key).and_then(Value::as_bytes).and_then(|t| NodeId::try_from(t).ok());
+        let now = Instant::now();
+        let token_valid = self.token_valid(addr.ip(), arguments.get("token").and_then(Value::as_bytes), now);
+
+        let mut values = BTreeMap::from([(b"id".to_vec(), Value::bytes(&self.id))]);
+        let mut state = self.state.lock().unwrap();
+        match query.get("q").and_then(Value::as_bytes).unwrap_or_default() {
+            b"ping" => {},
+            b"find_node" => match target("target") {
+                Some(target) => { values.insert(b"nodes".to_vec(), krpc::encode_nodes(&state.table.closest(&target, K))); },
+                None => return krpc::error(&transaction, krpc::PROTOCOL_ERROR, "missing target"),
+            },
+            b"get_peers" => match target("info_hash") {
+                Some(hash) => {
+                    values.insert(b"nodes".to_vec(), krpc::encode_nodes(&state.table.closest(&hash, K)));
+                    values.insert(b"token".to_vec(), Value::Bytes(self.token(addr.ip(), now)));
+                    let peers: Vec<SocketAddr> = state.peers
+                        .get(&InfoHash::from(hash))
+                        .map(|peers| peers.iter().filter(|(_, at)| at.elapsed() < PEER_TTL).map(|(addr, _)| *addr).collect())
+                        .unwrap_or_default();
+                    if !peers.is_empty() { values.insert(b"values".to_vec(), krpc::encode_peers(&peers)); }
+                },
+                None => return krpc::error(&transaction, krpc::PROTOCOL_ERROR, "missing info_hash"),
+            },
+            b"announce_peer" => {
+                let hash = match target("info_hash") {
+                    Some(hash) => InfoHash::from(hash),
+                    None => return krpc::error(&transaction, krpc::PROTOCOL_ERROR, "missing info_hash"),
+                };
+                let port = match arguments.get("implied_port").and_then(Value::as_int) {
+                    Some(1) => Some(addr.port()),
+                    _ => arguments.get("port").and_then(Value::as_int).and_then(|port| u16::try_from(port).ok()),
+                };
+                let port = match (token_valid, port) {
+                    (true, Some(port)) => port,
+                    (false, _) => return krpc::error(&transaction, krpc::PROTOCOL_ERROR, "bad token"),
+                    (true, None) => return krpc::error(&transaction, krpc::PROTOCOL_ERROR, "missing port"),
+                };
+                if !state.store_peer(hash, SocketAddr::new(addr.ip(), port)) {
+                    return krpc::error(&transaction, krpc::GENERIC_ERROR, "storage is full")
+                }
+            },
+            b"get" => match target("target") {
+                Some(target) => {
+                    values.insert(b"nodes".to_vec(), krpc::encode_nodes(&state.table.closest(&target, K)));
+                    values.insert(b"token".to_vec(), Value::Bytes(self.token(addr.ip(), now)));
+                    let newer = |item: &&MutableItem| arguments.get("seq").and_then(Value::as_int).is_none_or(|seq| item.seq > seq);
+                    if let Some(item) = state.items.get(&target).filter(newer) {
+                        values.insert(b"k".to_vec(), Value::bytes(&item.key));
+                        values.insert(b"seq".to_vec(), Value::Int(item.seq));
+                        values.insert(b"sig".to_vec(), Value::bytes(&item.signature));
+                        values.insert(b"v".to_vec(), item.value.clone());
+                    }
+                },
+                None => return krpc::error(&transaction, krpc::PROTOCOL_ERROR, "missing target"),
+            },
+            // NOTE: items with salt are not stored, gtr profiles do not use it
+            b"put" => match MutableItem::from_message(arguments) {
+                _ if !token_valid => return krpc::error(&transaction, krpc::PROTOCOL_ERROR, "bad token"),
+                _ if arguments.get("salt").is_some() => return krpc::error(&transaction, krpc::PROTOCOL_ERROR, "salt is not supported"),
+                Some(item) if item.value.encode().len() > MAX_VALUE_LEN => return krpc::error(&transaction, 205, "message too big"),
+                Some(item) if !item.verify() => return krpc::error(&transaction, 206, "invalid signature"),
+                Some(item) if state.items.get(&item.target()).is_some_and(|stored| stored.seq > item.seq) => {
+                    return krpc::error(&transaction, 302, "sequence number less than current")
+                },
+                Some(item) => if !state.store_item(item) {
+                    return krpc::error(&transaction, krpc::GENERIC_ERROR, "storage is full")
+                },
+                None => return krpc::error(&transaction, krpc::PROTOCOL_ERROR, "not a mutable item"),
+            },
+            _ => return krpc::error(&transaction, krpc::METHOD_UNKNOWN, "method unknown"),
+        }
+        krpc::response(&transaction, Value::Dict(values))
+    }
+}
+
+impl State {
+    fn store_peer(&mut self, hash: InfoHash, peer: SocketAddr) -> bool {
+        let now = Instant::now();
+        self.peers.retain(|_, peers| {
+            peers.retain(|_, at| now.duration_since(*at) < PEER_TTL);
+            !peers.is_empty()
+        });
+        if !self.peers.contains_key(&hash) && self.peers.len() >= MAX_STORED_HASHES { return false }
+
+        let peers = self.peers.entry(hash).or_default();
+        if !peers.contains_key(&peer) && peers.len() >= MAX_PEERS_PER_HASH { return false }
+        peers.insert(peer, now);
+        true
+    }
+
+    fn store_item(&mut self, item: MutableItem) -> bool {
+        let target = item.target();
+        if !self.items.contains_key(&target) && self.items.len() >= MAX_STORED_ITEMS { return false }
+        self.items.insert(target, item);
+        true
+    }
+}
+
+impl RoutingTable {
+    fn bucket(&self, id: &NodeId) -> usize {
+        let distance = distance(&self.id, id);
+        match distance.iter().position(|byte| *byte != 0) {
+            Some(i) => 8 * i + distance[i].leading_zeros() as usize,
+            None => 8 * distance.len(),
+        }
+    }
+
+    /// Adds node if its bucket has room, known node is moved to the end of its bucket
+    fn insert(&mut self, id: NodeId, addr: SocketAddr) {
+        if id == self.id { return }
+        self.remove(&addr);
+        let bucket = self.bucket(&id);
+        let nodes = &mut self.buckets[bucket];
+        nodes.retain(|(known, _)| *known != id);
+        if nodes.len() < K {
+            nodes.push((id, addr));
+        }
+    }
+
+    fn remove(&mut self, addr: &SocketAddr) {
+        self.buckets.iter_mut().for_each(|nodes| nodes.retain(|(_, known)| known != addr));
+    }
+
+    fn closest(&self, target: &NodeId, count: usize) -> Vec<(NodeId, SocketAddr)> {
+        let mut nodes: Vec<(NodeId, SocketAddr)> = self.buckets.iter().flatten().copied().collect();
+        nodes.sort_by_key(|(id, _)| distance(id, target));
+        nodes.truncate(count);
+        nodes
+    }
+}
+
+/// Answers queries and passes responses to lookups waiting for them
+async fn receive(inner: Arc<Inner>) {
+    // one byte more than accepted, so that longer datagrams are told apart from the longest ones
+    let mut buffer = [0; MAX_MESSAGE_LEN + 1];
+    loop {
+        let (len, addr) = match inner.socket.recv_from(&mut buffer).await {
+            Ok((len, _)) if len > MAX_MESSAGE_LEN => continue,
+            Ok(received) => received,
+            Err(_) => continue,
+        };
+        let message = match Value::decode(&buffer[..len]) {
+            Ok(message) => message,
+            Err(_) => continue,
+        };
+        match message.get("y").and_then(Value::as_bytes) {
+            Some(b"q") if !inner.read_only => {
+                let answer = inner.answer(addr, &message);
+                let _ = inner.socket.send_to(&answer.encode(), addr).await;
+            },
+            Some(b"r") | Some(b"e") => inner.respond(addr, message),
+            _ => continue,
+        }
+    }
+}
+
+/// Joins the DHT by looking up own id, repeats it while no node answers and later to refresh
+/// routing table
+async fn maintain(inner: Arc<Inner>, status: Arc<watch::Sender<Status>>, nodes_file: Option<PathBuf>) {
+    loop {
+        inner.lookup(inner.id, "find_node").await;
+        if inner.state.lock().unwrap().table.closest(&inner.id, 1).is_empty() {
+            sleep(BOOTSTRAP_RETRY).await;
+            continue
+        }
+
+        status.send_if_modified(|status| {
+            let bootstrapped = *status == Status::Bootstrapping;
+            if bootstrapped { *status = Status::Ready; }
+            bootstrapped
+        });
+        persist(&inner, nodes_file.as_ref());
+        sleep(REFRESH_INTERVAL).await;
+    }
+}
+
+fn persist(inner: &Inner, nodes_file: Option<&PathBuf>) {
+    let path = match nodes_file {
+        Some(path) => path,
+        None => return,
+    };
+    let nodes: Vec<SocketAddr> = inner.state.lock().unwrap()
+        .table
+        .closest(&inner.id, MAX_PERSISTED_NODES)
+        .into_iter()
+        .map(|(_, addr)| addr)
+        .collect();
+    // losing known nodes only makes next bootstrap slower, an empty table would lose them as well
+    if !nodes.is_empty() {
+        let _ = write_nodes(path, nodes);
+    }
+}
+
+/// Default location of persisted nodes, shared by all repositories of the user
+pub fn nodes_path() -> Option<PathBuf> {
+    cache_dir().map(|dir| dir.join(NODES_FILE))
+}
+
+/// Resolves configured address, host names are allowed
+pub fn socket_addr(address: &AddressPort) -> GtrResult<SocketAddr> {
+    let resolved = (address.addr.as_str(), address.port).to_socket_addrs();
+    match resolved.ok().and_then(|mut addrs| addrs.next()) {
+        Some(addr) => Ok(addr),
+        None => Err(TransportError::invalid_address(&format!("{}:{}", address.addr, address.port))),
+    }
+}
+
+fn read_nodes(path: &Path) -> Vec<SocketAddr> {
+    std::fs::read_to_string(path)
+        .ok()
+        .and_then(|data| toml::from_str::<Nodes>(&data).ok())
+        .unwrap_or_default()
+        .nodes
+}
+
+/// Replaces persisted nodes, they are written aside and renamed so that readers never see them
+/// partially written
+fn write_nodes(path: &Path, nodes: Vec<SocketAddr>) -> std::io::Result<()> {
+    // every node of every process persists to the same file, each one writes its own copy
+    static WRITES: AtomicUsize = AtomicUsize::new(0);
+
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    let content = toml::to_string(&Nodes { nodes }).unwrap_or_default();
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(format!(".{}.{}.new", std::process::id(), WRITES.fetch_add(1, Ordering::Relaxed)));
+    let written = path.with_file_name(name);
+
+    let mut file = std::fs::File::options().write(true).create_new(true).open(&written)?;
+    let replaced = file.write_all(content.as_bytes())
+        .and_then(|_| file.sync_all())
+        .and_then(|_| std::fs::rename(&written, path));
+    if replaced.is_err() {
+        let _ = std::fs::remove_file(&written);
+    }
+    replaced
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(router: u16) -> Torrent {
+        let address = |port| AddressPort { addr: String::from("127.0.0.1"), port };
+        Torrent { router: address(router), bind: address(0) }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn finds_announced_peers_without_persisting_them() {
+        // the router bootstraps from a node which never answers, it becomes ready once others join
+        let router = DhtNode::builder(&config(9)).start().unwrap();
+        let router_port = router.local_addr().unwrap().port();
+        let nodes_file = std::env::temp_dir().join(format!("gtr-dht-nodes-{}.toml", std::process::id()));
+        let announcer = DhtNode::builder(&config(router_port)).announce_port(7).start().unwrap();
+        let searcher = DhtNode::builder(&config(router_port)).nodes_file(nodes_file.clone()).start().unwrap();
+        for node in [&announcer, &searcher] {
+            timeout(Duration::from_secs(30), node.bootstrapped()).await.unwrap().unwrap();
+        }
+
+        let hash = InfoHash::from([5; 20]);
+        announcer.announce(&[hash]).await.unwrap();
+        // concurrent searches for the same hash get all peers each
+        let (first, second) = tokio::join!(searcher.search(hash), searcher.search(hash));
+        let peer: SocketAddr = "127.0.0.1:7".parse().unwrap();
+        assert_eq!(first.unwrap(), [peer]);
+        assert_eq!(second.unwrap(), [peer]);
+
+        searcher.shutdown();
+        let persisted = read_nodes(&nodes_file);
+        let _ = std::fs::remove_file(&nodes_file);
+        let name = nodes_file.file_name().unwrap().to_string_lossy().to_string();
+        let left: Vec<_> = std::fs::read_dir(std::env::temp_dir())
+            .unwrap()
+            .filter_map(Result::ok)
+            .filter(|entry| entry.file_name().to_string_lossy().starts_with(&name))
+            .collect();
+        assert!(left.is_empty());
+        assert!(persisted.contains(&router.local_addr().unwrap()));
+        assert!(!persisted.contains(&peer));
+        assert!(searcher.search(hash).await.is_err());
+    }
+
+    /// Sends query to node, returns its answer
+    async fn ask(socket: &UdpSocket, node: &DhtNode, name: &str, arguments: Value) -> Value {
+        let message = krpc::query(b"aa", name, arguments).encode();
+        socket.send_to(&message, node.local_addr().unwrap()).await.unwrap();
+        let mut buffer = [0; MAX_MESSAGE_LEN];
+        let (len, _) = timeout(QUERY_TIMEOUT, socket.recv_from(&mut buffer)).await.unwrap().unwrap();
+        Value::decode(&buffer[..len]).unwrap()
+    }
+
+    fn error_code(answer: &Value) -> Option<i64> {
+        match answer.get("e") {
+            Some(Value::List(error)) => error.first().and_then(Value::as_int),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn keeps_answering_nodes_in_buckets_by_distance() {
+        let mut table = RoutingTable { id: [0; 20], buckets: vec![Vec::new(); 8 * 20 + 1] };
+        let addr = |port| SocketAddr::from(([127, 0, 0, 1], port));
+        let id = |first: u8, last: u8| { let mut id = [0; 20]; id[0] = first; id[19] = last; id };
+
+        table.insert([0; 20], addr(1));
+        assert!(table.closest(&[0; 20], K).is_empty());
+
+        // all ids starting with 1 bit share no prefix with the table id, they fill the same bucket
+        for i in 0..=K as u8 {
+            table.insert(id(0x80, i), addr(100 + i as u16));
+        }
+        assert_eq!(table.bucket(&id(0x80, 0)), 0);
+        assert_eq!(table.buckets[0].len(), K);
+        assert!(!table.buckets[0].contains(&(id(0x80, K as u8), addr(100 + K as u16))));
+
+        // a known node which changed its address is kept once, with the new address
+        table.insert(id(0x80, 0), addr(200));
+        assert_eq!(table.buckets[0].len(), K);
+        assert_eq!(table.buckets[0].last(), Some(&(id(0x80, 0), addr(200))));
+
+        table.insert(id(0x01, 0), addr(300));
+        assert_eq!(table.bucket(&id(0x01, 0)), 7);
+        assert_eq!(table.closest(&[0; 20], 1), [(id(0x01, 0), addr(300))]);
+
+        // nodes which do not answer are dropped
+        table.remove(&addr(300));
+        assert_eq!(table.closest(&[0; 20], 1), [(id(0x80, 0), addr(200))]);
+    }
+
+    #[tokio::test]
+    async fn accepts_tokens_of_current_and_previous_interval() {
+        let node = DhtNode::builder(&config(9)).start().unwrap();
+        let (inner, ip) = (&node.inner, IpAddr::from([127, 0, 0, 1]));
+        let given = inner.started + TOKEN_INTERVAL / 2;
+        let token = inner.token(ip, given);
+
+        assert!(inner.token_valid(ip, Some(&token), given));
+        assert!(inner.token_valid(ip, Some(&token), given + TOKEN_INTERVAL));
+        assert!(!inner.token_valid(ip, Some(&token), given + 2 * TOKEN_INTERVAL));
+        assert!(!inner.token_valid(IpAddr::from([127, 0, 0, 2]), Some(&token), given));
+        assert!(!inner.token_valid(ip, None, given));
+    }
+
+    #[tokio::test]
+    async fn answers_invalid_queries_with_errors() {
+        let node = DhtNode::builder(&config(9)).start().unwrap();
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let id = || ("id", Value::bytes(&[1; 20]));
+
+        let answer = ask(&socket, &node, "ping", Value::dict([])).await;
+        assert_eq!(error_code(&answer), Some(krpc::PROTOCOL_ERROR));
+        let answer = ask(&socket, &node, "vote", Value::dict([id()])).await;
+        assert_eq!(error_code(&answer), Some(krpc::METHOD_UNKNOWN));
+        let answer = ask(&socket, &node, "get_peers", Value::dict([id()])).await;
+        assert_eq!(error_code(&answer), Some(krpc::PROTOCOL_ERROR));
+
+        let announce = |token: &[u8]| Value::dict([
+            id(),
+            ("info_hash", Value::bytes(&[5; 20])),
+            ("port", Value::Int(7)),
+            ("token", Value::bytes(token)),
+        ]);
+        let answer = ask(&socket, &node, "announce_peer", announce(b"forged")).await;
+        assert_eq!(error_code(&answer), Some(krpc::PROTOCOL_ERROR));
+
+        // the token of `get_peers` response lets the node announce itself
+        let answer = ask(&socket, &node, "get_peers", Value::dict([id(), ("info_hash", Value::bytes(&[5; 20]))])).await;
+        let token = answer.get("r").and_then(|r| r.get("token")).and_then(Value::as_bytes).unwrap().to_vec();
+        let answer = ask(&socket, &node, "announce_peer", announce(&token)).await;
+        assert_eq!(error_code(&answer), None);
+        assert_eq!(node.inner.stored_peers(&InfoHash::from([5; 20])), ["127.0.0.1:7".parse().unwrap()]);
+    }
+
+    #[tokio::test]
+    async fn ignores_malformed_and_oversized_datagrams() {
+        let node = DhtNode::builder(&config(9)).start().unwrap();
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let addr = node.local_addr().unwrap();
+
+        socket.send_to(b"d1:ad2:id", addr).await.unwrap();
+        socket.send_to(b"li1ee", addr).await.unwrap();
+        // a valid query padded past the limit would be answered if it was read truncated
+        let mut oversized = krpc::query(b"zz", "ping", Value::dict([("id", Value::bytes(&[1; 20]))])).encode();
+        oversized.resize(MAX_MESSAGE_LEN + 1, b'0');
+        socket.send_to(&oversized, addr).await.unwrap();
+
+        // the first answer is the one of the valid query sent after them
+        let answer = ask(&socket, &node, "ping", Value::dict([("id", Value::bytes(&[1; 20]))])).await;
+        assert_eq!(krpc::transaction(&answer), Some(*b"aa"));
+        assert!(answer.get("r").and_then(krpc::sender_id).is_some());
+    }
+}
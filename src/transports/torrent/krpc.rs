@@ -0,0 +1,126 @@
+use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4};
+
+use crate::transports::torrent::bencode::Value;
+
+// NOTE: https://www.bittorrent.org/beps/bep_0005.html#krpc-protocol
+// Messages of DHT nodes are bencoded dictionaries sent over UDP: queries, responses and errors,
+// all carrying the transaction id chosen by the querying node. Only IPv4 nodes and peers are
+// encoded, the same as by mainline DHT.
+
+/// Node ids, info hashes and item targets share one 160 bit key space
+pub type NodeId = [u8; 20];
+
+// compact node info: id, IPv4 address and port
+const NODE_INFO_LEN: usize = 26;
+// compact peer info: IPv4 address and port
+const PEER_INFO_LEN: usize = 6;
+
+pub const GENERIC_ERROR: i64 = 201;
+pub const PROTOCOL_ERROR: i64 = 203;
+pub const METHOD_UNKNOWN: i64 = 204;
+
+pub fn query(transaction: &[u8; 2], name: &str, arguments: Value) -> Value {
+    Value::dict([
+        ("a", arguments),
+        ("q", Value::bytes(name.as_bytes())),
+        ("t", Value::bytes(transaction)),
+        ("y", Value::bytes(b"q")),
+    ])
+}
+
+pub fn response(transaction: &Value, values: Value) -> Value {
+    Value::dict([("r", values), ("t", transaction.clone()), ("y", Value::bytes(b"r"))])
+}
+
+pub fn error(transaction: &Value, code: i64, message: &str) -> Value {
+    let error = Value::List(vec![Value::Int(code), Value::bytes(message.as_bytes())]);
+    Value::dict([("e", error), ("t", transaction.clone()), ("y", Value::bytes(b"e"))])
+}
+
+/// Transaction id of message, gtr always uses two bytes
+pub fn transaction(message: &Value) -> Option<[u8; 2]> {
+    message.get("t").and_then(Value::as_bytes).and_then(|t| t.try_into().ok())
+}
+
+/// Id of node sending query or response
+pub fn sender_id(message: &Value) -> Option<NodeId> {
+    message.get("id").and_then(Value::as_bytes).and_then(|id| id.try_into().ok())
+}
+
+pub fn compact_nodes(nodes: Option<&Value>) -> Vec<(NodeId, SocketAddr)> {
+    let nodes = nodes.and_then(Value::as_bytes).unwrap_or_default();
+    nodes
+        .chunks_exact(NODE_INFO_LEN)
+        .map(|node| {
+            let mut id = [0; 20];
+            id.copy_from_slice(&node[..20]);
+            (id, compact_addr(&node[20..]))
+        })
+        .collect()
+}
+
+pub fn encode_nodes(nodes: &[(NodeId, SocketAddr)]) -> Value {
+    let encoded = nodes
+        .iter()
+        .filter_map(|(id, addr)| encode_addr(addr).map(|addr| [&id[..], &addr].concat()))
+        .collect::<Vec<_>>()
+        .concat();
+    Value::Bytes(encoded)
+}
+
+/// Peers of `get_peers` response, a list of compact peer infos
+pub fn compact_peers(values: Option<&Value>) -> Vec<SocketAddr> {
+    let values = match values {
+        Some(Value::List(values)) => values,
+        _ => return Vec::new(),
+    };
+    values
+        .iter()
+        .filter_map(Value::as_bytes)
+        .filter(|peer| peer.len() == PEER_INFO_LEN)
+        .map(compact_addr)
+        .collect()
+}
+
+pub fn encode_peers(peers: &[SocketAddr]) -> Value {
+    Value::List(peers.iter().filter_map(encode_addr).map(|peer| Value::Bytes(peer.to_vec())).collect())
+}
+
+pub fn distance(a: &NodeId, b: &NodeId) -> NodeId {
+    let mut distance = [0; 20];
+    distance.iter_mut().zip(a.iter().zip(b)).for_each(|(d, (a, b))| *d = a ^ b);
+    distance
+}
+
+fn compact_addr(info: &[u8]) -> SocketAddr {
+    let ip = Ipv4Addr::new(info[0], info[1], info[2], info[3]);
+    let port = u16::from_be_bytes([info[4], info[5]]);
+    SocketAddr::V4(SocketAddrV4::new(ip, port))
+}
+
+fn encode_addr(addr: &SocketAddr) -> Option<[u8; PEER_INFO_LEN]> {
+    match addr {
+        SocketAddr::V4(addr) => {
+            let [a, b, c, d] = addr.ip().octets();
+            let [high, low] = addr.port().to_be_bytes();
+            Some([a, b, c, d, high, low])
+        },
+        SocketAddr::V6(_) => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encodes_compact_nodes_and_peers() {
+        let addr: SocketAddr = "127.0.0.1:6881".parse().unwrap();
+        let nodes = [([1; 20], addr), ([2; 20], "[::1]:6881".parse().unwrap())];
+
+        let encoded = encode_nodes(&nodes);
+        assert_eq!(encoded.as_bytes().unwrap().len(), NODE_INFO_LEN);
+        assert_eq!(compact_nodes(Some(&encoded)), [([1; 20], addr)]);
+        assert_eq!(compact_peers(Some(&encode_peers(&[addr]))), [addr]);
+    }
+}
@@ -0,0 +1,255 @@
+use std::collections::HashSet;
+use std::io::SeekFrom;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use sha1::{Digest, Sha1};
+use tokio::fs::{File, OpenOptions};
+use tokio::io::{AsyncSeekExt, AsyncWriteExt};
+use tokio::sync::mpsc::{unbounded_channel, UnboundedSender};
+use tokio::task::JoinSet;
+use tokio::time::timeout;
+
+use crate::transports::Progress;
+use crate::transports::torrent::metainfo::Info;
+use crate::transports::torrent::ut_metadata::{self, MetadataMessage, METADATA_PIECE_LEN};
+use crate::transports::torrent::wire::{block_request, parse_block, Connection, InfoHash, Message, PeerId, BLOCK_LEN, CHOKE, INTERESTED, PIECE, REQUEST, UNCHOKE};
+use crate::utils::error::{GtrResult, TransportError};
+
+// NOTE: https://www.bittorrent.org/beps/bep_0003.html#peer-messages
+// Metadata is taken from the first peer which has it, pieces are spread over all peers: each of
+// them takes the next missing piece once it has received the previous one, pieces of failed peers
+// are left to the others.
+
+// metadata of packs is small, 20 bytes per piece of 256 KiB
+const MAX_METADATA_LEN: usize = 1024 * 1024;
+const METADATA_TIMEOUT: Duration = Duration::from_secs(30);
+const PIECE_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Downloads torrent `info_hash` from `peers` into file at `path`, returns its info dictionary
+pub async fn download(
+    peers: &[SocketAddr],
+    peer_id: PeerId,
+    info_hash: InfoHash,
+    path: &PathBuf,
+    progress: &mut Progress<'_>) -> GtrResult<Info>
+{
+    let mut connecting = JoinSet::new();
+    for addr in peers {
+        connecting.spawn(Connection::connect(*addr, info_hash, peer_id));
+    }
+    let mut connected = Vec::new();
+    while let Some(joined) = connecting.join_next().await {
+        if let Ok(Ok(connection)) = joined {
+            connected.push(Peer { connection, choked: true });
+        }
+    }
+
+    let mut info = None;
+    for peer in connected.iter_mut() {
+        if let Ok(Ok(metadata)) = timeout(METADATA_TIMEOUT, peer.metadata()).await {
+            if InfoHash::from(<[u8; 20]>::from(Sha1::digest(&metadata))) == info_hash {
+                info = Info::decode(&metadata).ok();
+            }
+        }
+        if info.is_some() { break }
+    }
+    let info = match info {
+        Some(info) => Arc::new(info),
+        None => return Err(TransportError::transport_failed(&format!("no peer sent metadata of {info_hash:?}"))),
+    };
+
+    let created = async {
+        let file = File::create(path).await?;
+        file.set_len(info.length).await
+    };
+    if let Err(e) = created.await {
+        return Err(TransportError::transport_failed(&format!("can not create {}, {e}", path.display())))
+    }
+
+    // popped from the end, pieces are requested in order
+    let missing = Arc::new(Mutex::new((0..info.pieces.len()).rev().collect::<Vec<usize>>()));
+    let (received, mut receiving) = unbounded_channel();
+    let mut downloading = JoinSet::new();
+    for peer in connected {
+        let (info, missing, received, path) = (info.clone(), missing.clone(), received.clone(), path.clone());
+        downloading.spawn(async move { peer.download(&info, &missing, &received, &path).await });
+    }
+    drop(received);
+
+    let mut pieces = 0;
+    while receiving.recv().await.is_some() {
+        pieces += 1;
+        progress(&format!("Receiving pack {}: {pieces}/{}\r", info.name, info.pieces.len()));
+    }
+    while downloading.join_next().await.is_some() {}
+    if pieces < info.pieces.len() {
+        return Err(TransportError::transport_failed(&format!("received {pieces} of {} pieces of {}", info.pieces.len(), info.name)))
+    }
+    progress("\n");
+
+    Ok(Arc::unwrap_or_clone(info))
+}
+
+/// Connected peer, it sends pieces only while it does not choke
+struct Peer {
+    connection: Connection,
+    choked: bool,
+}
+
+impl Peer {
+    async fn receive(&mut self) -> GtrResult<Message> {
+        let message = self.connection.receive().await?;
+        match message {
+            Message::Other(CHOKE, _) => self.choked = true,
+            Message::Other(UNCHOKE, _) => self.choked = false,
+            _ => {},
+        }
+        Ok(message)
+    }
+
+    async fn metadata(&mut self) -> GtrResult<Vec<u8>> {
+        let size = match self.connection.metadata_size {
+            Some(size) if size <= MAX_METADATA_LEN && self.connection.supports(ut_metadata::EXTENSION) => size,
+            _ => return Err(TransportError::transport_failed(&format!("{} does not send metadata", self.connection.remote))),
+        };
+
+        let mut metadata = Vec::with_capacity(size);
+        for piece in 0..size.div_ceil(METADATA_PIECE_LEN) {
+            self.connection.send_extended(ut_metadata::EXTENSION, &MetadataMessage::Request(piece).encode()).await?;
+            loop {
+                let payload = match self.receive().await? {
+                    Message::Extended(name, payload) if name == ut_metadata::EXTENSION => payload,
+                    _ => continue,
+                };
+                match MetadataMessage::decode(&payload)? {
+                    MetadataMessage::Data(received, _, data) if received == piece => {
+                        metadata.extend(data);
+                        break
+                    },
+                    MetadataMessage::Reject(rejected) if rejected == piece => {
+                        return Err(TransportError::transport_failed(&format!("{} rejected metadata", self.connection.remote)))
+                    },
+                    _ => continue,
+                }
+            }
+        }
+        if metadata.len() != size {
+            return Err(TransportError::malformed_message(&format!("metadata of {} bytes instead of {size}", metadata.len())))
+        }
+        Ok(metadata)
+    }
+
+    /// Downloads missing pieces and writes them into file until there are none or peer fails
+    async fn download(
+        mut self,
+        info: &Info,
+        missing: &Mutex<Vec<usize>>,
+        received: &UnboundedSender<usize>,
+        path: &PathBuf) -> GtrResult<()>
+    {
+        let mut file = match OpenOptions::new().write(true).open(path).await {
+            Ok(file) => file,
+            Err(e) => return Err(TransportError::transport_failed(&format!("can not open {}, {e}", path.display()))),
+        };
+        self.connection.send(INTERESTED, &[]).await?;
+
+        loop {
+            let index = match missing.lock().unwrap().pop() {
+                Some(index) => index,
+                None => return Ok(()),
+            };
+            let data = match timeout(PIECE_TIMEOUT, self.piece(info, index)).await {
+                Ok(Ok(data)) => data,
+                Ok(Err(e)) => {
+                    missing.lock().unwrap().push(index);
+                    return Err(e)
+                },
+                Err(_) => {
+                    missing.lock().unwrap().push(index);
+                    return Err(TransportError::transport_failed(&format!("{} did not send piece {index}", self.connection.remote)))
+                },
+            };
+
+            let written = async {
+                file.seek(SeekFrom::Start(index as u64 * info.piece_length)).await?;
+                file.write_all(&data).await
+            };
+            if let Err(e) = written.await {
+                missing.lock().unwrap().push(index);
+                return Err(TransportError::transport_failed(&format!("can not write {}, {e}", path.display())))
+            }
+            let _ = received.send(index);
+        }
+    }
+
+    /// Requests all blocks of piece at once, the piece is verified against its hash
+    async fn piece(&mut self, info: &Info, index: usize) -> GtrResult<Vec<u8>> {
+        while self.choked {
+            self.receive().await?;
+        }
+
+        let size = match u32::try_from(info.piece_size(index)) {
+            Ok(size) => size,
+            Err(_) => return Err(TransportError::malformed_message(&format!("piece {index} of {} is too large", info.name))),
+        };
+        let mut data = vec![0; size as usize];
+        let mut pending = HashSet::new();
+        for begin in (0..size).step_by(BLOCK_LEN as usize) {
+            self.connection.send(REQUEST, &block_request(index as u32, begin, BLOCK_LEN.min(size - begin))).await?;
+            pending.insert(begin);
+        }
+
+        while !pending.is_empty() {
+            match self.receive().await? {
+                Message::Other(PIECE, payload) => {
+                    let (piece, begin, block) = parse_block(&payload)?;
+                    let end = begin as usize + block.len();
+                    if piece as usize == index && end <= data.len() && pending.remove(&begin) {
+                        data[begin as usize..end].copy_from_slice(block);
+                    }
+                },
+                Message::Other(CHOKE, _) => {
+                    return Err(TransportError::transport_failed(&format!("{} choked during piece {index}", self.connection.remote)))
+                },
+                _ => continue,
+            }
+        }
+
+        if <[u8; 20]>::from(Sha1::digest(&data)) != info.pieces[index] {
+            return Err(TransportError::malformed_message(&format!("piece {index} from {} does not match its hash", self.connection.remote)))
+        }
+        Ok(data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transports::torrent::metainfo::PIECE_LENGTH;
+    use crate::transports::torrent::ut_gittorrent::PackServer;
+
+    #[tokio::test]
+    async fn downloads_pack_seeded_by_peer() {
+        let dir = std::env::temp_dir().join(format!("gtr-leech-{}", std::process::id()));
+        tokio::fs::create_dir_all(&dir).await.unwrap();
+        let (seeded, downloaded) = (dir.join("seeded.pack"), dir.join("downloaded.pack"));
+        let content: Vec<u8> = (0..PIECE_LENGTH as usize + BLOCK_LEN as usize + 1).map(|i| (i % 251) as u8).collect();
+        tokio::fs::write(&seeded, &content).await.unwrap();
+
+        let server = Arc::new(PackServer::new(PeerId::from([1; 20])));
+        let info_hash = server.seed(seeded.clone(), Info::from_file(&seeded, "seeded.pack").await.unwrap());
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        server.listen(listener).unwrap();
+
+        let mut progress = |_: &str| {};
+        let info = download(&[addr], PeerId::from([2; 20]), info_hash, &downloaded, &mut progress).await.unwrap();
+        server.stop();
+
+        assert_eq!(info.name, "seeded.pack");
+        assert_eq!(tokio::fs::read(&downloaded).await.unwrap(), content);
+        let _ = tokio::fs::remove_dir_all(&dir).await;
+    }
+}
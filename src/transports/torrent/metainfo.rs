@@ -0,0 +1,141 @@
+use std::path::PathBuf;
+use sha1::{Digest, Sha1};
+use tokio::fs::File;
+use tokio::io::AsyncReadExt;
+
+use crate::transports::torrent::bencode::Value;
+use crate::transports::torrent::wire::InfoHash;
+use crate::utils::error::{GitError, GtrResult, TransportError};
+
+// NOTE: https://www.bittorrent.org/beps/bep_0003.html#metainfo-files
+// Packs are shared as single file torrents named `<sha>.pack`, the same as in gittorrent.
+
+pub const PIECE_LENGTH: u64 = 256 * 1024;
+// pieces are held in memory while they are verified, usual torrents do not exceed 16 MiB
+const MAX_PIECE_LENGTH: u64 = 16 * 1024 * 1024;
+
+/// Info dictionary of single file torrent, its hash identifies the torrent
+#[derive(Clone, Debug, PartialEq)]
+pub struct Info {
+    pub name: String,
+    pub length: u64,
+    pub piece_length: u64,
+    pub pieces: Vec<[u8; 20]>,
+}
+
+impl Info {
+    /// Hashes pieces of given file
+    pub async fn from_file(path: &PathBuf, name: &str) -> GtrResult<Self> {
+        let mut file = match File::open(path).await {
+            Ok(file) => file,
+            Err(e) => return Err(GitError::pack_read_failed(Box::new(e))),
+        };
+
+        let mut pieces = Vec::new();
+        let mut length = 0;
+        let mut piece = vec![0; PIECE_LENGTH as usize];
+        loop {
+            let read = match read_piece(&mut file, &mut piece).await {
+                Ok(read) => read,
+                Err(e) => return Err(GitError::pack_read_failed(Box::new(e))),
+            };
+            if read == 0 { break }
+            pieces.push(Sha1::digest(&piece[..read]).into());
+            length += read as u64;
+        }
+
+        Ok(Info { name: String::from(name), length, piece_length: PIECE_LENGTH, pieces })
+    }
+
+    /// Info dictionary received as torrent metadata
+    pub fn decode(metadata: &[u8]) -> GtrResult<Self> {
+        let value = Value::decode(metadata)?;
+        let int = |key| value.get(key).and_then(Value::as_int).and_then(|int| u64::try_from(int).ok());
+        let (name, length, piece_length, pieces) = match (
+            value.get("name").and_then(Value::as_bytes),
+            int("length"),
+            int("piece length"),
+            value.get("pieces").and_then(Value::as_bytes),
+        ) {
+            (Some(name), Some(length), Some(piece_length), Some(pieces)) => (name, length, piece_length, pieces),
+            _ => return Err(TransportError::malformed_message("metadata is not info of single file torrent")),
+        };
+        let expected = length.div_ceil(piece_length.max(1));
+        if piece_length > MAX_PIECE_LENGTH {
+            return Err(TransportError::malformed_message(&format!("piece length {piece_length} is too large")))
+        }
+        if piece_length == 0 || pieces.len() % 20 != 0 || (pieces.len() / 20) as u64 != expected {
+            return Err(TransportError::malformed_message("pieces do not match length of torrent"))
+        }
+
+        Ok(Info {
+            name: String::from_utf8_lossy(name).into_owned(),
+            length,
+            piece_length,
+            pieces: pieces.chunks_exact(20).map(|piece| piece.try_into().unwrap()).collect(),
+        })
+    }
+
+    /// Size of piece, the last one may be shorter
+    pub fn piece_size(&self, index: usize) -> u64 {
+        let begin = index as u64 * self.piece_length;
+        self.piece_length.min(self.length.saturating_sub(begin))
+    }
+
+    pub fn to_value(&self) -> Value {
+        Value::dict([
+            ("length", Value::Int(self.length as i64)),
+            ("name", Value::bytes(self.name.as_bytes())),
+            ("piece length", Value::Int(self.piece_length as i64)),
+            ("pieces", Value::Bytes(self.pieces.concat())),
+        ])
+    }
+
+    /// Bencoded info dictionary, peers exchange it with ut_metadata
+    pub fn metadata(&self) -> Vec<u8> {
+        self.to_value().encode()
+    }
+
+    pub fn info_hash(&self) -> InfoHash {
+        let hash: [u8; 20] = Sha1::digest(self.metadata()).into();
+        hash.into()
+    }
+}
+
+/// Fills piece as much as file allows, returns number of bytes read
+async fn read_piece(file: &mut File, piece: &mut [u8]) -> std::io::Result<usize> {
+    let mut read = 0;
+    while read < piece.len() {
+        match file.read(&mut piece[read..]).await? {
+            0 => break,
+            n => read += n,
+        }
+    }
+    Ok(read)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn hashes_pieces_of_file() {
+        let path = std::env::temp_dir().join(format!("gtr-metainfo-{}.pack", std::process::id()));
+        tokio::fs::write(&path, vec![7; PIECE_LENGTH as usize + 10]).await.unwrap();
+        let info = Info::from_file(&path, "pack").await.unwrap();
+        let _ = tokio::fs::remove_file(&path).await;
+
+        assert_eq!(info.pieces.len(), 2);
+        assert_eq!(info.piece_size(1), 10);
+        assert_eq!(info.pieces[1], <[u8; 20]>::from(Sha1::digest([7; 10])));
+        assert_eq!(Info::decode(&info.metadata()).unwrap(), info);
+
+        let mut truncated = info.clone();
+        truncated.pieces.pop();
+        assert!(Info::decode(&truncated.metadata()).is_err());
+
+        // one piece as large as the whole pack
+        let huge = Info { name: info.name, length: 1 << 32, piece_length: 1 << 32, pieces: vec![[0; 20]] };
+        assert!(Info::decode(&huge.metadata()).unwrap_err().to_string().contains("too large"));
+    }
+}
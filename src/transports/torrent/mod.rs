@@ -0,0 +1,404 @@
+pub mod bencode;
+pub mod bep44;
+pub mod dht;
+pub mod krpc;
+pub mod leech;
+pub mod metainfo;
+pub mod profile;
+pub mod seed;
+pub mod ut_gittorrent;
+pub mod ut_metadata;
+pub mod wire;
+
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use async_trait::async_trait;
+use sha1::digest::DynDigest;
+use sha1::Sha1;
+use sha2::Sha256;
+use tokio::fs::{self, File};
+use tokio::io::{AsyncReadExt, AsyncWriteExt, DuplexStream};
+
+use crate::config::config_file::Torrent as TorrentConfig;
+use crate::config::user_dirs::cache_dir;
+use crate::git_interface::pkt_line::{Packet, PktReader};
+use crate::git_interface::protocol_v2::shared_refs;
+use crate::git_interface::refs::{ObjectFormat, ObjectId, Ref};
+use crate::transports::url::{RepoUrl, Scheme};
+use crate::transports::{Progress, Stats, Transport, Tunnel};
+use crate::utils::error::{GitError, GtrError, GtrResult, ProtocolError, TransportError};
+use bep44::Client;
+use dht::{nodes_path, socket_addr, DhtNode};
+use ut_gittorrent::{ask, upload, PackServer};
+use wire::{InfoHash, PeerId};
+
+pub const SCHEME: &str = "torrent";
+const UPLOAD_PACK: &str = "git-upload-pack";
+// packs downloaded from peers, they are seeded further
+const PACKS_DIR: &str = "packs";
+
+/// Exchange with peers found on mainline DHT, `torrent://<hex sha1>/reponame`
+pub struct Torrent {
+    dht: DhtNode,
+    bind: SocketAddr,
+    // bound when the transport starts so that DHT node announces its port, accepting connections
+    // starts with serving
+    listener: Mutex<Option<std::net::TcpListener>>,
+    peer_id: PeerId,
+    server: Arc<PackServer>,
+    // bytes of packs downloaded from peers
+    downloaded: AtomicU64,
+}
+
+impl Torrent {
+    /// Starts DHT node as configured in `.gtr/config.toml`
+    ///
+    /// Port 0 binds ephemeral ports, peers are told the one of the pack server.
+    pub fn start(conf: &TorrentConfig) -> GtrResult<Self> {
+        let bind = socket_addr(&conf.bind)?;
+        let bound = std::net::TcpListener::bind(bind)
+            .and_then(|listener| listener.local_addr().map(|addr| (listener, addr.port())));
+        let (listener, port) = match bound {
+            Ok(bound) => bound,
+            Err(e) => return Err(TransportError::transport_failed(&format!("can not listen on {bind}, {e}"))),
+        };
+        let builder = DhtNode::builder(conf).announce_port(port);
+        let builder = match nodes_path() {
+            Some(path) => builder.nodes_file(path),
+            None => builder,
+        };
+        let dht = builder.start()?;
+        let peer_id = peer_id()?;
+        let server = Arc::new(PackServer::new(peer_id));
+        Ok(Torrent {
+            dht,
+            bind,
+            listener: Mutex::new(Some(listener)),
+            peer_id,
+            server,
+            downloaded: AtomicU64::new(0),
+        })
+    }
+
+    /// Client for mutable items on the same interface as DHT node
+    async fn client(&self) -> GtrResult<Client> {
+        Client::bind(SocketAddr::new(self.bind.ip(), 0), self.dht.nodes()).await
+    }
+
+    /// Asks peers announcing commit for its pack and downloads it from peers seeding it, returns
+    /// path of the pack which is seeded further
+    async fn fetch_commit(&self, url: &str, id: &ObjectId, progress: &mut Progress<'_>) -> GtrResult<PathBuf> {
+        let sha = id.to_hex();
+        let path = match cache_dir() {
+            Some(dir) => dir.join(PACKS_DIR).join(format!("{sha}.pack")),
+            None => return Err(TransportError::transport_failed("home directory is not known")),
+        };
+        if let Err(e) = fs::create_dir_all(path.parent().unwrap_or(&path)).await {
+            return Err(TransportError::transport_failed(&format!("can not create {}, {e}", path.display())))
+        }
+
+        // pieces are verified as they arrive, the pack is complete only once all of them are
+        static DOWNLOADS: AtomicUsize = AtomicUsize::new(0);
+        let downloading = path.with_extension(format!("pack.{}.{}.part", std::process::id(), DOWNLOADS.fetch_add(1, Ordering::Relaxed)));
+
+        let mut failure = TransportError::transport_failed(&format!("no peer of {url} announces {sha}"));
+        for addr in self.dht.search(id.dht_key().into()).await? {
+            let info_hash = match ask(addr, self.peer_id, id).await {
+                Ok(info_hash) => info_hash,
+                Err(e) => { failure = e; continue },
+            };
+            // the asked peer seeds the pack first
+            let mut seeders = self.dht.search(info_hash).await.unwrap_or_default();
+            seeders.retain(|seeder| *seeder != addr);
+            seeders.insert(0, addr);
+
+            let downloaded = match leech::download(&seeders, self.peer_id, info_hash, &downloading, progress).await {
+                Ok(info) => match fs::rename(&downloading, &path).await {
+                    Ok(()) => Ok(info),
+                    Err(e) => Err(TransportError::transport_failed(&format!("can not write {}, {e}", path.display()))),
+                },
+                Err(e) => Err(e),
+            };
+            match downloaded {
+                Ok(info) => {
+                    self.downloaded.fetch_add(info.length, Ordering::Relaxed);
+                    self.server.seed(path.clone(), info);
+                    // NOTE: only running server (`gtr serve`) seeds, remote helper exits after fetch
+                    if self.server.listening() {
+                        self.dht.announce(&[info_hash]).await?;
+                    }
+                    return Ok(path)
+                },
+                Err(e) => failure = e,
+            }
+        }
+        let _ = fs::remove_file(&downloading).await;
+        Err(failure)
+    }
+}
+
+/// Tunnel to git-upload-pack which advertises exactly the refs of signed profile
+///
+/// Any peer announcing a commit of the repository is asked for the tunnel, it could advertise
+/// refs the user did not publish. Advertisement is read ahead and passed on to git unchanged.
+async fn verify_advertisement(stream: DuplexStream, refs: &[Ref]) -> GtrResult<Tunnel> {
+    let (reader, writer) = tokio::io::split(stream);
+    let mut reader = PktReader::new(reader);
+    let mut advertisement = Vec::new();
+    loop {
+        let data = match reader.expect_packet().await? {
+            Packet::Data(data) => data,
+            Packet::Flush => break,
+            _ => return Err(ProtocolError::pkt_malformed("unexpected packet in ref advertisement")),
+        };
+        advertisement.extend_from_slice(format!("{:04x}", data.len() + 4).as_bytes());
+        advertisement.extend_from_slice(&data);
+
+        // `<sha> <name>`, capabilities follow the first ref after NUL
+        let line = String::from_utf8_lossy(&data);
+        let line = line.split('\0').next().unwrap_or_default().trim_end();
+        let (id, name) = match line.split_once(' ') {
+            Some(advertised) => advertised,
+            None => return Err(ProtocolError::pkt_malformed(&format!("invalid ref advertisement {line}"))),
+        };
+        // peeled tags, and `capabilities^{}` of repository without refs
+        if name.ends_with("^{}") { continue }
+        if !refs.iter().any(|r| r.name() == name && r.id().to_hex() == id) {
+            return Err(TransportError::transport_failed(&format!("peer advertises {name} at {id} which is not published")))
+        }
+    }
+    advertisement.extend_from_slice(b"0000");
+
+    let advertised = std::io::Cursor::new(advertisement).chain(reader.into_inner());
+    Ok(Tunnel { reader: Box::new(advertised), writer: Box::new(writer) })
+}
+
+/// Profile target and repository name of `torrent://<hex target>/<repository>` address, petnames
+/// are resolved by the remote helper
+fn parse_url(url: &str) -> GtrResult<([u8; 20], String)> {
+    let url: RepoUrl = url.parse()?;
+    let target = match url.scheme {
+        Scheme::Torrent => url.key().and_then(|key| <[u8; 20]>::try_from(key).ok()),
+        _ => None,
+    };
+    match target {
+        Some(target) => Ok((target, url.repository)),
+        None => Err(TransportError::invalid_address(&url.to_string())),
+    }
+}
+
+/// Writes objects of all packs into one pack, git tolerates objects present in several of them
+///
+/// Packs end with checksum of the repository object format, SHA-1 or SHA-256.
+async fn concat_packs(packs: &[PathBuf], format: ObjectFormat, path: &Path) -> GtrResult<()> {
+    // signature, version and number of objects
+    const HEADER_LEN: usize = 12;
+    let checksum_len = format.hash_len() as u64;
+
+    let concatenated = async {
+        let mut objects = 0;
+        for pack in packs {
+            let mut header = [0; HEADER_LEN];
+            File::open(pack).await?.read_exact(&mut header).await?;
+            objects += u32::from_be_bytes([header[8], header[9], header[10], header[11]]);
+        }
+
+        let mut out = File::create(path).await?;
+        let mut checksum: Box<dyn DynDigest + Send> = match format {
+            ObjectFormat::Sha1 => Box::new(Sha1::default()),
+            ObjectFormat::Sha256 => Box::new(Sha256::default()),
+        };
+        let header = [&b"PACK"[..], &2u32.to_be_bytes(), &objects.to_be_bytes()].concat();
+        checksum.update(&header);
+        out.write_all(&header).await?;
+
+        let mut buffer = vec![0; 64 * 1024];
+        for pack in packs {
+            let mut file = File::open(pack).await?;
+            let len = file.metadata().await?.len().saturating_sub(HEADER_LEN as u64 + checksum_len);
+            file.read_exact(&mut [0; HEADER_LEN]).await?;
+            let mut objects = (&mut file).take(len);
+            loop {
+                let read = objects.read(&mut buffer).await?;
+                if read == 0 { break }
+                checksum.update(&buffer[..read]);
+                out.write_all(&buffer[..read]).await?;
+            }
+        }
+        out.write_all(&checksum.finalize()).await?;
+        out.flush().await
+    };
+    match concatenated.await {
+        Ok(()) => Ok(()),
+        Err(e) => Err(GitError::pack_read_failed(Box::new(e))),
+    }
+}
+
+/// Random peer id of this process
+fn peer_id() -> GtrResult<PeerId> {
+    let mut id = [0; 20];
+    if let Err(e) = getrandom::getrandom(&mut id) {
+        return Err(TransportError::transport_failed(&format!("can not generate peer id, {e}")))
+    }
+    Ok(PeerId::from(id))
+}
+
+#[async_trait]
+impl Transport for Torrent {
+    fn scheme(&self) -> &'static str {
+        SCHEME
+    }
+
+    /// Announces tips of shared refs, peers find this node searching for them and ask it for their
+    /// packs, and publishes them in the user profile
+    async fn announce(&self, dir: &Path, refs: &[Ref]) -> GtrResult<()> {
+        let dir = dir.to_path_buf();
+        self.server.share(&dir, refs);
+        let hashes: Vec<InfoHash> = refs.iter().map(|r| r.id().dht_key().into()).collect();
+        self.dht.announce(&hashes).await?;
+
+        profile::publish(&self.client().await?, &dir).await?;
+        Ok(())
+    }
+
+    async fn resolve(&self, url: &str) -> GtrResult<Vec<Ref>> {
+        let (target, repository) = parse_url(url)?;
+        profile::resolve(&self.client().await?, &target, &repository).await
+    }
+
+    /// Packs of whole history of each wanted commit, the same as in gittorrent, so `haves` are not
+    /// used and the pack may contain objects the repository already has
+    async fn fetch_pack(
+        &self,
+        url: &str,
+        wants: &[ObjectId],
+        _haves: &[ObjectId],
+        pack_path: &Path,
+        progress: &mut Progress) -> GtrResult<()>
+    {
+        let mut wants = wants.to_vec();
+        wants.sort();
+        wants.dedup();
+
+        let mut packs = Vec::new();
+        for want in &wants {
+            packs.push(self.fetch_commit(url, want, progress).await?);
+        }
+        let format = wants.first().map(ObjectId::format).unwrap_or(ObjectFormat::Sha1);
+        concat_packs(&packs, format, pack_path).await
+    }
+
+    /// Tunnel to git-upload-pack of peer announcing tip of the repository, fetch then negotiates
+    /// with it and receives only missing objects
+    ///
+    /// Peers running gittorrent can only be asked for packs, git falls back to `fetch` then.
+    async fn connect(&self, url: &str, service: &str) -> GtrResult<Option<Tunnel>> {
+        if service != UPLOAD_PACK {
+            return Ok(None)
+        }
+        let refs = self.resolve(url).await?;
+        // peers of any tip have the repository
+        let tip = match refs.iter().find(|r| r.name() != "HEAD") {
+            Some(tip) => *tip.id(),
+            None => return Ok(None),
+        };
+        for addr in self.dht.search(tip.dht_key().into()).await? {
+            let stream = match upload(addr, self.peer_id, &tip).await {
+                Ok(Some(stream)) => stream,
+                Ok(None) | Err(_) => continue,
+            };
+            if let Ok(tunnel) = verify_advertisement(stream, &refs).await {
+                return Ok(Some(tunnel))
+            }
+        }
+        Ok(None)
+    }
+
+    /// Answers asks of peers for packs on the port DHT node announces, DHT node answers queries on
+    /// its own as long as it runs
+    async fn serve(&self, dir: &Path) -> GtrResult<()> {
+        let dir = dir.to_path_buf();
+        self.server.share(&dir, &shared_refs(&dir).await?);
+        if let Some(listener) = self.listener.lock().unwrap().take() {
+            self.server.listen(listener)?;
+        }
+        self.dht.bootstrapped().await
+    }
+
+    async fn shutdown(&self) -> GtrResult<()> {
+        self.server.stop();
+        self.dht.shutdown();
+        Ok(())
+    }
+
+    fn stats(&self) -> Stats {
+        Stats {
+            peers: self.server.peers().iter().map(|addr| addr.to_string()).collect(),
+            uploaded: self.server.uploaded(),
+            downloaded: self.downloaded.load(Ordering::Relaxed),
+        }
+    }
+
+    fn failures(&self) -> Vec<GtrError> {
+        self.server.failures()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sha2::Digest;
+
+    #[tokio::test]
+    async fn concatenates_packs_with_checksum_of_object_format() {
+        let dir = std::env::temp_dir().join(format!("gtr-concat-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        // header and checksum around objects, only their count and bytes matter here
+        let pack = |objects: u32, data: &[u8]| {
+            let content = [&b"PACK"[..], &2u32.to_be_bytes(), &objects.to_be_bytes(), data].concat();
+            [content.clone(), Sha256::digest(&content).to_vec()].concat()
+        };
+        let packs = [dir.join("a.pack"), dir.join("b.pack")];
+        std::fs::write(&packs[0], pack(1, b"first")).unwrap();
+        std::fs::write(&packs[1], pack(2, b"second")).unwrap();
+
+        let path = dir.join("all.pack");
+        concat_packs(&packs, ObjectFormat::Sha256, &path).await.unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), pack(3, b"firstsecond"));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn passes_on_only_advertisement_of_published_refs() {
+        const MASTER: &str = "66ef7ea67c18d2341afb8c1521afbab31014e62f";
+        let published = [
+            Ref::new("HEAD", MASTER.parse().unwrap(), Some("refs/heads/master"), None),
+            Ref::new("refs/heads/master", MASTER.parse().unwrap(), None, None),
+        ];
+        let advertisement = |refs: &[&str]| {
+            let lines: Vec<String> = refs.iter().map(|name| format!("{MASTER} {name}\n")).collect();
+            let lines = [&[format!("{MASTER} HEAD\0multi_ack symref=HEAD:refs/heads/master\n")], lines.as_slice()].concat();
+            lines.iter().map(|line| format!("{:04x}{line}", line.len() + 4)).collect::<String>() + "0000"
+        };
+
+        let (mut peer, stream) = tokio::io::duplex(1024);
+        let advertised = advertisement(&["refs/heads/master"]);
+        peer.write_all(format!("{advertised}0008NAK\n").as_bytes()).await.unwrap();
+        let mut tunnel = verify_advertisement(stream, &published).await.unwrap();
+        tunnel.writer.write_all(b"0000").await.unwrap();
+        let mut passed = vec![0; advertised.len() + 8];
+        tunnel.reader.read_exact(&mut passed).await.unwrap();
+        assert_eq!(String::from_utf8(passed).unwrap(), format!("{advertised}0008NAK\n"));
+        let mut request = [0; 4];
+        peer.read_exact(&mut request).await.unwrap();
+        assert_eq!(&request, b"0000");
+
+        let (mut peer, stream) = tokio::io::duplex(1024);
+        peer.write_all(advertisement(&["refs/heads/master", "refs/heads/secret"]).as_bytes()).await.unwrap();
+        let rejected = verify_advertisement(stream, &published).await;
+        assert!(rejected.is_err_and(|e| e.to_string().contains("refs/heads/secret")));
+    }
+}
@@ -0,0 +1,293 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use serde::{Deserialize, Serialize};
+use tokio::fs;
+
+use crate::config::config_file;
+use crate::config::user_dirs::{self, state_dir};
+use crate::git_interface::ls_remote;
+use crate::git_interface::refs::Ref;
+use crate::identity::keystore;
+use crate::transports::torrent::bencode::Value;
+use crate::transports::torrent::bep44::{Client, MutableItem};
+use crate::utils::error::{GtrResult, ProfileError, TransportError};
+use crate::utils::hex::hex;
+
+// NOTE: the same as in gittorrent, profile is JSON string stored as value of mutable DHT item
+// signed by the user, its address is `torrent://<hex target of the item>/<repository name>`, the
+// target is hash of public key of the user identity.
+
+/// Limit of encoded profile, it leaves space for bencoding within `bep44::MAX_VALUE_LEN`
+pub const MAX_PROFILE_LEN: usize = 950;
+// last published profile and its sequence number
+const PUBLISHED_FILE: &str = "profile.toml";
+
+/// Repositories published by the user with their refs (full names) and object ids (hex)
+#[derive(Serialize, Deserialize, Default, Clone, Debug, PartialEq)]
+pub struct UserProfile {
+    pub repositories: BTreeMap<String, BTreeMap<String, String>>,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+struct Published {
+    seq: Option<i64>,
+    profile: UserProfile,
+    // directories of published repositories by their names
+    #[serde(default)]
+    dirs: BTreeMap<String, PathBuf>,
+}
+
+impl UserProfile {
+    pub fn encode(&self) -> GtrResult<Vec<u8>> {
+        let encoded = match serde_json::to_vec(self) {
+            Ok(encoded) => encoded,
+            Err(e) => return Err(TransportError::malformed_message(&format!("{e}"))),
+        };
+        if encoded.len() > MAX_PROFILE_LEN {
+            return Err(ProfileError::profile_too_large(encoded.len(), MAX_PROFILE_LEN))
+        }
+        Ok(encoded)
+    }
+
+    pub fn decode(encoded: &[u8]) -> GtrResult<Self> {
+        match serde_json::from_slice(encoded) {
+            Ok(profile) => Ok(profile),
+            Err(e) => Err(TransportError::malformed_message(&format!("invalid profile, {e}"))),
+        }
+    }
+
+    /// Refs of given repository, HEAD goes first
+    ///
+    /// Profile keeps only the object id of HEAD, it points to the first branch with the same id.
+    pub fn refs(&self, repository: &str) -> GtrResult<Vec<Ref>> {
+        let refs = match self.repositories.get(repository) {
+            Some(refs) => refs,
+            None => return Err(ProfileError::repository_not_published(repository)),
+        };
+        let branch_of = |id: &String| refs
+            .iter()
+            .find(|(name, other)| name.starts_with("refs/heads/") && other == &id)
+            .map(|(name, _)| name.as_str());
+        // "HEAD" sorts before "refs/..."
+        refs
+            .iter()
+            .map(|(name, id)| {
+                let target = if name == "HEAD" { branch_of(id) } else { None };
+                Ok(Ref::new(name, id.parse()?, target, None))
+            })
+            .collect()
+    }
+}
+
+/// Adds refs of repository in `dir` to the user profile and stores it on DHT, returns target of
+/// the profile item
+///
+/// Sequence number is increased whenever profile changes, unchanged profile is stored again to
+/// keep it alive on DHT.
+pub async fn publish(client: &Client, dir: &PathBuf) -> GtrResult<[u8; 20]> {
+    let identity = keystore::load_or_generate().await?;
+    let key = identity.signing_key();
+    let dir = std::fs::canonicalize(dir).unwrap_or(dir.clone());
+    let name = repository_name(&dir)?;
+    let path = state_dir().map(|state| state.join(PUBLISHED_FILE));
+    // `gtd` and `git push` publishing at once would store different profiles with the same seq
+    let _lock = match &path {
+        Some(path) => Some(user_dirs::lock(path).await?),
+        None => None,
+    };
+    let mut published = read_published(path.as_deref()).await;
+    claim(&published, &name, &dir)?;
+    let refs = repository_refs(&dir).await?;
+
+    // items stored from other devices of the user or before local state was lost
+    let target = identity.hash();
+    let stored = match client.get(&target).await? {
+        Some(item) => match item.value.as_bytes() {
+            Some(encoded) => Some((item.seq, UserProfile::decode(encoded)?)),
+            None => return Err(TransportError::malformed_message("stored profile is not a string")),
+        },
+        None => None,
+    };
+    let (seq, profile) = next_profile(&published, stored, &name, refs);
+
+    let item = MutableItem::sign(key, seq, Value::Bytes(profile.encode()?))?;
+    if client.put(&item).await? == 0 {
+        return Err(TransportError::dht_failed("no node stored the profile"))
+    }
+    match profile.repositories.contains_key(&name) {
+        true => published.dirs.insert(name, dir),
+        false => published.dirs.remove(&name),
+    };
+    if let Some(path) = path {
+        write_published(&path, &Published { seq: Some(seq), profile, dirs: published.dirs }).await;
+    }
+
+    Ok(target)
+}
+
+/// Refs of `repository` from profile stored under `target`, signature is verified by `Client`
+pub async fn resolve(client: &Client, target: &[u8; 20], repository: &str) -> GtrResult<Vec<Ref>> {
+    let item = match client.get(target).await? {
+        Some(item) => item,
+        None => return Err(ProfileError::profile_not_found(&hex(target))),
+    };
+    match item.value.as_bytes() {
+        Some(encoded) => UserProfile::decode(encoded)?.refs(repository),
+        None => Err(TransportError::malformed_message("profile is not a string")),
+    }
+}
+
+/// Sequence number and profile with refs of repository `name`, an empty `refs` removes it
+///
+/// Profile stored on DHT with the same or higher sequence number than the one published from
+/// this device may come from another device of the user, repositories published there are kept.
+fn next_profile(
+    published: &Published,
+    stored: Option<(i64, UserProfile)>,
+    name: &str,
+    refs: BTreeMap<String, String>) -> (i64, UserProfile)
+{
+    let (seq, previous) = match stored {
+        Some((seq, profile)) if published.seq.is_none_or(|published| seq >= published) => (Some(seq), profile),
+        _ => (published.seq, published.profile.clone()),
+    };
+
+    let mut profile = previous.clone();
+    if refs.is_empty() {
+        profile.repositories.remove(name);
+    } else {
+        profile.repositories.insert(String::from(name), refs);
+    }
+    let seq = match seq {
+        Some(seq) if profile == previous => seq,
+        Some(seq) => seq + 1,
+        None => 0,
+    };
+
+    (seq, profile)
+}
+
+/// Shared branches and HEAD if it points to one of them, clone checks it out
+async fn repository_refs(dir: &Path) -> GtrResult<BTreeMap<String, String>> {
+    let conf = config_file::read_or_create(dir).await?;
+    let shared: Vec<String> = conf.branches.iter().map(|b| format!("refs/heads/{b}")).collect();
+
+    let refs = ls_remote(&dir.to_string_lossy()).await?;
+    let mut published: BTreeMap<String, String> = refs
+        .iter()
+        .filter(|r| shared.iter().any(|name| name.eq(r.name())))
+        .map(|r| (String::from(r.name()), r.id().to_hex()))
+        .collect();
+    if let Some(Ref::Symbolic { name, target, id }) = refs.iter().find(|r| r.name().eq("HEAD")) {
+        if published.contains_key(target) {
+            published.insert(name.clone(), id.to_hex());
+        }
+    }
+
+    Ok(published)
+}
+
+/// Name of repository in profile, the name of its directory
+fn repository_name(dir: &Path) -> GtrResult<String> {
+    match dir.file_name() {
+        Some(name) => Ok(name.to_string_lossy().into_owned()),
+        None => Err(ProfileError::repository_not_published(&dir.to_string_lossy())),
+    }
+}
+
+/// Fails if repository of another directory with the same name is published
+///
+/// Profile is keyed by directory names, one would replace the other. Repositories of removed
+/// directories can not be published again, so their names are free.
+fn claim(published: &Published, name: &str, dir: &Path) -> GtrResult<()> {
+    match published.dirs.get(name) {
+        Some(other) if other != dir && other.exists() => Err(ProfileError::repository_name_taken(name, other)),
+        _ => Ok(()),
+    }
+}
+
+async fn read_published(path: Option<&Path>) -> Published {
+    let path = match path {
+        Some(path) => path,
+        None => return Published::default(),
+    };
+    // without it profile is published anew, sequence continues from the one stored on DHT
+    match fs::read_to_string(path).await {
+        Ok(data) => toml::from_str(&data).unwrap_or_default(),
+        Err(_) => Published::default(),
+    }
+}
+
+/// Replaces published profile, the caller holds its lock
+async fn write_published(path: &Path, published: &Published) {
+    let content = toml::to_string(published).unwrap_or_default();
+    // losing it only makes next publish start from the sequence number stored on DHT
+    let _ = user_dirs::replace(path, &content).await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MASTER: &str = "66ef7ea67c18d2341afb8c1521afbab31014e62f";
+
+    #[test]
+    fn encodes_profile_within_size_limit() {
+        let refs = BTreeMap::from([
+            (String::from("refs/heads/master"), String::from(MASTER)),
+            (String::from("HEAD"), String::from(MASTER)),
+        ]);
+        let mut profile = UserProfile::default();
+        profile.repositories.insert(String::from("gtr"), refs);
+
+        let decoded = UserProfile::decode(&profile.encode().unwrap()).unwrap();
+        let refs = decoded.refs("gtr").unwrap();
+        let names: Vec<&str> = refs.iter().map(|r| r.name()).collect();
+        assert_eq!(names, ["HEAD", "refs/heads/master"]);
+        assert!(matches!(&refs[0], Ref::Symbolic { target, .. } if target == "refs/heads/master"));
+        assert!(decoded.refs("other").is_err());
+
+        for i in 0..10 {
+            profile.repositories.insert(format!("repository-{i}"), profile.repositories["gtr"].clone());
+        }
+        assert!(profile.encode().is_err());
+    }
+
+    #[test]
+    fn keeps_repositories_published_from_other_devices() {
+        let refs = |id: &str| BTreeMap::from([(String::from("refs/heads/master"), String::from(id))]);
+        let mut published = Published { seq: Some(3), ..Published::default() };
+        published.profile.repositories.insert(String::from("gtr"), refs(MASTER));
+
+        // another device published its repository since
+        let mut stored = published.profile.clone();
+        stored.repositories.insert(String::from("other"), refs(MASTER));
+        let (seq, profile) = next_profile(&published, Some((5, stored.clone())), "gtr", refs(MASTER));
+        assert_eq!((seq, &profile), (5, &stored));
+
+        let (seq, profile) = next_profile(&published, Some((5, stored)), "gtr", BTreeMap::new());
+        assert_eq!(seq, 6);
+        assert_eq!(profile.repositories.keys().collect::<Vec<_>>(), ["other"]);
+
+        // DHT lost the latest profile
+        let (seq, profile) = next_profile(&published, Some((1, UserProfile::default())), "gtr", refs(MASTER));
+        assert_eq!((seq, profile), (3, published.profile.clone()));
+        assert_eq!(next_profile(&Published::default(), None, "gtr", refs(MASTER)).0, 0);
+    }
+
+    #[test]
+    fn refuses_repositories_of_the_same_name() {
+        let dir = std::env::temp_dir().join(format!("gtr-profile-{}", std::process::id()));
+        let (first, second) = (dir.join("first").join("gtr"), dir.join("second").join("gtr"));
+        std::fs::create_dir_all(&first).unwrap();
+        let mut published = Published::default();
+        published.dirs.insert(String::from("gtr"), first.clone());
+
+        assert!(claim(&published, "gtr", &first).is_ok());
+        assert!(claim(&published, "other", &second).is_ok());
+        assert!(claim(&published, "gtr", &second).is_err());
+        // the repository published first was removed
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert!(claim(&published, "gtr", &second).is_ok());
+    }
+}
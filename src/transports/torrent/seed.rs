@@ -0,0 +1,71 @@
+use std::io::SeekFrom;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use tokio::fs::File;
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+
+use crate::transports::torrent::metainfo::Info;
+use crate::transports::torrent::ut_metadata::{self, MetadataMessage};
+use crate::transports::torrent::wire::{block, parse_block_request, Connection, Message, BITFIELD, BLOCK_LEN, PIECE, REQUEST, UNCHOKE};
+use crate::utils::error::{GitError, GtrResult, TransportError};
+
+// NOTE: https://www.bittorrent.org/beps/bep_0003.html#peer-messages
+// Seeder has all pieces and never chokes, so it only answers requests for blocks and metadata.
+
+/// Serves metadata and pieces of pack to connected peer until it disconnects, sent bytes are added
+/// to `uploaded`
+pub async fn serve(mut connection: Connection, pack: &PathBuf, info: &Info, uploaded: &AtomicU64) -> GtrResult<()> {
+    let metadata = info.metadata();
+    let mut file = match File::open(pack).await {
+        Ok(file) => file,
+        Err(e) => return Err(GitError::pack_read_failed(Box::new(e))),
+    };
+
+    connection.send(BITFIELD, &bitfield(info.pieces.len())).await?;
+    connection.send(UNCHOKE, &[]).await?;
+    loop {
+        match connection.receive().await? {
+            Message::Extended(name, payload) if name == ut_metadata::EXTENSION => {
+                if let MetadataMessage::Request(piece) = MetadataMessage::decode(&payload)? {
+                    let answer = ut_metadata::answer(&metadata, piece);
+                    connection.send_extended(ut_metadata::EXTENSION, &answer.encode()).await?;
+                }
+            },
+            Message::Other(REQUEST, payload) => {
+                let (index, begin, len) = parse_block_request(&payload)?;
+                let data = read_block(&mut file, info, index, begin, len).await?;
+                connection.send(PIECE, &block(index, begin, &data)).await?;
+                uploaded.fetch_add(data.len() as u64, Ordering::Relaxed);
+            },
+            _ => continue,
+        }
+    }
+}
+
+/// Pieces the seeder has, all of them
+fn bitfield(pieces: usize) -> Vec<u8> {
+    let mut bitfield = vec![0; pieces.div_ceil(8)];
+    // the first piece is the highest bit of the first byte
+    (0..pieces).for_each(|piece| bitfield[piece / 8] |= 0x80 >> (piece % 8));
+    bitfield
+}
+
+async fn read_block(file: &mut File, info: &Info, index: u32, begin: u32, len: u32) -> GtrResult<Vec<u8>> {
+    let valid = (index as usize) < info.pieces.len()
+        && len <= BLOCK_LEN
+        && begin as u64 + len as u64 <= info.piece_size(index as usize);
+    if !valid {
+        return Err(TransportError::malformed_message(&format!("request of {len} bytes at {begin} of piece {index}")))
+    }
+
+    let mut data = vec![0; len as usize];
+    let offset = index as u64 * info.piece_length + begin as u64;
+    let read = async {
+        file.seek(SeekFrom::Start(offset)).await?;
+        file.read_exact(&mut data).await
+    };
+    match read.await {
+        Ok(_) => Ok(data),
+        Err(e) => Err(GitError::pack_read_failed(Box::new(e))),
+    }
+}
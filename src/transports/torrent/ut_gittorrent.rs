@@ -0,0 +1,452 @@
+use std::collections::{HashMap, HashSet};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWriteExt, DuplexStream};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::task::JoinHandle;
+use tokio::time::timeout;
+
+use crate::git_interface::protocol_v2::shared_refs;
+use crate::git_interface::pack_cache::CachedPack;
+use crate::git_interface::refs::{ObjectId, Ref};
+use crate::git_interface::{start_shared_upload_process, upload_pack};
+use crate::transports::torrent::bencode::Value;
+use crate::transports::torrent::metainfo::Info;
+use crate::transports::torrent::seed;
+use crate::transports::torrent::wire::{Connection, Incoming, InfoHash, Message, Outgoing, PeerId, Shared};
+use crate::utils::error::{GtrError, GtrResult, TransportError};
+use crate::utils::hex::{from_hex, hex};
+
+// NOTE: https://github.com/cjb/GitTorrent/blob/master/lib/ut_gittorrent.js
+// Peer connects with DHT key of a commit as info hash and asks for it with
+// `{gitTorrent: {ask: <sha>}}`, the other side generates pack of it, seeds it as `<sha>.pack` and
+// answers with `{gitTorrent: {onhave: <hex info hash>}}`. Original implementation ignores asks for
+// shas which are not announced, gtr answers them with `reject` which original clients ignore.
+//
+// gtr peers can also ask with `{gitTorrent: {upload: <sha>}}` for git-upload-pack of repository
+// announcing the commit, both sides then relay its protocol in `{gitTorrent: {data: <bytes>}}`
+// messages until it exits. Fetch negotiates over it and receives only missing objects. Original
+// implementation ignores the message, so clients fall back to asking for packs.
+
+pub const EXTENSION: &str = "ut_gittorrent";
+// generating pack of large repository takes a while
+const ASK_TIMEOUT: Duration = Duration::from_secs(600);
+// peers send keep-alive every two minutes, connections silent for longer are dropped
+const IDLE_TIMEOUT: Duration = Duration::from_secs(150);
+// git-upload-pack advertises refs right away, peers not knowing `upload` never answer it
+const UPLOAD_TIMEOUT: Duration = Duration::from_secs(30);
+// data messages stay well below the limit of wire messages
+const DATA_LEN: usize = 16 * 1024;
+
+#[derive(Debug, PartialEq)]
+pub enum GitTorrentMessage {
+    Ask(String),
+    OnHave(String),
+    Reject(String),
+    Upload(String),
+    Data(Vec<u8>),
+}
+
+impl GitTorrentMessage {
+    pub fn encode(&self) -> Vec<u8> {
+        let (key, value) = match self {
+            GitTorrentMessage::Ask(sha) => ("ask", sha.as_bytes()),
+            GitTorrentMessage::OnHave(info_hash) => ("onhave", info_hash.as_bytes()),
+            GitTorrentMessage::Reject(sha) => ("reject", sha.as_bytes()),
+            GitTorrentMessage::Upload(sha) => ("upload", sha.as_bytes()),
+            GitTorrentMessage::Data(data) => ("data", data.as_slice()),
+        };
+        Value::dict([("gitTorrent", Value::dict([(key, Value::bytes(value))]))]).encode()
+    }
+
+    /// Decodes message, data after bencoded dictionary is ignored the same as in gittorrent
+    pub fn decode(payload: &[u8]) -> GtrResult<Self> {
+        let (message, _) = Value::decode_prefix(payload)?;
+        let field = |key| {
+            message.get("gitTorrent")?.get(key)?.as_bytes().map(|value| String::from_utf8_lossy(value).into_owned())
+        };
+        if let Some(sha) = field("ask") {
+            return Ok(GitTorrentMessage::Ask(sha))
+        }
+        if let Some(info_hash) = field("onhave") {
+            return Ok(GitTorrentMessage::OnHave(info_hash))
+        }
+        if let Some(sha) = field("reject") {
+            return Ok(GitTorrentMessage::Reject(sha))
+        }
+        if let Some(sha) = field("upload") {
+            return Ok(GitTorrentMessage::Upload(sha))
+        }
+        if let Some(data) = message.get("gitTorrent").and_then(|m| m.get("data")).and_then(Value::as_bytes) {
+            return Ok(GitTorrentMessage::Data(data.to_vec()))
+        }
+        Err(TransportError::malformed_message("unknown ut_gittorrent message"))
+    }
+}
+
+/// Answers asks of peers for packs of announced commits and seeds the packs
+pub struct PackServer {
+    peer_id: PeerId,
+    // announced commits with repositories they are in
+    announced: Mutex<HashMap<ObjectId, PathBuf>>,
+    // generated packs by info hash of torrents they are seeded as
+    packs: Mutex<HashMap<InfoHash, (PathBuf, Info)>>,
+    // generated packs of announced commits, the pack cache of their repository keeps them
+    generated: Mutex<HashMap<ObjectId, CachedPack>>,
+    listener: Mutex<Option<JoinHandle<()>>>,
+    // connected peers and bytes of pieces sent to them
+    peers: Mutex<HashSet<SocketAddr>>,
+    uploaded: AtomicU64,
+    // errors of this node while serving peers, not reported yet
+    failures: Mutex<Vec<GtrError>>,
+}
+
+impl PackServer {
+    pub fn new(peer_id: PeerId) -> Self {
+        PackServer {
+            peer_id,
+            announced: Mutex::new(HashMap::new()),
+            packs: Mutex::new(HashMap::new()),
+            generated: Mutex::new(HashMap::new()),
+            listener: Mutex::new(None),
+            peers: Mutex::new(HashSet::new()),
+            uploaded: AtomicU64::new(0),
+            failures: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Replaces announced commits of repository in `dir` with tips of `refs`
+    ///
+    /// Packs generated for commits which are not announced anymore stop being seeded, the pack
+    /// cache may evict them then.
+    pub fn share(&self, dir: &PathBuf, refs: &[Ref]) {
+        let mut announced = self.announced.lock().unwrap();
+        announced.retain(|_, repository| repository != dir);
+        announced.extend(refs.iter().map(|r| (*r.id(), dir.clone())));
+
+        let mut generated = self.generated.lock().unwrap();
+        let released: Vec<PathBuf> = generated
+            .extract_if(|id, _| !announced.contains_key(id))
+            .map(|(_, pack)| pack.path)
+            .collect();
+        self.packs.lock().unwrap().retain(|_, (path, _)| !released.contains(path));
+    }
+
+    /// Seeds pack, generated or downloaded from peers, returns info hash of its torrent
+    pub fn seed(&self, pack: PathBuf, info: Info) -> InfoHash {
+        let info_hash = info.info_hash();
+        self.packs.lock().unwrap().insert(info_hash, (pack, info));
+        info_hash
+    }
+
+    /// Pack seeded as torrent with given info hash
+    pub fn pack(&self, info_hash: &InfoHash) -> Option<(PathBuf, Info)> {
+        self.packs.lock().unwrap().get(info_hash).cloned()
+    }
+
+    pub fn listening(&self) -> bool {
+        self.listener.lock().unwrap().is_some()
+    }
+
+    /// Accepts connections of peers on bound `listener`, does nothing if it already does
+    pub fn listen(self: &Arc<Self>, listener: std::net::TcpListener) -> GtrResult<()> {
+        if self.listener.lock().unwrap().is_some() {
+            return Ok(())
+        }
+        let listener = match listener.set_nonblocking(true).and_then(|_| TcpListener::from_std(listener)) {
+            Ok(listener) => listener,
+            Err(e) => return Err(TransportError::transport_failed(&format!("can not listen for peers, {e}"))),
+        };
+
+        let server = self.clone();
+        let task = tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let server = server.clone();
+                tokio::spawn(async move {
+                    if let Err(e) = server.handle(stream).await {
+                        server.failures.lock().unwrap().push(e);
+                    }
+                });
+            }
+        });
+        *self.listener.lock().unwrap() = Some(task);
+        Ok(())
+    }
+
+    pub fn peers(&self) -> Vec<SocketAddr> {
+        self.peers.lock().unwrap().iter().copied().collect()
+    }
+
+    pub fn uploaded(&self) -> u64 {
+        self.uploaded.load(Ordering::Relaxed)
+    }
+
+    /// Errors of serving peers since the last call
+    pub fn failures(&self) -> Vec<GtrError> {
+        std::mem::take(&mut *self.failures.lock().unwrap())
+    }
+
+    pub fn stop(&self) {
+        if let Some(task) = self.listener.lock().unwrap().take() {
+            task.abort();
+        }
+    }
+
+    /// Peers connect with info hash of a seeded pack to download it, or with DHT key of announced
+    /// commit to ask for its pack
+    ///
+    /// Fails only on errors of this node, misbehaving peers only lose their connection.
+    async fn handle(&self, stream: TcpStream) -> GtrResult<()> {
+        let shared = |info_hash: &InfoHash| {
+            if let Some((_, info)) = self.packs.lock().unwrap().get(info_hash) {
+                return Some(Shared::Torrent(info.metadata().len()))
+            }
+            let announced = self.announced.lock().unwrap().keys().any(|id| InfoHash::from(id.dht_key()).eq(info_hash));
+            announced.then_some(Shared::Extensions)
+        };
+        let connection = match Connection::accept(stream, self.peer_id, shared).await {
+            Ok(connection) => connection,
+            Err(_) => return Ok(()),
+        };
+        let remote = connection.remote;
+        self.peers.lock().unwrap().insert(remote);
+        let served = match self.pack(&connection.info_hash) {
+            Some((pack, info)) => {
+                // NOTE: seeding ends when the peer disconnects, it has nothing to report
+                let _ = seed::serve(connection, &pack, &info, &self.uploaded).await;
+                Ok(())
+            },
+            None => self.answer(connection).await,
+        };
+        self.peers.lock().unwrap().remove(&remote);
+        served
+    }
+
+    /// Answers asks of peer until it disconnects, misbehaves or stays idle
+    async fn answer(&self, mut connection: Connection) -> GtrResult<()> {
+        loop {
+            let payload = match timeout(IDLE_TIMEOUT, connection.receive()).await {
+                Ok(Ok(Message::Extended(name, payload))) if name == EXTENSION => payload,
+                Ok(Ok(_)) => continue,
+                _ => return Ok(()),
+            };
+            let answer = match GitTorrentMessage::decode(&payload) {
+                Ok(GitTorrentMessage::Ask(sha)) => match self.generate(&sha).await {
+                    Ok(Some(info_hash)) => GitTorrentMessage::OnHave(hex(info_hash.as_ref())),
+                    Ok(None) => GitTorrentMessage::Reject(sha),
+                    // NOTE: the commit is announced, failing to generate its pack is an error of
+                    // this node rather than of the peer
+                    Err(e) => {
+                        let remote = connection.remote;
+                        return Err(TransportError::transport_failed(&format!("can not generate pack of {sha} for {remote}, {e}")))
+                    },
+                },
+                Ok(GitTorrentMessage::Upload(sha)) => match self.announced(&sha) {
+                    Some((_, dir)) => return self.tunnel(connection, &dir).await,
+                    None => GitTorrentMessage::Reject(sha),
+                },
+                _ => continue,
+            };
+            if connection.send_extended(EXTENSION, &answer.encode()).await.is_err() {
+                return Ok(())
+            }
+        }
+    }
+
+    /// Generates pack of announced commit, returns info hash of torrent it is seeded as or none if
+    /// the commit is not announced
+    async fn generate(&self, sha: &str) -> GtrResult<Option<InfoHash>> {
+        let (id, dir) = match self.announced(sha) {
+            Some(announced) => announced,
+            None => return Ok(None),
+        };
+
+        let pack = upload_pack(&dir, &[id], &[], &mut |_: &str| {}).await?;
+        let info = Info::from_file(&pack.path, &format!("{sha}.pack")).await?;
+        let info_hash = self.seed(pack.path.clone(), info);
+        self.generated.lock().unwrap().insert(id, pack);
+        Ok(Some(info_hash))
+    }
+
+    /// Announced commit with repository it is in
+    fn announced(&self, sha: &str) -> Option<(ObjectId, PathBuf)> {
+        let id = sha.parse::<ObjectId>().ok()?;
+        self.announced.lock().unwrap().get(&id).map(|dir| (id, dir.clone()))
+    }
+
+    /// Relays data of peer to git-upload-pack of repository in `dir` advertising its shared
+    /// branches, and the output back until it exits
+    async fn tunnel(&self, connection: Connection, dir: &Path) -> GtrResult<()> {
+        let remote = connection.remote;
+        let started = match shared_refs(dir).await {
+            Ok(refs) => start_shared_upload_process(dir, &refs).await,
+            Err(e) => Err(e),
+        };
+        let mut upload = match started {
+            Ok(upload) => upload,
+            Err(e) => return Err(TransportError::transport_failed(&format!("can not upload {} for {remote}, {e}", dir.display()))),
+        };
+        let (mut stdin, stdout) = (upload.stdin.take().unwrap(), upload.stdout.take().unwrap());
+
+        let (mut incoming, outgoing) = connection.into_split();
+        let requests = tokio::spawn(async move {
+            while let Ok(Some(data)) = timeout(IDLE_TIMEOUT, next_data(&mut incoming)).await {
+                if stdin.write_all(&data).await.is_err() { break }
+            }
+        });
+        send_data(stdout, outgoing).await;
+        requests.abort();
+        // NOTE: upload-pack fails on invalid requests of the peer, that is not an error of this node
+        let _ = upload.wait().await;
+        Ok(())
+    }
+}
+
+/// Asks peer at `addr` for pack of commit `id`, returns info hash of torrent the peer seeds it as
+pub async fn ask(addr: SocketAddr, peer_id: PeerId, id: &ObjectId) -> GtrResult<InfoHash> {
+    let mut connection = Connection::connect(addr, id.dht_key().into(), peer_id).await?;
+    if !connection.supports(EXTENSION) {
+        return Err(TransportError::transport_failed(&format!("{addr} does not support {EXTENSION}")))
+    }
+    let sha = id.to_hex();
+    connection.send_extended(EXTENSION, &GitTorrentMessage::Ask(sha.clone()).encode()).await?;
+
+    let answered = async {
+        loop {
+            let payload = match connection.receive().await? {
+                Message::Extended(name, payload) if name == EXTENSION => payload,
+                _ => continue,
+            };
+            match GitTorrentMessage::decode(&payload)? {
+                GitTorrentMessage::OnHave(info_hash) => match from_hex(&info_hash) {
+                    Some(info_hash) => return Ok(InfoHash::from(info_hash)),
+                    None => return Err(TransportError::malformed_message(&format!("invalid info hash {info_hash}"))),
+                },
+                GitTorrentMessage::Reject(rejected) if rejected == sha => {
+                    return Err(TransportError::transport_failed(&format!("{addr} rejected {sha}")))
+                },
+                _ => continue,
+            }
+        }
+    };
+    match timeout(ASK_TIMEOUT, answered).await {
+        Ok(answer) => answer,
+        Err(_) => Err(TransportError::transport_failed(&format!("{addr} did not answer for {sha}"))),
+    }
+}
+
+/// Opens tunnel to git-upload-pack of repository announcing commit `id` at peer `addr`, none if
+/// the peer rejects it or does not know the message (e.g. gittorrent)
+///
+/// Bytes written to the returned stream are sent to git-upload-pack and its output is read back.
+pub async fn upload(addr: SocketAddr, peer_id: PeerId, id: &ObjectId) -> GtrResult<Option<DuplexStream>> {
+    let mut connection = Connection::connect(addr, id.dht_key().into(), peer_id).await?;
+    if !connection.supports(EXTENSION) {
+        return Ok(None)
+    }
+    let sha = id.to_hex();
+    connection.send_extended(EXTENSION, &GitTorrentMessage::Upload(sha.clone()).encode()).await?;
+
+    let answered = async {
+        loop {
+            let payload = match connection.receive().await? {
+                Message::Extended(name, payload) if name == EXTENSION => payload,
+                _ => continue,
+            };
+            match GitTorrentMessage::decode(&payload) {
+                Ok(GitTorrentMessage::Data(data)) => return Ok(Some(data)),
+                Ok(GitTorrentMessage::Reject(rejected)) if rejected == sha => return Ok(None),
+                _ => continue,
+            }
+        }
+    };
+    let first = match timeout(UPLOAD_TIMEOUT, answered).await {
+        Ok(Ok(Some(first))) => first,
+        Ok(Ok(None)) | Err(_) => return Ok(None),
+        Ok(Err(e)) => return Err(e),
+    };
+
+    let (local, tunnel) = tokio::io::duplex(DATA_LEN);
+    tokio::spawn(async move {
+        let (mut incoming, outgoing) = connection.into_split();
+        let (reader, mut writer) = tokio::io::split(tunnel);
+        let requests = tokio::spawn(send_data(reader, outgoing));
+        let mut data = Some(first);
+        while let Some(received) = data {
+            if writer.write_all(&received).await.is_err() { break }
+            data = next_data(&mut incoming).await;
+        }
+        // git sees end of output while it may still close its requests
+        let _ = writer.shutdown().await;
+        let _ = requests.await;
+    });
+    Ok(Some(local))
+}
+
+/// Payload of the next data message, none once the peer disconnects
+async fn next_data(incoming: &mut Incoming) -> Option<Vec<u8>> {
+    loop {
+        let payload = match incoming.receive().await.ok()? {
+            Message::Extended(name, payload) if name == EXTENSION => payload,
+            _ => continue,
+        };
+        if let Ok(GitTorrentMessage::Data(data)) = GitTorrentMessage::decode(&payload) {
+            return Some(data)
+        }
+    }
+}
+
+/// Sends everything read from `reader` in data messages, the connection closes at its end
+async fn send_data(mut reader: impl AsyncRead + Unpin, mut outgoing: Outgoing) {
+    let mut buffer = vec![0; DATA_LEN];
+    while let Ok(read @ 1..) = reader.read(&mut buffer).await {
+        let data = GitTorrentMessage::Data(buffer[..read].to_vec());
+        if outgoing.send_extended(EXTENSION, &data.encode()).await.is_err() { break }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SHA: &str = "66ef7ea67c18d2341afb8c1521afbab31014e62f";
+
+    #[tokio::test]
+    async fn rejects_asks_for_not_announced_commits() {
+        let asked = GitTorrentMessage::Ask(String::from(SHA));
+        assert_eq!(asked.encode(), format!("d10:gitTorrentd3:ask40:{SHA}ee").into_bytes());
+        assert_eq!(GitTorrentMessage::decode(&[asked.encode(), b"ee".to_vec()].concat()).unwrap(), asked);
+
+        // SHA-256 ids which differ after DHT key, peer is accepted but the commit is not announced
+        let announced: ObjectId = format!("{SHA}{}", "0".repeat(24)).parse().unwrap();
+        let other: ObjectId = format!("{SHA}{}", "1".repeat(24)).parse().unwrap();
+        let server = Arc::new(PackServer::new(PeerId::from([1; 20])));
+        let dir = std::env::temp_dir().join(format!("gtr-not-a-repository-{}", std::process::id()));
+        server.share(&dir, &[Ref::new("refs/heads/master", announced, None, None)]);
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        server.listen(listener).unwrap();
+
+        let rejected = ask(addr, PeerId::from([2; 20]), &other).await;
+        assert!(rejected.unwrap_err().to_string().contains("rejected"));
+        // announced commit which can not be packed is not rejected, the connection is closed
+        let failed = ask(addr, PeerId::from([2; 20]), &announced).await;
+        assert!(!failed.unwrap_err().to_string().contains("rejected"));
+        // only the failure of this node is reported, not the rejected ask
+        let mut failures = server.failures();
+        for _ in 0..100 {
+            if !failures.is_empty() { break }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            failures = server.failures();
+        }
+        assert_eq!(failures.len(), 1);
+        assert!(failures[0].to_string().contains(&announced.to_hex()));
+        assert!(server.failures().is_empty());
+        server.stop();
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
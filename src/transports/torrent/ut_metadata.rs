@@ -0,0 +1,82 @@
+use crate::transports::torrent::bencode::Value;
+use crate::utils::error::{GtrResult, TransportError};
+
+// NOTE: https://www.bittorrent.org/beps/bep_0009.html
+// Peers knowing only info hash of a pack (from `onhave`) get its info dictionary from the peers
+// seeding it, metadata is sent in pieces of 16 KiB after bencoded message.
+
+pub const EXTENSION: &str = "ut_metadata";
+pub const METADATA_PIECE_LEN: usize = 16 * 1024;
+
+const REQUEST: i64 = 0;
+const DATA: i64 = 1;
+const REJECT: i64 = 2;
+
+#[derive(Debug, PartialEq)]
+pub enum MetadataMessage {
+    Request(usize),
+    /// Piece with total size of metadata
+    Data(usize, usize, Vec<u8>),
+    Reject(usize),
+}
+
+impl MetadataMessage {
+    pub fn encode(&self) -> Vec<u8> {
+        match self {
+            MetadataMessage::Request(piece) => message(REQUEST, *piece).encode(),
+            MetadataMessage::Reject(piece) => message(REJECT, *piece).encode(),
+            MetadataMessage::Data(piece, total_size, data) => {
+                let mut data_message = message(DATA, *piece);
+                if let Value::Dict(entries) = &mut data_message {
+                    entries.insert(b"total_size".to_vec(), Value::Int(*total_size as i64));
+                }
+                [data_message.encode(), data.clone()].concat()
+            },
+        }
+    }
+
+    pub fn decode(payload: &[u8]) -> GtrResult<Self> {
+        let (message, data) = Value::decode_prefix(payload)?;
+        let int = |key| message.get(key).and_then(Value::as_int).and_then(|int| usize::try_from(int).ok());
+        let piece = match int("piece") {
+            Some(piece) => piece,
+            None => return Err(TransportError::malformed_message("ut_metadata message without piece")),
+        };
+        match (message.get("msg_type").and_then(Value::as_int), int("total_size")) {
+            (Some(REQUEST), _) => Ok(MetadataMessage::Request(piece)),
+            (Some(DATA), Some(total_size)) => Ok(MetadataMessage::Data(piece, total_size, data.to_vec())),
+            (Some(REJECT), _) => Ok(MetadataMessage::Reject(piece)),
+            _ => Err(TransportError::malformed_message("unknown ut_metadata message")),
+        }
+    }
+}
+
+/// Answer to request for piece of `metadata`
+pub fn answer(metadata: &[u8], piece: usize) -> MetadataMessage {
+    match metadata.chunks(METADATA_PIECE_LEN).nth(piece) {
+        Some(data) => MetadataMessage::Data(piece, metadata.len(), data.to_vec()),
+        None => MetadataMessage::Reject(piece),
+    }
+}
+
+fn message(msg_type: i64, piece: usize) -> Value {
+    Value::dict([("msg_type", Value::Int(msg_type)), ("piece", Value::Int(piece as i64))])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn answers_requests_for_metadata_pieces() {
+        let metadata = vec![1; METADATA_PIECE_LEN + 1];
+
+        let data = answer(&metadata, 1);
+        assert_eq!(data, MetadataMessage::Data(1, METADATA_PIECE_LEN + 1, vec![1]));
+        assert_eq!(data.encode(), b"d8:msg_typei1e5:piecei1e10:total_sizei16385ee\x01");
+        assert_eq!(MetadataMessage::decode(&data.encode()).unwrap(), data);
+
+        assert_eq!(answer(&metadata, 2), MetadataMessage::Reject(2));
+        assert_eq!(MetadataMessage::decode(b"d8:msg_typei0e5:piecei3ee").unwrap(), MetadataMessage::Request(3));
+    }
+}
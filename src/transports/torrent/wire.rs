@@ -0,0 +1,440 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::net::SocketAddr;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
+use tokio::net::TcpStream;
+use tokio::time::timeout;
+
+use crate::transports::torrent::bencode::Value;
+use crate::utils::error::{GtrResult, TransportError};
+use crate::utils::hex::hex;
+
+// NOTE: https://www.bittorrent.org/beps/bep_0003.html#peer-protocol
+// NOTE: https://www.bittorrent.org/beps/bep_0010.html
+
+const PROTOCOL: &[u8] = b"BitTorrent protocol";
+const HANDSHAKE_LEN: usize = 1 + PROTOCOL.len() + 8 + 20 + 20;
+// the largest messages are pieces of 16 KiB blocks
+const MAX_MESSAGE_LEN: usize = 64 * 1024;
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+// reserved bit of handshake telling that peer supports extension protocol (BEP 10)
+const EXTENSION_PROTOCOL: (usize, u8) = (5, 0x10);
+
+pub const CHOKE: u8 = 0;
+pub const UNCHOKE: u8 = 1;
+pub const INTERESTED: u8 = 2;
+pub const BITFIELD: u8 = 5;
+pub const REQUEST: u8 = 6;
+pub const PIECE: u8 = 7;
+/// Size of blocks pieces are requested in
+pub const BLOCK_LEN: u32 = 16 * 1024;
+
+const EXTENDED: u8 = 20;
+const EXTENDED_HANDSHAKE: u8 = 0;
+/// Extension messages understood by gtr with ids peers use to send them to it
+const LOCAL_EXTENSIONS: [(&str, u8); 2] = [("ut_gittorrent", 1), ("ut_metadata", 2)];
+
+/// SHA-1 of torrent metadata, or DHT key of commit peers are asked for packs of
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct InfoHash([u8; 20]);
+
+/// Id peer chooses for itself, it is sent in handshake
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct PeerId([u8; 20]);
+
+impl From<[u8; 20]> for InfoHash {
+    fn from(hash: [u8; 20]) -> Self {
+        InfoHash(hash)
+    }
+}
+
+impl AsRef<[u8]> for InfoHash {
+    fn as_ref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl fmt::Debug for InfoHash {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", hex(&self.0))
+    }
+}
+
+impl From<[u8; 20]> for PeerId {
+    fn from(id: [u8; 20]) -> Self {
+        PeerId(id)
+    }
+}
+
+impl AsRef<[u8]> for PeerId {
+    fn as_ref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl fmt::Debug for PeerId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", hex(&self.0))
+    }
+}
+
+/// The first message of each side of connection
+pub struct Handshake {
+    pub info_hash: InfoHash,
+    pub peer_id: PeerId,
+    pub reserved: [u8; 8],
+}
+
+impl Handshake {
+    /// Handshake with extension protocol enabled
+    pub fn new(info_hash: InfoHash, peer_id: PeerId) -> Self {
+        let mut reserved = [0; 8];
+        reserved[EXTENSION_PROTOCOL.0] |= EXTENSION_PROTOCOL.1;
+        Handshake { info_hash, peer_id, reserved }
+    }
+
+    fn supports_extensions(&self) -> bool {
+        self.reserved[EXTENSION_PROTOCOL.0] & EXTENSION_PROTOCOL.1 != 0
+    }
+
+    fn encode(&self) -> Vec<u8> {
+        let mut encoded = Vec::with_capacity(HANDSHAKE_LEN);
+        encoded.push(PROTOCOL.len() as u8);
+        encoded.extend_from_slice(PROTOCOL);
+        encoded.extend_from_slice(&self.reserved);
+        encoded.extend_from_slice(self.info_hash.as_ref());
+        encoded.extend_from_slice(self.peer_id.as_ref());
+        encoded
+    }
+
+    fn decode(encoded: &[u8; HANDSHAKE_LEN]) -> GtrResult<Self> {
+        if encoded[0] as usize != PROTOCOL.len() || !encoded[1..20].eq(PROTOCOL) {
+            return Err(TransportError::malformed_message("not a BitTorrent handshake"))
+        }
+        let mut reserved = [0; 8];
+        reserved.copy_from_slice(&encoded[20..28]);
+        let mut info_hash = [0; 20];
+        info_hash.copy_from_slice(&encoded[28..48]);
+        let mut peer_id = [0; 20];
+        peer_id.copy_from_slice(&encoded[48..]);
+
+        Ok(Handshake { info_hash: info_hash.into(), peer_id: peer_id.into(), reserved })
+    }
+}
+
+/// Message of peer wire protocol
+#[derive(Debug, PartialEq)]
+pub enum Message {
+    KeepAlive,
+    /// Extension message with local name of the extension
+    Extended(String, Vec<u8>),
+    Other(u8, Vec<u8>),
+}
+
+/// What local side shares under info hash peer connects with
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Shared {
+    /// Nothing but extension messages, e.g. asks for packs of commit with given DHT key
+    Extensions,
+    /// Torrent with metadata (info dictionary) of given size
+    Torrent(usize),
+}
+
+/// Connection to peer after handshakes, with extension protocol supported by both sides
+pub struct Connection {
+    stream: TcpStream,
+    pub remote: SocketAddr,
+    pub peer_id: PeerId,
+    pub info_hash: InfoHash,
+    /// Size of metadata of the torrent if peer has it
+    pub metadata_size: Option<usize>,
+    // ids of extension messages of the peer
+    extensions: HashMap<String, u8>,
+}
+
+impl Connection {
+    /// Connects to peer sharing `info_hash`
+    pub async fn connect(addr: SocketAddr, info_hash: InfoHash, peer_id: PeerId) -> GtrResult<Self> {
+        let stream = match timeout(HANDSHAKE_TIMEOUT, TcpStream::connect(addr)).await {
+            Ok(Ok(stream)) => stream,
+            Ok(Err(e)) => return Err(TransportError::transport_failed(&format!("can not connect {addr}, {e}"))),
+            Err(_) => return Err(TransportError::transport_failed(&format!("can not connect {addr}, timed out"))),
+        };
+        let handshake = Handshake::new(info_hash, peer_id);
+        let connected = async {
+            let mut stream = stream;
+            write_all(&mut stream, &handshake.encode()).await?;
+            let remote = read_handshake(&mut stream).await?;
+            if remote.info_hash != info_hash {
+                return Err(TransportError::transport_failed(&format!("{addr} does not share {info_hash:?}")))
+            }
+            Connection::extended_handshake(stream, addr, remote, Shared::Extensions).await
+        };
+        match timeout(HANDSHAKE_TIMEOUT, connected).await {
+            Ok(connection) => connection,
+            Err(_) => Err(TransportError::transport_failed(&format!("handshake with {addr} timed out"))),
+        }
+    }
+
+    /// Answers handshake of connected peer if it asks for info hash which is `shared`
+    pub async fn accept(stream: TcpStream, peer_id: PeerId, shared: impl Fn(&InfoHash) -> Option<Shared>) -> GtrResult<Self> {
+        let addr = match stream.peer_addr() {
+            Ok(addr) => addr,
+            Err(e) => return Err(TransportError::transport_failed(&format!("{e}"))),
+        };
+        let accepted = async {
+            let mut stream = stream;
+            let remote = read_handshake(&mut stream).await?;
+            let shared = match shared(&remote.info_hash) {
+                Some(shared) => shared,
+                None => return Err(TransportError::transport_failed(&format!("{addr} asked for not shared {:?}", remote.info_hash))),
+            };
+            write_all(&mut stream, &Handshake::new(remote.info_hash, peer_id).encode()).await?;
+            Connection::extended_handshake(stream, addr, remote, shared).await
+        };
+        match timeout(HANDSHAKE_TIMEOUT, accepted).await {
+            Ok(connection) => connection,
+            Err(_) => Err(TransportError::transport_failed(&format!("handshake with {addr} timed out"))),
+        }
+    }
+
+    /// Both sides send extended handshake, the one of the peer tells ids of its extensions
+    async fn extended_handshake(stream: TcpStream, remote: SocketAddr, handshake: Handshake, shared: Shared) -> GtrResult<Self> {
+        if !handshake.supports_extensions() {
+            return Err(TransportError::transport_failed(&format!("{remote} does not support extension protocol")))
+        }
+        let mut connection = Connection {
+            stream,
+            remote,
+            peer_id: handshake.peer_id,
+            info_hash: handshake.info_hash,
+            metadata_size: None,
+            extensions: HashMap::new(),
+        };
+
+        let supported = Value::Dict(
+            LOCAL_EXTENSIONS.iter().map(|(name, id)| (name.as_bytes().to_vec(), Value::Int(*id as i64))).collect()
+        );
+        let mut local = Value::dict([("m", supported), ("v", Value::bytes(b"gtr"))]);
+        if let (Value::Dict(entries), Shared::Torrent(size)) = (&mut local, shared) {
+            entries.insert(b"metadata_size".to_vec(), Value::Int(size as i64));
+        }
+        connection.write(EXTENDED, &[&[EXTENDED_HANDSHAKE], local.encode().as_slice()].concat()).await?;
+
+        // peers may send e.g. bitfield before it
+        loop {
+            match connection.read().await? {
+                Some((EXTENDED, payload)) if payload.first() == Some(&EXTENDED_HANDSHAKE) => {
+                    let handshake = Value::decode(&payload[1..])?;
+                    if let Some(Value::Dict(extensions)) = handshake.get("m") {
+                        connection.extensions = extensions
+                            .iter()
+                            .filter_map(|(name, id)| Some((String::from_utf8(name.clone()).ok()?, u8::try_from(id.as_int()?).ok()?)))
+                            // id 0 disables extension
+                            .filter(|(_, id)| *id != 0)
+                            .collect();
+                    }
+                    connection.metadata_size = handshake
+                        .get("metadata_size")
+                        .and_then(Value::as_int)
+                        .and_then(|size| usize::try_from(size).ok());
+                    return Ok(connection)
+                },
+                _ => continue,
+            }
+        }
+    }
+
+    pub fn supports(&self, extension: &str) -> bool {
+        self.extensions.contains_key(extension)
+    }
+
+    /// Sends message of extension supported by the peer
+    pub async fn send_extended(&mut self, extension: &str, payload: &[u8]) -> GtrResult<()> {
+        send_extended(&mut self.stream, self.remote, &self.extensions, extension, payload).await
+    }
+
+    pub async fn send(&mut self, id: u8, payload: &[u8]) -> GtrResult<()> {
+        self.write(id, payload).await
+    }
+
+    /// Next message, extension messages not known locally are skipped
+    pub async fn receive(&mut self) -> GtrResult<Message> {
+        receive(&mut self.stream, self.remote).await
+    }
+
+    /// Splits connection into halves receiving and sending messages, e.g. to relay data both ways
+    /// at once
+    pub fn into_split(self) -> (Incoming, Outgoing) {
+        let (reader, writer) = self.stream.into_split();
+        let incoming = Incoming { stream: reader, remote: self.remote };
+        let outgoing = Outgoing { stream: writer, remote: self.remote, extensions: self.extensions };
+        (incoming, outgoing)
+    }
+
+    async fn write(&mut self, id: u8, payload: &[u8]) -> GtrResult<()> {
+        write_message(&mut self.stream, id, payload).await
+    }
+
+    async fn read(&mut self) -> GtrResult<Option<(u8, Vec<u8>)>> {
+        read_message(&mut self.stream, self.remote).await
+    }
+}
+
+/// Receiving half of connection
+pub struct Incoming {
+    stream: OwnedReadHalf,
+    pub remote: SocketAddr,
+}
+
+impl Incoming {
+    /// Next message, extension messages not known locally are skipped
+    pub async fn receive(&mut self) -> GtrResult<Message> {
+        receive(&mut self.stream, self.remote).await
+    }
+}
+
+/// Sending half of connection, the peer sees end of stream when it is dropped
+pub struct Outgoing {
+    stream: OwnedWriteHalf,
+    pub remote: SocketAddr,
+    extensions: HashMap<String, u8>,
+}
+
+impl Outgoing {
+    /// Sends message of extension supported by the peer
+    pub async fn send_extended(&mut self, extension: &str, payload: &[u8]) -> GtrResult<()> {
+        send_extended(&mut self.stream, self.remote, &self.extensions, extension, payload).await
+    }
+}
+
+async fn send_extended(
+    stream: &mut (impl AsyncWrite + Unpin),
+    remote: SocketAddr,
+    extensions: &HashMap<String, u8>,
+    extension: &str,
+    payload: &[u8]) -> GtrResult<()>
+{
+    let id = match extensions.get(extension) {
+        Some(id) => *id,
+        None => return Err(TransportError::transport_failed(&format!("{remote} does not support {extension}"))),
+    };
+    write_message(stream, EXTENDED, &[&[id], payload].concat()).await
+}
+
+async fn receive(stream: &mut (impl AsyncRead + Unpin), remote: SocketAddr) -> GtrResult<Message> {
+    loop {
+        let (id, payload) = match read_message(stream, remote).await? {
+            Some(message) => message,
+            None => return Ok(Message::KeepAlive),
+        };
+        if id != EXTENDED {
+            return Ok(Message::Other(id, payload))
+        }
+        let name = LOCAL_EXTENSIONS.iter().find(|(_, local)| payload.first() == Some(local)).map(|(name, _)| *name);
+        if let Some(name) = name {
+            return Ok(Message::Extended(String::from(name), payload[1..].to_vec()))
+        }
+    }
+}
+
+async fn write_message(stream: &mut (impl AsyncWrite + Unpin), id: u8, payload: &[u8]) -> GtrResult<()> {
+    let len = (payload.len() + 1) as u32;
+    let message = [&len.to_be_bytes()[..], &[id], payload].concat();
+    write_all(stream, &message).await
+}
+
+/// Reads message as id and payload, none for keep-alive
+async fn read_message(stream: &mut (impl AsyncRead + Unpin), remote: SocketAddr) -> GtrResult<Option<(u8, Vec<u8>)>> {
+    let mut len = [0; 4];
+    read_exact(stream, &mut len).await?;
+    let len = u32::from_be_bytes(len) as usize;
+    if len == 0 { return Ok(None) }
+    if len > MAX_MESSAGE_LEN {
+        return Err(TransportError::malformed_message(&format!("message of {len} bytes from {remote}")))
+    }
+
+    let mut message = vec![0; len];
+    read_exact(stream, &mut message).await?;
+    Ok(Some((message[0], message.split_off(1))))
+}
+
+/// Payload of `request` message: piece index, offset in the piece and length of block
+pub fn block_request(index: u32, begin: u32, len: u32) -> Vec<u8> {
+    [index.to_be_bytes(), begin.to_be_bytes(), len.to_be_bytes()].concat()
+}
+
+pub fn parse_block_request(payload: &[u8]) -> GtrResult<(u32, u32, u32)> {
+    if payload.len() != 12 {
+        return Err(TransportError::malformed_message("request is not 12 bytes long"))
+    }
+    let int = |at: usize| u32::from_be_bytes([payload[at], payload[at + 1], payload[at + 2], payload[at + 3]]);
+    Ok((int(0), int(4), int(8)))
+}
+
+/// Payload of `piece` message: piece index, offset in the piece and the block
+pub fn block(index: u32, begin: u32, data: &[u8]) -> Vec<u8> {
+    [&index.to_be_bytes()[..], &begin.to_be_bytes(), data].concat()
+}
+
+pub fn parse_block(payload: &[u8]) -> GtrResult<(u32, u32, &[u8])> {
+    if payload.len() < 8 {
+        return Err(TransportError::malformed_message("piece message is too short"))
+    }
+    let (index, begin) = (&payload[..4], &payload[4..8]);
+    Ok((u32::from_be_bytes(index.try_into().unwrap()), u32::from_be_bytes(begin.try_into().unwrap()), &payload[8..]))
+}
+
+async fn read_handshake(stream: &mut TcpStream) -> GtrResult<Handshake> {
+    let mut handshake = [0; HANDSHAKE_LEN];
+    read_exact(stream, &mut handshake).await?;
+    Handshake::decode(&handshake)
+}
+
+async fn read_exact(stream: &mut (impl AsyncRead + Unpin), buffer: &mut [u8]) -> GtrResult<()> {
+    match stream.read_exact(buffer).await {
+        Ok(_) => Ok(()),
+        Err(e) => Err(TransportError::transport_failed(&format!("{e}"))),
+    }
+}
+
+async fn write_all(stream: &mut (impl AsyncWrite + Unpin), data: &[u8]) -> GtrResult<()> {
+    match stream.write_all(data).await {
+        Ok(()) => Ok(()),
+        Err(e) => Err(TransportError::transport_failed(&format!("{e}"))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::net::TcpListener;
+
+    #[tokio::test]
+    async fn exchanges_extension_messages_after_handshakes() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let shared = InfoHash::from([1; 20]);
+
+        let server = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut connection = Connection::accept(stream, PeerId::from([2; 20]), |hash| {
+                hash.eq(&shared).then_some(Shared::Torrent(10))
+            }).await.unwrap();
+            let message = connection.receive().await.unwrap();
+            connection.send_extended("ut_gittorrent", b"de").await.unwrap();
+            message
+        });
+
+        let mut connection = Connection::connect(addr, shared, PeerId::from([3; 20])).await.unwrap();
+        assert_eq!(connection.peer_id, PeerId::from([2; 20]));
+        assert!(connection.supports("ut_gittorrent"));
+        assert_eq!(connection.metadata_size, Some(10));
+        connection.send_extended("ut_gittorrent", b"le").await.unwrap();
+
+        assert_eq!(connection.receive().await.unwrap(), Message::Extended(String::from("ut_gittorrent"), b"de".to_vec()));
+        assert_eq!(server.await.unwrap(), Message::Extended(String::from("ut_gittorrent"), b"le".to_vec()));
+    }
+}
@@ -0,0 +1,352 @@
+use std::fmt;
+use std::str::FromStr;
+use std::sync::LazyLock;
+use bech32::{Bech32, Hrp};
+use data_encoding::{Encoding, Specification, BASE64URL_NOPAD};
+
+use crate::config::contacts::{is_petname, Contacts};
+use crate::utils::error::{GtrError, GtrResult, TransportError};
+use crate::utils::hex::{from_hex, hex};
+
+// NOTE: address of repository is `<scheme>://<user>/<repository>[#<ref>]`, the user is given by
+// their key in the encoding usual for the network, or by petname from `gtr contacts`:
+//   torrent://<hex>/gtr               SHA-1 of ed25519 key, target of the profile item (BEP 44)
+//   holepunch://<z-base-32>/gtr       ed25519 key, as hyperdht and keet print it
+//   ssb://<base64url>/gtr             ed25519 key of feed, `@<base64>.ed25519` without sigil
+//   gnunet://<crockford base32>/gtr   GNS zone (zTLD), 4 bytes of zone type and the key
+//   nostr://<npub>/gtr                secp256k1 key, NIP-19 bech32
+// Hex keys of holepunch and nostr are accepted as well, addresses are printed with the encodings
+// above. Fragment names the only branch or ref offered by the remote, e.g. `torrent://alice/gtr#dev`.
+// Local repositories (`file://<path>` or just the path) have no user, they are not parsed here and
+// the `file` transport takes them as paths.
+
+const GNS_ZONE_LEN: usize = 36;
+// https://lsd.gnunet.org/lsd0001/ PKEY and EDKEY
+const GNS_ZONE_TYPES: [[u8; 4]; 2] = [[0, 1, 0, 0], [0, 1, 0, 0x14]];
+const NPUB: Hrp = Hrp::parse_unchecked("npub");
+
+static Z_BASE32: LazyLock<Encoding> = LazyLock::new(|| {
+    let mut spec = Specification::new();
+    spec.symbols.push_str("ybndrfg8ejkmcpqxot1uwisza345h769");
+    spec.encoding().expect("z-base-32 is valid encoding")
+});
+
+// GNUnet reads lowercase letters and the digits Crockford's alphabet leaves out alike
+static CROCKFORD_BASE32: LazyLock<Encoding> = LazyLock::new(|| {
+    let mut spec = Specification::new();
+    spec.symbols.push_str("0123456789ABCDEFGHJKMNPQRSTVWXYZ");
+    spec.translate.from.push_str("abcdefghjkmnpqrstvwxyzOoIiLlUu");
+    spec.translate.to.push_str("ABCDEFGHJKMNPQRSTVWXYZ001111VV");
+    spec.encoding().expect("Crockford's base32 is valid encoding")
+});
+
+/// Network of repository address
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Scheme {
+    Torrent,
+    Holepunch,
+    Ssb,
+    Gnunet,
+    Nostr,
+}
+
+impl Scheme {
+    pub const ALL: [Scheme; 5] = [Scheme::Torrent, Scheme::Holepunch, Scheme::Ssb, Scheme::Gnunet, Scheme::Nostr];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Scheme::Torrent => "torrent",
+            Scheme::Holepunch => "holepunch",
+            Scheme::Ssb => "ssb",
+            Scheme::Gnunet => "gnunet",
+            Scheme::Nostr => "nostr",
+        }
+    }
+
+    /// Key of the user decoded from address, none if it is not valid for the network
+    pub fn decode_key(&self, user: &str) -> Option<Vec<u8>> {
+        let decode = |encoding: &Encoding, len| encoding.decode(user.as_bytes()).ok().filter(|key| key.len() == len);
+        match self {
+            Scheme::Torrent => from_hex::<20>(user).map(Vec::from),
+            Scheme::Holepunch => decode(&Z_BASE32, 32).or_else(|| from_hex::<32>(user).map(Vec::from)),
+            Scheme::Ssb => decode(&BASE64URL_NOPAD, 32),
+            Scheme::Gnunet => decode(&CROCKFORD_BASE32, GNS_ZONE_LEN).filter(|zone| GNS_ZONE_TYPES.iter().any(|t| zone.starts_with(t))),
+            Scheme::Nostr => match bech32::decode(user) {
+                Ok((hrp, key)) if hrp == NPUB && key.len() == 32 => Some(key),
+                _ => from_hex::<32>(user).map(Vec::from),
+            },
+        }
+    }
+
+    /// Key of the user as written in addresses, the key is valid one
+    pub fn encode_key(&self, key: &[u8]) -> String {
+        match self {
+            Scheme::Torrent => hex(key),
+            Scheme::Holepunch => Z_BASE32.encode(key),
+            Scheme::Ssb => BASE64URL_NOPAD.encode(key),
+            Scheme::Gnunet => CROCKFORD_BASE32.encode(key),
+            Scheme::Nostr => bech32::encode::<Bech32>(NPUB, key).unwrap_or_else(|_| hex(key)),
+        }
+    }
+
+    fn key_encoding(&self) -> &'static str {
+        match self {
+            Scheme::Torrent => "40 hex digits",
+            Scheme::Holepunch => "z-base-32 or hex ed25519 key",
+            Scheme::Ssb => "base64url ed25519 key",
+            Scheme::Gnunet => "base32 GNS zone",
+            Scheme::Nostr => "npub or hex key",
+        }
+    }
+}
+
+impl fmt::Display for Scheme {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Owner of repository as given in its address
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum User {
+    /// Public key of the user, its hash for torrent
+    Key(Vec<u8>),
+    /// Name of contact from the address book
+    Petname(String),
+}
+
+/// Address of repository shared by a user, `<scheme>://<user>/<repository>[#<ref>]`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RepoUrl {
+    pub scheme: Scheme,
+    pub user: User,
+    pub repository: String,
+    /// Branch or ref from fragment, the only one offered by the remote
+    pub reference: Option<String>,
+}
+
+impl RepoUrl {
+    /// Address of repository of the user with given key (hash for torrent)
+    pub fn new(scheme: Scheme, key: &[u8], repository: &str) -> Self {
+        RepoUrl { scheme, user: User::Key(key.to_vec()), repository: String::from(repository), reference: None }
+    }
+
+    /// Key of the user, none for petname which is not resolved yet
+    pub fn key(&self) -> Option<&[u8]> {
+        match &self.user {
+            User::Key(key) => Some(key),
+            User::Petname(_) => None,
+        }
+    }
+
+    /// Address with petname replaced by hash of the contact, the address book keeps torrent users
+    /// only
+    pub fn resolve(&self, contacts: &Contacts) -> GtrResult<Self> {
+        let name = match &self.user {
+            User::Key(_) => return Ok(self.clone()),
+            User::Petname(name) => name,
+        };
+        if self.scheme != Scheme::Torrent {
+            return Err(TransportError::invalid_url(&self.to_string(), &format!("{name} is not {}", self.scheme.key_encoding())))
+        }
+        let hash = contacts.resolve(name)?;
+        Ok(RepoUrl { user: User::Key(hash.to_vec()), ..self.clone() })
+    }
+
+    /// The same address without fragment, as passed to transports
+    pub fn without_reference(&self) -> Self {
+        RepoUrl { reference: None, ..self.clone() }
+    }
+
+    /// Full name of ref from fragment, branch names are taken from `refs/heads/`
+    pub fn reference_name(&self) -> Option<String> {
+        match &self.reference {
+            Some(name) if name.starts_with("refs/") => Some(name.clone()),
+            Some(name) => Some(format!("refs/heads/{name}")),
+            None => None,
+        }
+    }
+}
+
+impl FromStr for RepoUrl {
+    type Err = GtrError;
+
+    fn from_str(url: &str) -> GtrResult<Self> {
+        let (scheme, rest) = match url.split_once("://") {
+            Some(parts) => parts,
+            None => return Err(TransportError::invalid_url(url, "scheme is missing")),
+        };
+        let scheme = match Scheme::ALL.into_iter().find(|s| s.as_str() == scheme) {
+            Some(scheme) => scheme,
+            None => return Err(TransportError::invalid_url(url, &format!("unknown scheme {scheme}"))),
+        };
+        let (rest, reference) = match rest.split_once('#') {
+            Some((rest, reference)) => (rest, Some(reference)),
+            None => (rest, None),
+        };
+        let (user, repository) = match rest.split_once('/') {
+            Some((user, repository)) => (user, repository.trim_end_matches('/')),
+            None => (rest, ""),
+        };
+
+        let user = match scheme.decode_key(user) {
+            Some(key) => User::Key(key),
+            None if is_petname(user) => User::Petname(String::from(user)),
+            None => return Err(TransportError::invalid_url(url, &format!("user is not {}", scheme.key_encoding()))),
+        };
+        if repository.split('/').any(|name| name.is_empty() || name == "." || name == "..") {
+            return Err(TransportError::invalid_url(url, "repository name is missing or invalid"))
+        }
+        if let Some(reference) = reference.filter(|r| !valid_reference(r)) {
+            return Err(TransportError::invalid_url(url, &format!("invalid ref {reference}")))
+        }
+
+        Ok(RepoUrl { scheme, user, repository: String::from(repository), reference: reference.map(String::from) })
+    }
+}
+
+impl fmt::Display for RepoUrl {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let user = match &self.user {
+            User::Key(key) => self.scheme.encode_key(key),
+            User::Petname(name) => name.clone(),
+        };
+        write!(f, "{}://{user}/{}", self.scheme, self.repository)?;
+        if let Some(reference) = &self.reference {
+            write!(f, "#{reference}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Subset of `git check-ref-format` rules which keeps fragments unambiguous
+fn valid_reference(reference: &str) -> bool {
+    !reference.is_empty()
+        && !reference.starts_with(['-', '/'])
+        && !reference.ends_with(['/', '.'])
+        && !reference.contains("..")
+        && !reference.contains("//")
+        && !reference.chars().any(|c| c.is_whitespace() || c.is_control() || "~^:?*[\\#".contains(c))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+    use super::*;
+    use crate::identity::key_hash;
+
+    const HASH: &str = "56e6f1895a68d79df34436af3b69dfbbce19a6c5";
+    const PUBLIC_KEY: &str = "d75a980182b10ab7d54bfed3c964073a0ee172f3daa62325af021a68f707511a";
+
+    /// Address parses into itself, `invalid` ones fail
+    fn assert_addresses(url: RepoUrl, invalid: &[String]) {
+        assert_eq!(url.to_string().parse::<RepoUrl>().unwrap(), url);
+        for invalid in invalid {
+            assert!(invalid.parse::<RepoUrl>().is_err(), "{invalid}");
+        }
+    }
+
+    #[test]
+    fn parses_torrent_addresses() {
+        let hash = from_hex::<20>(HASH).unwrap();
+        assert_addresses(RepoUrl::new(Scheme::Torrent, &hash, "gtr"), &[
+            format!("torrent://+{}/gtr", &HASH[1..]),
+            format!("torrent://{}/gtr", &HASH[1..]),
+            format!("torrent://{HASH}00/gtr"),
+            format!("torrent://{HASH}"),
+            format!("torrent://{HASH}/"),
+            format!("torrent://{HASH}/a/../gtr"),
+        ]);
+
+        let canonical = format!("torrent://{HASH}/gtr#refs/tags/v0.1");
+        assert_eq!(canonical.parse::<RepoUrl>().unwrap().to_string(), canonical);
+        let url: RepoUrl = "torrent://alice/gtr/#dev".parse().unwrap();
+        assert_eq!(url.user, User::Petname(String::from("alice")));
+        assert_eq!(url.reference_name().as_deref(), Some("refs/heads/dev"));
+        assert_eq!(url.without_reference().to_string(), "torrent://alice/gtr");
+    }
+
+    #[test]
+    fn parses_holepunch_addresses() {
+        let key = from_hex::<32>(PUBLIC_KEY).unwrap();
+        assert_addresses(RepoUrl::new(Scheme::Holepunch, &key, "gtr"), &[
+            format!("holepunch://{HASH}/gtr"),
+            format!("holepunch://+{}/gtr", &PUBLIC_KEY[1..]),
+            format!("holepunch://{}/gtr", Z_BASE32.encode(&key[1..])),
+            format!("holepunch://{PUBLIC_KEY}/"),
+        ]);
+
+        let canonical = "holepunch://47pjoycnsrfmxikm95jh13y88e8qnhzu5kungjpxyepgt7a8krpy/gtr";
+        assert_eq!(canonical.parse::<RepoUrl>().unwrap().to_string(), canonical);
+        let url: RepoUrl = format!("holepunch://{PUBLIC_KEY}/gtr").parse().unwrap();
+        assert_eq!(url.key(), Some(key.as_slice()));
+    }
+
+    #[test]
+    fn parses_ssb_addresses() {
+        let key = from_hex::<32>(PUBLIC_KEY).unwrap();
+        assert_addresses(RepoUrl::new(Scheme::Ssb, &key, "gtr"), &[
+            format!("ssb://{}/gtr", BASE64URL_NOPAD.encode(&key[1..])),
+            format!("ssb://{}=/gtr", BASE64URL_NOPAD.encode(&key)),
+            format!("ssb://{}/", BASE64URL_NOPAD.encode(&key)),
+        ]);
+
+        let canonical = "ssb://11qYAYKxCrfVS_7TyWQHOg7hcvPapiMlrwIaaPcHURo/gtr";
+        assert_eq!(canonical.parse::<RepoUrl>().unwrap().to_string(), canonical);
+    }
+
+    #[test]
+    fn parses_gnunet_addresses() {
+        let key = from_hex::<32>(PUBLIC_KEY).unwrap();
+        let zone = [[0, 1, 0, 0x14].as_slice(), &key].concat();
+        assert_addresses(RepoUrl::new(Scheme::Gnunet, &zone, "gtr"), &[
+            format!("gnunet://{}/gtr", CROCKFORD_BASE32.encode(&[[0, 0, 0, 1].as_slice(), &key].concat())),
+            format!("gnunet://{}/gtr", CROCKFORD_BASE32.encode(&zone[..GNS_ZONE_LEN - 1])),
+            format!("gnunet://{}/", CROCKFORD_BASE32.encode(&zone)),
+        ]);
+
+        let url: RepoUrl = format!("gnunet://{}/gtr", CROCKFORD_BASE32.encode(&zone).to_lowercase()).parse().unwrap();
+        assert_eq!(url.key(), Some(zone.as_slice()));
+        assert_eq!(url.reference_name(), None);
+    }
+
+    #[test]
+    fn parses_nostr_addresses() {
+        let key = from_hex::<32>(PUBLIC_KEY).unwrap();
+        let url = RepoUrl { reference: Some(String::from("dev")), ..RepoUrl::new(Scheme::Nostr, &key, "projects/gtr") };
+        assert_addresses(url, &[
+            format!("nostr://{HASH}/gtr"),
+            format!("nostr://{}/gtr", bech32::encode::<Bech32>(Hrp::parse_unchecked("nsec"), &key).unwrap()),
+            format!("nostr://{}/gtr", bech32::encode::<Bech32>(NPUB, &key[1..]).unwrap()),
+            format!("nostr://{PUBLIC_KEY}/"),
+            format!("nostr://{PUBLIC_KEY}/gtr#"),
+            format!("nostr://{PUBLIC_KEY}/gtr#a..b"),
+        ]);
+
+        let canonical = "nostr://npub16adfsqvzky9t042tlmfujeq88g8wzuhnm2nzxfd0qgdx3ac82ydqme0hce/gtr";
+        assert_eq!(canonical.parse::<RepoUrl>().unwrap().to_string(), canonical);
+        let url: RepoUrl = format!("nostr://{PUBLIC_KEY}/gtr").parse().unwrap();
+        assert_eq!(url.key(), Some(key.as_slice()));
+    }
+
+    #[test]
+    fn rejects_addresses_without_known_scheme() {
+        for invalid in ["../gtr", "file:///srv/gtr", &format!("git://{HASH}/gtr")] {
+            assert!(invalid.parse::<RepoUrl>().is_err(), "{invalid}");
+        }
+    }
+
+    #[test]
+    fn resolves_petnames_of_torrent_users() {
+        let key = from_hex::<32>(PUBLIC_KEY).unwrap();
+        let contacts = Contacts {
+            contacts: BTreeMap::from([(String::from("alice"), String::from(HASH)), (String::from("bob"), String::from(PUBLIC_KEY))]),
+        };
+        let resolve = |url: &str| url.parse::<RepoUrl>().unwrap().resolve(&contacts).map(|url| url.to_string());
+        assert_eq!(resolve("torrent://alice/gtr#dev").unwrap(), format!("torrent://{HASH}/gtr#dev"));
+        assert_eq!(resolve("torrent://bob/gtr").unwrap(), format!("torrent://{}/gtr", hex(&key_hash(&key))));
+        assert_eq!(resolve(&format!("torrent://{HASH}/gtr")).unwrap(), format!("torrent://{HASH}/gtr"));
+        assert!(resolve("torrent://carol/gtr").is_err());
+        assert!(resolve("nostr://alice/gtr").is_err());
+    }
+}
@@ -1,6 +1,6 @@
 use std::fmt;
 use std::error::Error;
-use std::path::PathBuf;
+use std::path::Path;
 
 pub type GtrResult<T> = std::result::Result<T, GtrError>;
 
@@ -24,16 +24,23 @@ impl fmt::Display for GtrError {
 }
 
 pub trait GitError {
-    fn not_git_repo(dir: &PathBuf) -> Self;
+    fn not_git_repo(dir: &Path) -> Self;
     fn command_failed(e: Box<dyn Error>) -> Self;
     fn ignore_failed(e: Box<dyn Error>) -> Self;
     fn pack_read_failed(e: Box<dyn Error>) -> Self;
     fn pack_write_failed(e: Box<dyn Error>) -> Self;
+    fn pack_index_failed(message: &str) -> Self;
+    fn pack_cache_failed(e: Box<dyn Error>) -> Self;
+    fn pack_ingest_failed(message: &str) -> Self;
+    fn hook_install_failed(e: Box<dyn Error>) -> Self;
+    fn invalid_object_id(id: &str) -> Self;
+    fn malformed_ref(line: &str) -> Self;
+    fn unsupported_object_format(format: &str) -> Self;
 }
 
 impl GitError for GtrError {
-    fn not_git_repo(dir: &PathBuf) -> Self {
-        GtrError::new(format!("{} is not a git repository", dir.as_path().display()))
+    fn not_git_repo(dir: &Path) -> Self {
+        GtrError::new(format!("{} is not a git repository", dir.display()))
     }
 
     fn command_failed(e: Box<dyn Error>) -> Self {
@@ -51,6 +58,34 @@ impl GitError for GtrError {
     fn pack_write_failed(e: Box<dyn Error>) -> Self {
         GtrError::new(format!("Error reading pack file content: {:?}", e))
     }
+
+    fn pack_index_failed(message: &str) -> Self {
+        GtrError::new(format!("Error indexing pack file: {message}"))
+    }
+
+    fn pack_cache_failed(e: Box<dyn Error>) -> Self {
+        GtrError::new(format!("Error managing pack cache: {:?}", e))
+    }
+
+    fn pack_ingest_failed(message: &str) -> Self {
+        GtrError::new(format!("Error applying received pack: {message}"))
+    }
+
+    fn hook_install_failed(e: Box<dyn Error>) -> Self {
+        GtrError::new(format!("Error installing git hooks: {:?}", e))
+    }
+
+    fn invalid_object_id(id: &str) -> Self {
+        GtrError::new(format!("Invalid object id: {id}"))
+    }
+
+    fn malformed_ref(line: &str) -> Self {
+        GtrError::new(format!("Malformed reference: {line}"))
+    }
+
+    fn unsupported_object_format(format: &str) -> Self {
+        GtrError::new(format!("Unsupported object format: {format}"))
+    }
  }
 
 pub trait ConfigError {
@@ -72,3 +107,183 @@ impl ConfigError for GtrError {
         GtrError::new(format!("Cant create  gtr directory {:?}", e))
     }
 }
+
+pub trait ProtocolError {
+    fn pkt_read_failed(e: Box<dyn Error>) -> Self;
+    fn pkt_write_failed(e: Box<dyn Error>) -> Self;
+    fn pkt_malformed(reason: &str) -> Self;
+    fn remote_failed(message: &str) -> Self;
+}
+
+impl ProtocolError for GtrError {
+    fn pkt_read_failed(e: Box<dyn Error>) -> Self {
+        GtrError::new(format!("Error reading pkt-line: {:?}", e))
+    }
+
+    fn pkt_write_failed(e: Box<dyn Error>) -> Self {
+        GtrError::new(format!("Error writing pkt-line: {:?}", e))
+    }
+
+    fn pkt_malformed(reason: &str) -> Self {
+        GtrError::new(format!("Malformed pkt-line: {reason}"))
+    }
+
+    fn remote_failed(message: &str) -> Self {
+        GtrError::new(format!("Remote reported error: {message}"))
+    }
+}
+
+pub trait TransportError {
+    fn unsupported_scheme(scheme: &str) -> Self;
+    fn transport_failed(message: &str) -> Self;
+    fn invalid_address(address: &str) -> Self;
+    fn invalid_url(url: &str, reason: &str) -> Self;
+    fn dht_failed(message: &str) -> Self;
+    fn malformed_message(reason: &str) -> Self;
+    fn item_too_large(len: usize, max: usize) -> Self;
+}
+
+impl TransportError for GtrError {
+    fn unsupported_scheme(scheme: &str) -> Self {
+        GtrError::new(format!("No transport for {scheme}:// addresses, it may require a cargo feature"))
+    }
+
+    fn transport_failed(message: &str) -> Self {
+        GtrError::new(format!("Transport error: {message}"))
+    }
+
+    fn invalid_address(address: &str) -> Self {
+        GtrError::new(format!("Invalid network address: {address}"))
+    }
+
+    fn invalid_url(url: &str, reason: &str) -> Self {
+        GtrError::new(format!("Invalid repository address {url}: {reason}"))
+    }
+
+    fn dht_failed(message: &str) -> Self {
+        GtrError::new(format!("DHT error: {message}"))
+    }
+
+    fn malformed_message(reason: &str) -> Self {
+        GtrError::new(format!("Malformed message: {reason}"))
+    }
+
+    fn item_too_large(len: usize, max: usize) -> Self {
+        GtrError::new(format!("DHT item is {len} bytes, at most {max} are allowed"))
+    }
+}
+
+pub trait ProfileError {
+    fn profile_too_large(len: usize, max: usize) -> Self;
+    fn profile_not_found(target: &str) -> Self;
+    fn repository_not_published(name: &str) -> Self;
+    fn repository_name_taken(name: &str, dir: &Path) -> Self;
+}
+
+impl ProfileError for GtrError {
+    fn profile_too_large(len: usize, max: usize) -> Self {
+        GtrError::new(format!("Profile is {len} bytes, at most {max} fit into DHT, stop sharing some repositories"))
+    }
+
+    fn profile_not_found(target: &str) -> Self {
+        GtrError::new(format!("No profile is published at {target}"))
+    }
+
+    fn repository_not_published(name: &str) -> Self {
+        GtrError::new(format!("Repository {name} is not published"))
+    }
+
+    fn repository_name_taken(name: &str, dir: &Path) -> Self {
+        GtrError::new(format!("Repository {name} is already published from {}, rename one of the directories", dir.display()))
+    }
+}
+
+pub trait IdentityError {
+    fn identity_failed(e: Box<dyn Error>) -> Self;
+    fn identity_exists(path: &Path) -> Self;
+    fn key_exposed(path: &Path, mode: u32) -> Self;
+    fn invalid_key(path: &Path) -> Self;
+    fn wrong_passphrase() -> Self;
+    fn passphrase_unset() -> Self;
+}
+
+impl IdentityError for GtrError {
+    fn identity_failed(e: Box<dyn Error>) -> Self {
+        GtrError::new(format!("Error loading identity key: {e}"))
+    }
+
+    fn identity_exists(path: &Path) -> Self {
+        GtrError::new(format!("Identity key {} already exists, replacing it changes addresses of published repositories", path.display()))
+    }
+
+    fn key_exposed(path: &Path, mode: u32) -> Self {
+        GtrError::new(format!("Identity key {} is accessible by other users (mode {mode:o}), restrict it with `chmod 600`", path.display()))
+    }
+
+    fn invalid_key(path: &Path) -> Self {
+        GtrError::new(format!("{} is not an identity key", path.display()))
+    }
+
+    fn wrong_passphrase() -> Self {
+        GtrError::new(String::from("Wrong passphrase of identity key"))
+    }
+
+    fn passphrase_unset() -> Self {
+        GtrError::new(String::from("Identity key is encrypted and there is no terminal to ask its passphrase, set GTR_PASSPHRASE"))
+    }
+}
+
+pub trait ContactError {
+    fn unknown_contact(name: &str) -> Self;
+    fn contact_exists(name: &str) -> Self;
+    fn invalid_petname(name: &str) -> Self;
+    fn invalid_key(key: &str) -> Self;
+}
+
+impl ContactError for GtrError {
+    fn unknown_contact(name: &str) -> Self {
+        GtrError::new(format!("Unknown contact {name}, add it with `gtr contacts add {name} <hex sha1>`"))
+    }
+
+    fn contact_exists(name: &str) -> Self {
+        GtrError::new(format!("Contact {name} already exists"))
+    }
+
+    fn invalid_petname(name: &str) -> Self {
+        GtrError::new(format!("Invalid petname {name}, use ASCII letters, digits, '-' and '_'"))
+    }
+
+    fn invalid_key(key: &str) -> Self {
+        GtrError::new(format!("{key} is neither hex sha1 (40 digits) nor public key (64 digits)"))
+    }
+}
+
+pub trait DaemonError {
+    fn signal_failed(e: Box<dyn Error>) -> Self;
+    fn service_failed(message: &str) -> Self;
+    fn not_running(e: Box<dyn Error>) -> Self;
+    fn already_running(path: &Path) -> Self;
+    fn control_failed(e: Box<dyn Error>) -> Self;
+}
+
+impl DaemonError for GtrError {
+    fn signal_failed(e: Box<dyn Error>) -> Self {
+        GtrError::new(format!("Error handling signals: {e}"))
+    }
+
+    fn service_failed(message: &str) -> Self {
+        GtrError::new(format!("Error managing daemon service: {message}"))
+    }
+
+    fn not_running(e: Box<dyn Error>) -> Self {
+        GtrError::new(format!("Daemon is not running: {e}"))
+    }
+
+    fn already_running(path: &Path) -> Self {
+        GtrError::new(format!("Daemon is already running, it listens on {}", path.display()))
+    }
+
+    fn control_failed(e: Box<dyn Error>) -> Self {
+        GtrError::new(format!("Error opening control socket: {e}"))
+    }
+}
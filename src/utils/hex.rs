@@ -0,0 +1,15 @@
+/// Lowercase hex digits of bytes, e.g. of SHA-1 digest or public key
+pub fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+/// `N` bytes from `2 * N` hex digits
+pub fn from_hex<const N: usize>(hex: &str) -> Option<[u8; N]> {
+    // from_str_radix also accepts sign, e.g. `+f`
+    if hex.len() != 2 * N || !hex.chars().all(|c| c.is_ascii_hexdigit()) { return None }
+    let mut bytes = [0; N];
+    for (i, byte) in bytes.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(hex.get(2 * i..2 * i + 2)?, 16).ok()?;
+    }
+    Some(bytes)
+}
@@ -0,0 +1,150 @@
+use std::net::{SocketAddr, UdpSocket};
+use std::path::PathBuf;
+use std::process::Output;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+use gtr::config::config_file::{AddressPort, Torrent as TorrentConfig};
+use gtr::transports::torrent::dht::DhtNode;
+use tokio::process::Command;
+use tokio::time::{sleep, timeout, Instant};
+
+// NOTE: network of DHT nodes on loopback, gtr processes started by tests join it through the
+// first node. Every user of the network has its own home with XDG directories, `gtr`, `gtd` and
+// `git` (which runs `git-remote-torrent`) get them in environment, the test process keeps its own.
+
+const BOOTSTRAP_TIMEOUT: Duration = Duration::from_secs(60);
+const ANNOUNCE_TIMEOUT: Duration = Duration::from_secs(120);
+
+pub struct Network {
+    pub nodes: Vec<DhtNode>,
+    router: SocketAddr,
+    // never answers, the first node bootstraps from it until other nodes join
+    _silent: UdpSocket,
+    /// Temporary directory with homes of users
+    pub home: PathBuf,
+}
+
+impl Network {
+    /// Starts `size` DHT nodes
+    pub async fn start(size: usize) -> Network {
+        // tests of one binary run in parallel, each of them with its own network
+        static NETWORKS: AtomicUsize = AtomicUsize::new(0);
+        let id = NETWORKS.fetch_add(1, Ordering::Relaxed);
+        let home = std::env::temp_dir().join(format!("gtr-network-{}-{id}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&home);
+
+        // nodes bind ports the system picks, taking free ones in advance could race with other tests
+        let silent = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let first = DhtNode::builder(&config(silent.local_addr().unwrap().port(), 0)).start().unwrap();
+        let router = first.local_addr().unwrap();
+        let mut nodes = vec![first];
+        // the first node joins once the others bootstrapping from it fill its routing table
+        for _ in 1..size.max(2) {
+            nodes.push(DhtNode::builder(&config(router.port(), 0)).start().unwrap());
+        }
+        for node in &nodes {
+            timeout(BOOTSTRAP_TIMEOUT, node.bootstrapped()).await.unwrap().unwrap();
+        }
+
+        Network { nodes, router, _silent: silent, home }
+    }
+
+    /// User with the torrent transport joining the network configured in its `daemon.toml`
+    pub fn user(&self, name: &str) -> User {
+        let home = self.home.join(name);
+        let config_dir = home.join("config").join("gtr");
+        std::fs::create_dir_all(&config_dir).unwrap();
+        // `gtd` binds ports the system picks and announces the one of its pack server
+        let torrent = config(self.router.port(), 0);
+        let daemon = format!(
+            "[transport.torrent]\nrouter = {{ addr = \"{}\", port = {} }}\nbind = {{ addr = \"{}\", port = {} }}\n",
+            torrent.router.addr, torrent.router.port, torrent.bind.addr, torrent.bind.port,
+        );
+        std::fs::write(config_dir.join("daemon.toml"), daemon).unwrap();
+        User { home }
+    }
+}
+
+impl Drop for Network {
+    fn drop(&mut self) {
+        self.nodes.iter().for_each(DhtNode::shutdown);
+        let _ = std::fs::remove_dir_all(&self.home);
+    }
+}
+
+pub struct User {
+    pub home: PathBuf,
+}
+
+impl User {
+    /// Command run by the user, binaries of gtr are found on PATH
+    pub fn command(&self, program: &str) -> Command {
+        let bin_dir = PathBuf::from(env!("CARGO_BIN_EXE_git-remote-torrent")).parent().unwrap().to_path_buf();
+        let path = std::env::join_paths(
+            std::iter::once(bin_dir.clone()).chain(std::env::split_paths(&std::env::var_os("PATH").unwrap_or_default()))
+        ).unwrap();
+        let program = match bin_dir.join(program).exists() {
+            true => bin_dir.join(program),
+            false => PathBuf::from(program),
+        };
+
+        let mut command = Command::new(program);
+        command
+            .current_dir(&self.home)
+            .env("PATH", path)
+            .env("HOME", &self.home)
+            .env("XDG_CONFIG_HOME", self.home.join("config"))
+            .env("XDG_STATE_HOME", self.home.join("state"))
+            .env("XDG_CACHE_HOME", self.home.join("cache"))
+            .env("XDG_RUNTIME_DIR", self.home.join("run"))
+            .env_remove("GTR_PASSPHRASE")
+            .kill_on_drop(true);
+        command
+    }
+
+    /// Runs command in `dir`, returns its trimmed output
+    pub async fn run(&self, dir: &PathBuf, program: &str, args: &[&str]) -> String {
+        let output = self.output(dir, program, args).await;
+        assert!(output.status.success(), "{program} {args:?}: {}", String::from_utf8_lossy(&output.stderr));
+        String::from_utf8_lossy(&output.stdout).trim().to_string()
+    }
+
+    pub async fn output(&self, dir: &PathBuf, program: &str, args: &[&str]) -> Output {
+        self.command(program).current_dir(dir).args(args).output().await.unwrap()
+    }
+
+    /// Git repository with one commit on `master` which is shared by `gtr init`
+    pub async fn repository(&self, name: &str) -> PathBuf {
+        let dir = self.home.join(name);
+        std::fs::create_dir_all(&dir).unwrap();
+        self.run(&dir, "git", &["init", "-q", "-b", "master"]).await;
+        self.run(&dir, "git", &["-c", "user.name=gtr", "-c", "user.email=gtr@localhost", "commit", "-q", "--allow-empty", "-m", name]).await;
+        self.run(&dir, "gtr", &["init", "--path", &dir.to_string_lossy()]).await;
+        dir
+    }
+
+    /// Waits for running `gtd` of the user to announce repository in `dir`
+    pub async fn announced(&self, dir: &PathBuf) {
+        let started = Instant::now();
+        loop {
+            let status = self.run(dir, "gtr", &["list", "--path", &dir.to_string_lossy()]).await;
+            if status.contains("announce status: announced") {
+                return
+            }
+            assert!(!status.contains("announce failed") && started.elapsed() < ANNOUNCE_TIMEOUT, "{status}");
+            sleep(Duration::from_millis(500)).await;
+        }
+    }
+
+    /// Hash of the user identity, generated by the first publish
+    pub async fn hash(&self) -> String {
+        let shown = self.run(&self.home, "gtr", &["identity", "show"]).await;
+        let hash = shown.lines().find_map(|line| line.strip_prefix("hash: "));
+        String::from(hash.unwrap())
+    }
+}
+
+fn config(router: u16, bind: u16) -> TorrentConfig {
+    let address = |port| AddressPort { addr: String::from("127.0.0.1"), port };
+    TorrentConfig { router: address(router), bind: address(bind) }
+}
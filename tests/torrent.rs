@@ -0,0 +1,70 @@
+mod common;
+
+use std::process::Stdio;
+use std::time::Duration;
+use common::Network;
+use tokio::time::timeout;
+
+const SCENARIO_TIMEOUT: Duration = Duration::from_secs(300);
+
+#[tokio::test(flavor = "multi_thread")]
+async fn clones_repository_announced_on_dht() {
+    let network = Network::start(3).await;
+    let publisher = network.user("publisher");
+    let cloner = network.user("cloner");
+    let published = publisher.repository("published").await;
+
+    let scenario = async {
+        let mut gtd = publisher.command("gtd").stdout(Stdio::null()).spawn().unwrap();
+        publisher.announced(&published).await;
+        // not shared, the tunnel does not advertise it
+        publisher.run(&published, "git", &["branch", "secret"]).await;
+
+        // the cloner has no repository settings, `git-remote-torrent` uses its `daemon.toml`
+        let url = format!("torrent://{}/published", publisher.hash().await);
+        cloner.run(&cloner.home, "git", &["clone", "-q", &url, "clone"]).await;
+
+        let clone = cloner.home.join("clone");
+        assert_eq!(cloner.run(&clone, "git", &["symbolic-ref", "HEAD"]).await, "refs/heads/master");
+        let master = publisher.run(&published, "git", &["rev-parse", "master"]).await;
+        assert_eq!(cloner.run(&clone, "git", &["rev-parse", "HEAD"]).await, master);
+        // git-upload-pack of the publisher sent objects through the tunnel, no pack was asked for
+        let packs = cloner.home.join("cache").join("gtr").join("packs");
+        assert!(!packs.join(format!("{master}.pack")).exists());
+        assert_eq!(cloner.run(&clone, "git", &["branch", "-r"]).await, "origin/HEAD -> origin/master\n  origin/master");
+
+        // the fragment limits refs of the remote, git falls back to fetching the pack of the branch
+        cloner.run(&cloner.home, "git", &["clone", "-q", &format!("{url}#master"), "branch"]).await;
+        assert_eq!(cloner.run(&cloner.home.join("branch"), "git", &["rev-parse", "HEAD"]).await, master);
+        assert!(packs.join(format!("{master}.pack")).exists());
+
+        gtd.kill().await.unwrap();
+    };
+    timeout(SCENARIO_TIMEOUT, scenario).await.unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn pushes_branch_while_daemon_runs() {
+    let network = Network::start(3).await;
+    let publisher = network.user("publisher");
+    let cloner = network.user("cloner");
+    let published = publisher.repository("published").await;
+
+    let scenario = async {
+        let mut gtd = publisher.command("gtd").stdout(Stdio::null()).spawn().unwrap();
+        publisher.announced(&published).await;
+
+        // the helper leaves the ports of `gtd` to it and lets it announce the pushed branch
+        let url = format!("torrent://{}/published", publisher.hash().await);
+        publisher.run(&published, "git", &["checkout", "-q", "-b", "dev"]).await;
+        publisher.run(&published, "git", &["-c", "user.name=gtr", "-c", "user.email=gtr@localhost", "commit", "-q", "--allow-empty", "-m", "dev"]).await;
+        publisher.run(&published, "git", &["push", "-q", &url, "dev"]).await;
+
+        let dev = publisher.run(&published, "git", &["rev-parse", "dev"]).await;
+        let listed = cloner.run(&cloner.home, "git", &["ls-remote", &url]).await;
+        assert!(listed.lines().any(|line| line == format!("{dev}\trefs/heads/dev")), "{listed}");
+
+        gtd.kill().await.unwrap();
+    };
+    timeout(SCENARIO_TIMEOUT, scenario).await.unwrap();
+}